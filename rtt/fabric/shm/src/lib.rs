@@ -1,6 +1,20 @@
 use anyhow::*;
 use memmap2::{MmapMut};
-use std::sync::atomic::{AtomicU32, Ordering::*};
+use std::fs::OpenOptions;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+mod segment;
+
+pub use segment::{Frame, Frames, SegmentStats, ShmReader, ShmSegment, Snapshot};
+
+/// Frame types carried in segment and ring frame headers. Consumers may
+/// define further types above these.
+pub const FRAME_DATA: u16 = 0;
+pub const FRAME_CONTROL: u16 = 1;
+pub const FRAME_HEARTBEAT: u16 = 2;
+
+const RING_MAGIC: u32 = 0x5254_5452; // "RTTR"
+const SLOT_HEADER_LEN: usize = 8;
 
 #[repr(C)]
 pub struct RingHeader {
@@ -10,6 +24,10 @@ pub struct RingHeader {
     pub read: AtomicU32,
 }
 
+/// Single-producer single-consumer ring of fixed-size slots. Each slot is
+/// `u32 len | u16 frame_type | u16 reserved | payload`. The ring lives in the
+/// file it is opened at, so the producer and the consumer, in one process
+/// or two, share it by opening the same path.
 pub struct SpscRing {
     mmap: MmapMut,
     hdr_off: usize,
@@ -18,13 +36,148 @@ pub struct SpscRing {
 }
 
 impl SpscRing {
-    pub fn open(_path: &str, _slots: usize, _slot_len: usize) -> Result<Self> {
-        // TODO: create shared mem, map both ends; placeholder uses file-backed mmap
-        let len = 1<<20;
-        let file = tempfile::tempfile()?;
-        let mmap = unsafe { MmapMut::map_mut(&file)? };
-        Ok(Self{ mmap, hdr_off:0, data_off:64, slot_len:_slot_len })
-    }
-    pub fn push(&mut self, _frame: &[u8]) -> Result<()> { Ok(()) }
-    pub fn pop(&mut self) -> Result<Option<Vec<u8>>> { Ok(None) }
+    /// Create the ring at `path`, or attach to the ring already there. Both
+    /// ends must pass the same `slots` and `slot_len`.
+    pub fn open(path: &str, slots: usize, slot_len: usize) -> Result<Self> {
+        if !slots.is_power_of_two() || slots > u32::MAX as usize {
+            bail!("Ring slot count must be a power of two, got {}", slots);
+        }
+        if slot_len <= SLOT_HEADER_LEN {
+            bail!("Ring slots must be larger than {} bytes, got {}", SLOT_HEADER_LEN, slot_len);
+        }
+        let data_off = 64;
+        let len = (data_off + slots * slot_len) as u64;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open ring: {:?}", path))?;
+        match file.metadata()?.len() {
+            0 => file.set_len(len)?,
+            n if n != len => bail!("Ring {:?} is {} bytes, not the {} of {} slots of {} bytes", path, n, len, slots, slot_len),
+            _ => {}
+        }
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        let mask = (slots - 1) as u32;
+        if mmap[0..4] == [0; 4] {
+            mmap[4..8].copy_from_slice(&mask.to_le_bytes());
+        }
+        let ring = Self { mmap, hdr_off: 0, data_off, slot_len };
+        match ring.magic().load(Ordering::Acquire) {
+            // New: the other end may be attaching, so the magic goes last
+            0 => ring.magic().store(RING_MAGIC, Ordering::Release),
+            RING_MAGIC if ring.header().mask == mask => {}
+            RING_MAGIC => bail!("Ring {:?} has {} slots, not {}", path, ring.header().mask as usize + 1, slots),
+            _ => bail!("Not an RTT ring: {:?}", path),
+        }
+        Ok(ring)
+    }
+
+    fn magic(&self) -> &AtomicU32 {
+        unsafe { &*(self.mmap.as_ptr().add(self.hdr_off) as *const AtomicU32) }
+    }
+
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.mmap.as_ptr().add(self.hdr_off) as *const RingHeader) }
+    }
+
+    fn slot(&mut self, index: u32) -> &mut [u8] {
+        let off = self.data_off + (index & self.header().mask) as usize * self.slot_len;
+        &mut self.mmap[off..off + self.slot_len]
+    }
+
+    /// Push a `FRAME_DATA` frame.
+    pub fn push(&mut self, frame: &[u8]) -> Result<()> {
+        self.push_typed(FRAME_DATA, frame)
+    }
+
+    pub fn push_typed(&mut self, frame_type: u16, frame: &[u8]) -> Result<()> {
+        if frame.len() > self.slot_len - SLOT_HEADER_LEN {
+            bail!("Frame of {} bytes exceeds ring slot payload of {}", frame.len(), self.slot_len - SLOT_HEADER_LEN);
+        }
+        let hdr = self.header();
+        let write = hdr.write.load(Ordering::Relaxed);
+        if write.wrapping_sub(hdr.read.load(Ordering::Acquire)) > hdr.mask {
+            bail!("Ring full");
+        }
+        let slot = self.slot(write);
+        slot[0..4].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+        slot[4..6].copy_from_slice(&frame_type.to_le_bytes());
+        slot[6..8].fill(0);
+        slot[SLOT_HEADER_LEN..SLOT_HEADER_LEN + frame.len()].copy_from_slice(frame);
+        self.header().write.store(write.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Pop the next frame of any type, without its type.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>> {
+        Ok(self.pop_typed()?.map(|(_, frame)| frame))
+    }
+
+    /// Pop the next frame and its type.
+    pub fn pop_typed(&mut self) -> Result<Option<(u16, Vec<u8>)>> {
+        let hdr = self.header();
+        let read = hdr.read.load(Ordering::Relaxed);
+        if read == hdr.write.load(Ordering::Acquire) {
+            return Ok(None);
+        }
+        let slot = self.slot(read);
+        let len = u32::from_le_bytes(slot[0..4].try_into().unwrap()) as usize;
+        let frame_type = u16::from_le_bytes(slot[4..6].try_into().unwrap());
+        let frame = slot
+            .get(SLOT_HEADER_LEN..SLOT_HEADER_LEN + len)
+            .context("Corrupt ring slot length")?
+            .to_vec();
+        self.header().read.store(read.wrapping_add(1), Ordering::Release);
+        Ok(Some((frame_type, frame)))
+    }
+
+    /// Pop the next frame tagged `frame_type`. Frames of other types ahead of
+    /// it are consumed and discarded, so a consumer filtering this way must
+    /// be the only reader interested in the ring.
+    pub fn pop_type(&mut self, frame_type: u16) -> Result<Option<Vec<u8>>> {
+        while let Some((t, frame)) = self.pop_typed()? {
+            if t == frame_type {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_filters_by_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ring");
+        let path = path.to_str().unwrap();
+        let mut ring = SpscRing::open(path, 4, 32).unwrap();
+        ring.push(b"d1").unwrap();
+        ring.push_typed(FRAME_HEARTBEAT, b"").unwrap();
+        ring.push_typed(FRAME_CONTROL, b"drain").unwrap();
+        ring.push(b"d2").unwrap();
+        assert!(ring.push(b"full").is_err());
+        assert!(ring.push(&[0u8; 32]).is_err());
+
+        assert_eq!(ring.pop_type(FRAME_DATA).unwrap().unwrap(), b"d1");
+        assert_eq!(ring.pop_type(FRAME_DATA).unwrap().unwrap(), b"d2");
+        assert_eq!(ring.pop_type(FRAME_DATA).unwrap(), None);
+
+        // Slots are reused after wrap-around
+        ring.push_typed(FRAME_CONTROL, b"resume").unwrap();
+        assert_eq!(ring.pop_typed().unwrap(), Some((FRAME_CONTROL, b"resume".to_vec())));
+
+        // A second handle on the same path is the other end of the same ring
+        let mut consumer = SpscRing::open(path, 4, 32).unwrap();
+        ring.push(b"shared").unwrap();
+        assert_eq!(consumer.pop().unwrap().unwrap(), b"shared");
+        assert_eq!(ring.pop().unwrap(), None);
+        assert!(SpscRing::open(path, 8, 16).is_err());
+        assert!(SpscRing::open(path, 2, 64).is_err());
+    }
 }
@@ -0,0 +1,724 @@
+//! Named shared-memory segments
+//!
+//! A segment is a file under `/dev/shm` (the temp dir where that does not
+//! exist) mapped by every participant. A 64-byte header is followed by an
+//! append-only frame log:
+//!
+//! ```text
+//! header: u32 magic | u32 version | u64 capacity | u64 used | u64 frames | u64 seq
+//!         | u64 head | u64 lock | u64 generation
+//! frame:  u32 len | u16 frame_type | u16 reserved | u64 seq | payload[len] | pad to 8 bytes
+//! ```
+//!
+//! `frame_type` lets one segment multiplex data, control and heartbeat
+//! traffic. It occupies bytes that older writers zeroed, so their frames
+//! read back as `FRAME_DATA`.
+//!
+//! The writer fills in a frame, then publishes it by advancing `used` with
+//! release ordering. Readers load `used` with acquire ordering, so they never
+//! observe a partially written frame.
+//!
+//! Frames before `head` have been consumed. `compact` moves the live frames
+//! `head..used` to the front of the log to reclaim that space. Sequence
+//! numbers travel with the frames, so consumers should track their position
+//! by `seq`, never by byte offset. Writing, consuming and compacting take the
+//! header lock; compaction also bumps `generation` before and after the
+//! move (it is odd while frames are in motion). `snapshot` uses it as a
+//! seqlock: it copies the live frames and retries if `generation` was odd
+//! or changed meanwhile, so its frames are never torn by a concurrent
+//! compaction. `frames` and `as_slice` borrow the mapping directly and are
+//! only stable while no other handle compacts.
+//!
+//! The header lock makes a segment safe for many producers: any number of
+//! handles, in any number of processes, may write it concurrently, and the
+//! frames get distinct, gap-free sequence numbers. The lock is a futex-style
+//! mutex in the low 32 bits of the `lock` word. On Linux, contended writers
+//! sleep in the kernel on a shared (not process-private) futex; elsewhere
+//! they spin briefly and yield, which is correct but burns CPU under heavy
+//! contention. There is no owner recovery: a process that dies while
+//! holding the lock wedges the segment's other writers. The critical
+//! sections are short and do no I/O, which keeps that window small.
+
+use anyhow::{bail, Context, Result};
+use memmap2::{Mmap, MmapMut};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::FRAME_DATA;
+
+pub const SEGMENT_MAGIC: u32 = 0x3154_5452; // "RTT1"
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 64;
+const FRAME_HEADER_LEN: usize = 16;
+
+const OFF_CAPACITY: usize = 8;
+const OFF_USED: usize = 16;
+const OFF_FRAMES: usize = 24;
+const OFF_SEQ: usize = 32;
+const OFF_HEAD: usize = 40;
+const OFF_LOCK: usize = 48;
+const OFF_GEN: usize = 56;
+
+fn align8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+fn segment_path(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        bail!("Invalid segment name: {:?}", name);
+    }
+    let dir = PathBuf::from("/dev/shm");
+    let dir = if dir.is_dir() { dir } else { std::env::temp_dir() };
+    Ok(dir.join(name))
+}
+
+/// View a header word as an atomic. `map` must be the start of a mapping,
+/// which is page aligned, and `off` a multiple of 8.
+fn header_word(map: &[u8], off: usize) -> &AtomicU64 {
+    assert!(off.is_multiple_of(8) && off + 8 <= HEADER_LEN && map.len() >= HEADER_LEN);
+    unsafe { &*(map.as_ptr().add(off) as *const AtomicU64) }
+}
+
+fn read_u16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+fn read_u32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(buf[off..off + 8].try_into().unwrap())
+}
+
+/// The magic and version share the first header word, which `create`
+/// stores last; loading it with acquire ordering makes the rest of the
+/// header visible.
+fn magic_and_version(map: &[u8]) -> (u32, u32) {
+    let word = header_word(map, 0).load(Ordering::Acquire);
+    (word as u32, (word >> 32) as u32)
+}
+
+fn check_header(map: &[u8], name: &str) -> Result<()> {
+    if map.len() < HEADER_LEN || magic_and_version(map).0 != SEGMENT_MAGIC {
+        bail!("Not an RTT segment: {}", name);
+    }
+    let version = magic_and_version(map).1;
+    if version != VERSION {
+        bail!("Unsupported segment version {} in {}", version, name);
+    }
+    if HEADER_LEN as u64 + read_u64(map, OFF_CAPACITY) > map.len() as u64 {
+        bail!("Segment {} is smaller than its declared capacity", name);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentStats {
+    /// Bytes available for frames, excluding the header.
+    pub capacity: usize,
+    /// Bytes of published frames, including frame headers and padding and
+    /// consumed frames not yet compacted away.
+    pub used: usize,
+    /// Bytes of consumed frames that `compact` would reclaim.
+    pub consumed: usize,
+    /// Live (published, unconsumed) frames.
+    pub frames: u64,
+    /// Compaction counter; odd while a compaction is moving frames.
+    pub generation: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub seq: u64,
+    pub frame_type: u16,
+    pub payload: &'a [u8],
+}
+
+/// Iterator over the published frames of a segment, oldest first.
+pub struct Frames<'a> {
+    data: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Frames<'a> {
+    /// Only the frames tagged `frame_type`.
+    pub fn of_type(self, frame_type: u16) -> impl Iterator<Item = Frame<'a>> {
+        self.filter(move |f| f.frame_type == frame_type)
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Frame<'a>> {
+        if self.off + FRAME_HEADER_LEN > self.data.len() {
+            return None;
+        }
+        let len = read_u32(self.data, self.off) as usize;
+        let frame_type = read_u16(self.data, self.off + 4);
+        let seq = read_u64(self.data, self.off + 8);
+        let start = self.off + FRAME_HEADER_LEN;
+        let payload = self.data.get(start..start + len)?;
+        self.off = align8(start + len);
+        Some(Frame { seq, frame_type, payload })
+    }
+}
+
+// Reading is identical for writable and read-only mappings.
+
+fn published(map: &[u8]) -> &[u8] {
+    let used = header_word(map, OFF_USED).load(Ordering::Acquire) as usize;
+    let used = used.min(map.len() - HEADER_LEN);
+    let head = (header_word(map, OFF_HEAD).load(Ordering::Acquire) as usize).min(used);
+    &map[HEADER_LEN + head..HEADER_LEN + used]
+}
+
+/// Copy of the live frames, checked against `generation` as a seqlock.
+fn snapshot_of(map: &[u8]) -> Snapshot {
+    loop {
+        let generation = header_word(map, OFF_GEN).load(Ordering::Acquire);
+        if generation % 2 == 1 {
+            std::thread::yield_now();
+            continue;
+        }
+        let data = published(map).to_vec();
+        fence(Ordering::Acquire);
+        if header_word(map, OFF_GEN).load(Ordering::Relaxed) == generation {
+            return Snapshot { data, generation };
+        }
+    }
+}
+
+fn stats_of(map: &[u8]) -> SegmentStats {
+    SegmentStats {
+        capacity: read_u64(map, OFF_CAPACITY) as usize,
+        used: header_word(map, OFF_USED).load(Ordering::Acquire) as usize,
+        consumed: header_word(map, OFF_HEAD).load(Ordering::Acquire) as usize,
+        frames: header_word(map, OFF_FRAMES).load(Ordering::Acquire),
+        generation: header_word(map, OFF_GEN).load(Ordering::Acquire),
+    }
+}
+
+/// View the low half of the header lock word as the lock's futex word.
+fn lock_word(map: &[u8]) -> &AtomicU32 {
+    let word = header_word(map, OFF_LOCK);
+    // The futex is the low-addressed half, which holds the low bits on the
+    // little-endian targets the header format is defined for
+    unsafe { &*(word.as_ptr() as *const AtomicU32) }
+}
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and other writers may be waiting to be woken.
+const CONTENDED: u32 = 2;
+
+#[cfg(target_os = "linux")]
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    // Returns at once if the word no longer holds `expected`; spurious
+    // wakeups are fine, the caller re-checks
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAIT, expected, std::ptr::null::<libc::timespec>());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, 1);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    for _ in 0..100 {
+        if word.load(Ordering::Relaxed) != expected {
+            return;
+        }
+        std::hint::spin_loop();
+    }
+    std::thread::yield_now();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wake(_word: &AtomicU32) {}
+
+/// Holds the header lock of a segment until dropped. The lock word is in
+/// the shared mapping, so it excludes handles in other processes too. It
+/// is kept as a pointer so the holder can still write the rest of the map.
+struct HeaderLock(*const AtomicU32);
+
+impl HeaderLock {
+    fn acquire(map: &[u8]) -> Self {
+        let word = lock_word(map);
+        if word.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // Announce a waiter; taking the lock this way leaves it marked
+            // contended, which costs at most one needless wake
+            while word.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(word, CONTENDED);
+            }
+        }
+        Self(word)
+    }
+}
+
+impl Drop for HeaderLock {
+    fn drop(&mut self) {
+        // The guard never outlives the `ShmSegment` method that took it
+        let word = unsafe { &*self.0 };
+        if word.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(word);
+        }
+    }
+}
+
+/// Live frames copied out of a segment by `snapshot`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    data: Vec<u8>,
+    /// The segment's `generation` when the copy was taken.
+    pub generation: u64,
+}
+
+impl Snapshot {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn frames(&self) -> Frames<'_> {
+        Frames { data: &self.data, off: 0 }
+    }
+}
+
+/// Read-write handle to a named segment.
+pub struct ShmSegment {
+    mmap: MmapMut,
+}
+
+impl ShmSegment {
+    /// Create a new segment with `capacity` bytes for frames. Fails if a
+    /// segment with this name already exists.
+    pub fn create(name: &str, capacity: usize) -> Result<Self> {
+        let path = segment_path(name)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create segment: {:?}", path))?;
+        file.set_len((HEADER_LEN + align8(capacity)) as u64)?;
+
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        mmap[OFF_CAPACITY..OFF_CAPACITY + 8].copy_from_slice(&(align8(capacity) as u64).to_le_bytes());
+        // Stamp the magic last, together with the version, so a handle that
+        // sees the magic sees a complete header
+        let stamp = SEGMENT_MAGIC as u64 | (VERSION as u64) << 32;
+        header_word(&mmap, 0).store(stamp, Ordering::Release);
+        Ok(Self { mmap })
+    }
+
+    /// Attach read-write to an existing segment.
+    pub fn open(name: &str) -> Result<Self> {
+        let path = segment_path(name)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open segment: {:?}", path))?;
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        check_header(&mmap, name)?;
+        Ok(Self { mmap })
+    }
+
+    /// Attach read-write if the segment exists and its creator has written
+    /// the header; `Ok(None)` if not yet. Any other failure, such as a name
+    /// held by a foreign file, is an error.
+    pub fn try_open(name: &str) -> Result<Option<Self>> {
+        let path = segment_path(name)?;
+        let mut magic = [0u8; 4];
+        match File::open(&path).and_then(|mut f| f.read_exact(&mut magic)) {
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::UnexpectedEof) => return Ok(None),
+            // Created, but `create` has not stamped the magic yet
+            Ok(()) if magic == [0; 4] => return Ok(None),
+            _ => {}
+        }
+        Self::open(name).map(Some)
+    }
+
+    /// Wait for a producer to create the segment, polling every `interval`,
+    /// and attach read-write once it appears. Fails after `timeout`.
+    pub fn try_open_with_retry(name: &str, timeout: Duration, interval: Duration) -> Result<Self> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(seg) = Self::try_open(name)? {
+                return Ok(seg);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                bail!("Segment {} did not appear within {:?}", name, timeout);
+            }
+            std::thread::sleep(interval.min(deadline - now));
+        }
+    }
+
+    /// Attach to an existing segment through a `PROT_READ` mapping. The
+    /// returned handle has no write APIs.
+    pub fn open_readonly(name: &str) -> Result<ShmReader> {
+        let path = segment_path(name)?;
+        let file = File::open(&path).with_context(|| format!("Failed to open segment: {:?}", path))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        check_header(&mmap, name)?;
+        Ok(ShmReader { mmap })
+    }
+
+    /// Remove the named segment. Existing mappings stay valid.
+    pub fn unlink(name: &str) -> Result<()> {
+        let path = segment_path(name)?;
+        fs::remove_file(&path).with_context(|| format!("Failed to remove segment: {:?}", path))
+    }
+
+    /// Append a `FRAME_DATA` frame and return its sequence number.
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<u64> {
+        self.write_typed_frame(FRAME_DATA, payload)
+    }
+
+    /// Append a frame tagged `frame_type` and return its sequence number.
+    pub fn write_typed_frame(&mut self, frame_type: u16, payload: &[u8]) -> Result<u64> {
+        let _lock = HeaderLock::acquire(&self.mmap);
+        let stats = stats_of(&self.mmap);
+        let need = align8(FRAME_HEADER_LEN + payload.len());
+        if payload.len() > u32::MAX as usize || stats.used + need > stats.capacity {
+            bail!(
+                "Segment full: {} byte frame, {} of {} bytes used",
+                payload.len(),
+                stats.used,
+                stats.capacity
+            );
+        }
+
+        let seq = header_word(&self.mmap, OFF_SEQ).load(Ordering::Relaxed) + 1;
+        let off = HEADER_LEN + stats.used;
+        let frame = &mut self.mmap[off..off + need];
+        frame[0..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame[4..6].copy_from_slice(&frame_type.to_le_bytes());
+        frame[6..8].fill(0);
+        frame[8..16].copy_from_slice(&seq.to_le_bytes());
+        frame[FRAME_HEADER_LEN..FRAME_HEADER_LEN + payload.len()].copy_from_slice(payload);
+
+        header_word(&self.mmap, OFF_SEQ).store(seq, Ordering::Relaxed);
+        header_word(&self.mmap, OFF_FRAMES).fetch_add(1, Ordering::Release);
+        header_word(&self.mmap, OFF_USED).store((stats.used + need) as u64, Ordering::Release);
+        Ok(seq)
+    }
+
+    /// Live frame bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        published(&self.mmap)
+    }
+
+    pub fn frames(&self) -> Frames<'_> {
+        Frames { data: self.as_slice(), off: 0 }
+    }
+
+    /// Copy of the live frames, consistent even while other handles compact.
+    pub fn snapshot(&self) -> Snapshot {
+        snapshot_of(&self.mmap)
+    }
+
+    pub fn stats(&self) -> SegmentStats {
+        stats_of(&self.mmap)
+    }
+
+    /// Mark every frame with `seq <= through` as consumed and return how many
+    /// frames that was. Consumed frames stay in place until `compact`.
+    pub fn consume(&mut self, through: u64) -> usize {
+        let _lock = HeaderLock::acquire(&self.mmap);
+        let mut n = 0;
+        let mut off = 0;
+        for frame in self.frames() {
+            if frame.seq > through {
+                break;
+            }
+            off = align8(off + FRAME_HEADER_LEN + frame.payload.len());
+            n += 1;
+        }
+        let head = header_word(&self.mmap, OFF_HEAD).load(Ordering::Relaxed);
+        header_word(&self.mmap, OFF_FRAMES).fetch_sub(n as u64, Ordering::Release);
+        header_word(&self.mmap, OFF_HEAD).store(head + off as u64, Ordering::Release);
+        n
+    }
+
+    /// Move the live frames to the front of the log and return the bytes
+    /// reclaimed. Sequence numbers and the next `seq` are unchanged.
+    pub fn compact(&mut self) -> usize {
+        let _lock = HeaderLock::acquire(&self.mmap);
+        let stats = stats_of(&self.mmap);
+        if stats.consumed == 0 {
+            return 0;
+        }
+        header_word(&self.mmap, OFF_GEN).fetch_add(1, Ordering::Relaxed);
+        // Readers that see the moved frames also see the odd generation
+        fence(Ordering::Release);
+        let start = HEADER_LEN + stats.consumed;
+        self.mmap.copy_within(start..HEADER_LEN + stats.used, HEADER_LEN);
+        header_word(&self.mmap, OFF_HEAD).store(0, Ordering::Release);
+        header_word(&self.mmap, OFF_USED).store((stats.used - stats.consumed) as u64, Ordering::Release);
+        header_word(&self.mmap, OFF_GEN).fetch_add(1, Ordering::Release);
+        stats.consumed
+    }
+}
+
+/// Read-only handle to a named segment, for monitors and other observers.
+/// Only reading APIs exist, so it cannot be used to corrupt the fabric:
+///
+/// ```compile_fail
+/// let mut reader = rtt_fabric_shm::ShmSegment::open_readonly("segment").unwrap();
+/// reader.write_frame(b"frame").unwrap();
+/// ```
+pub struct ShmReader {
+    mmap: Mmap,
+}
+
+impl ShmReader {
+    /// Live frame bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        published(&self.mmap)
+    }
+
+    pub fn frames(&self) -> Frames<'_> {
+        Frames { data: self.as_slice(), off: 0 }
+    }
+
+    /// Copy of the live frames, consistent even while other handles compact.
+    pub fn snapshot(&self) -> Snapshot {
+        snapshot_of(&self.mmap)
+    }
+
+    pub fn stats(&self) -> SegmentStats {
+        stats_of(&self.mmap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(tag: &str) -> String {
+        format!("rtt-test-{}-{}", std::process::id(), tag)
+    }
+
+    #[test]
+    fn test_write_and_read_frames() {
+        let name = name("rw");
+        let mut seg = ShmSegment::create(&name, 256).unwrap();
+        assert_eq!(seg.write_frame(b"hello").unwrap(), 1);
+        assert_eq!(seg.write_frame(b"fabric!!").unwrap(), 2);
+
+        let other = ShmSegment::open(&name).unwrap();
+        let frames: Vec<_> = other.frames().collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload, b"hello");
+        assert_eq!(frames[1], Frame { seq: 2, frame_type: FRAME_DATA, payload: b"fabric!!" });
+        assert_eq!(other.stats(), SegmentStats { capacity: 256, used: 48, consumed: 0, frames: 2, generation: 0 });
+
+        assert!(seg.write_frame(&[0u8; 256]).is_err());
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_frames_of_type() {
+        use crate::{FRAME_CONTROL, FRAME_HEARTBEAT};
+
+        let name = name("types");
+        let mut seg = ShmSegment::create(&name, 256).unwrap();
+        seg.write_frame(b"data-1").unwrap();
+        seg.write_typed_frame(FRAME_CONTROL, b"drain").unwrap();
+        seg.write_typed_frame(FRAME_HEARTBEAT, b"").unwrap();
+        seg.write_frame(b"data-2").unwrap();
+
+        let reader = ShmSegment::open_readonly(&name).unwrap();
+        let data: Vec<_> = reader.frames().of_type(FRAME_DATA).map(|f| f.payload).collect();
+        assert_eq!(data, vec![&b"data-1"[..], b"data-2"]);
+        let control: Vec<_> = reader.frames().of_type(FRAME_CONTROL).collect();
+        assert_eq!(control, vec![Frame { seq: 2, frame_type: FRAME_CONTROL, payload: b"drain" }]);
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_live_frames() {
+        let name = name("compact");
+        let mut seg = ShmSegment::create(&name, 96).unwrap();
+        for payload in [&b"one"[..], b"two", b"three", b"four"] {
+            seg.write_frame(payload).unwrap();
+        }
+        // 4 frames of 24 bytes; a fifth does not fit
+        assert!(seg.write_frame(b"five").is_err());
+
+        assert_eq!(seg.consume(2), 2);
+        let reader = ShmSegment::open_readonly(&name).unwrap();
+        assert_eq!(reader.stats().consumed, 48);
+        assert_eq!(reader.frames().map(|f| f.seq).collect::<Vec<_>>(), vec![3, 4]);
+
+        assert_eq!(seg.compact(), 48);
+        let stats = reader.stats();
+        assert_eq!((stats.used, stats.consumed, stats.frames, stats.generation), (48, 0, 2, 2));
+        let live: Vec<_> = reader.frames().map(|f| (f.seq, f.payload)).collect();
+        assert_eq!(live, vec![(3, &b"three"[..]), (4, b"four")]);
+
+        // Reclaimed space is writable and the sequence continues
+        assert_eq!(seg.write_frame(b"five").unwrap(), 5);
+        assert_eq!(seg.compact(), 0);
+        assert_eq!(reader.frames().last().unwrap().payload, b"five");
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_survives_concurrent_compaction() {
+        let name = name("seqlock");
+        let mut seg = ShmSegment::create(&name, 4096).unwrap();
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        // Frame `seq` carries a payload naming it; compaction moves frames
+        // under the reader while it copies them
+        let reader = {
+            let (name, done) = (name.clone(), done.clone());
+            std::thread::spawn(move || {
+                let reader = ShmSegment::open_readonly(&name).unwrap();
+                let mut checked = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let snapshot = reader.snapshot();
+                    assert_eq!(snapshot.generation % 2, 0);
+                    for frame in snapshot.frames() {
+                        assert_eq!(frame.payload, format!("frame-{:06}", frame.seq).as_bytes());
+                        checked += 1;
+                    }
+                }
+                checked
+            })
+        };
+        for seq in 1..=5000u64 {
+            assert_eq!(seg.write_frame(format!("frame-{:06}", seq).as_bytes()).unwrap(), seq);
+            if seq % 8 == 0 {
+                seg.consume(seq - 4);
+                seg.compact();
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        assert!(reader.join().unwrap() > 0);
+
+        let snapshot = seg.snapshot();
+        assert_eq!(snapshot.as_slice(), seg.as_slice());
+        assert_eq!(snapshot.generation, seg.stats().generation);
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_readonly_attach() {
+        let name = name("ro");
+        let mut seg = ShmSegment::create(&name, 128).unwrap();
+        seg.write_frame(b"before").unwrap();
+
+        let reader = ShmSegment::open_readonly(&name).unwrap();
+        assert_eq!(reader.frames().next().unwrap().payload, b"before");
+
+        // The reader sees frames published after it attached
+        seg.write_frame(b"after").unwrap();
+        assert_eq!(reader.frames().count(), 2);
+        assert_eq!(reader.stats(), seg.stats());
+        assert_eq!(reader.as_slice(), seg.as_slice());
+
+        #[cfg(target_os = "linux")]
+        {
+            let path = segment_path(&name).unwrap();
+            let maps = fs::read_to_string("/proc/self/maps").unwrap();
+            let perms: Vec<&str> = maps
+                .lines()
+                .filter(|l| l.ends_with(path.to_str().unwrap()))
+                .filter_map(|l| l.split_whitespace().nth(1))
+                .collect();
+            assert_eq!(perms.len(), 2);
+            assert!(perms.contains(&"r--s"), "no PROT_READ mapping in {:?}", perms);
+            assert!(perms.contains(&"rw-s"));
+        }
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        const WRITERS: usize = 4;
+        const FRAMES: usize = 500;
+        let name = name("mpsc");
+        let mut seg = ShmSegment::create(&name, WRITERS * FRAMES * 32).unwrap();
+
+        // Each writer attaches its own mapping, as a separate process would
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let name = name.clone();
+                std::thread::spawn(move || {
+                    let mut seg = ShmSegment::open(&name).unwrap();
+                    (0..FRAMES).map(|i| seg.write_frame(format!("w{}-{:04}", w, i).as_bytes()).unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut seqs: Vec<u64> = writers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+        seqs.sort();
+        assert_eq!(seqs, (1..=(WRITERS * FRAMES) as u64).collect::<Vec<_>>());
+
+        let frames: Vec<_> = seg.frames().collect();
+        assert_eq!(frames.len(), WRITERS * FRAMES);
+        assert_eq!(seg.stats().used, WRITERS * FRAMES * 24);
+        let mut next = [0usize; WRITERS];
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.seq, i as u64 + 1);
+            // Every payload is intact and each writer's frames are in its order
+            let text = std::str::from_utf8(frame.payload).unwrap();
+            let (w, n) = text[1..].split_once('-').unwrap();
+            let w: usize = w.parse().unwrap();
+            assert_eq!(n.parse::<usize>().unwrap(), next[w]);
+            next[w] += 1;
+        }
+        assert_eq!(next, [FRAMES; WRITERS]);
+        assert_eq!(lock_word(&seg.mmap).load(Ordering::Relaxed), UNLOCKED);
+        assert_eq!(seg.write_frame(b"after").unwrap(), (WRITERS * FRAMES) as u64 + 1);
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_open_waits_for_producer() {
+        let name = name("retry");
+        assert!(ShmSegment::try_open(&name).unwrap().is_none());
+        let err = ShmSegment::try_open_with_retry(&name, Duration::from_millis(30), Duration::from_millis(10));
+        assert!(err.err().unwrap().to_string().contains("did not appear"));
+
+        // The consumer starts waiting before the producer creates the segment
+        let consumer = {
+            let name = name.clone();
+            std::thread::spawn(move || ShmSegment::try_open_with_retry(&name, Duration::from_secs(5), Duration::from_millis(5)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        let mut seg = ShmSegment::create(&name, 128).unwrap();
+        seg.write_frame(b"ready").unwrap();
+
+        let attached = consumer.join().unwrap().unwrap();
+        assert_eq!(attached.stats().capacity, 128);
+        ShmSegment::unlink(&name).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_names_and_foreign_files() {
+        assert!(ShmSegment::create("../escape", 64).is_err());
+        assert!(ShmSegment::open("").is_err());
+
+        let name = name("foreign");
+        fs::write(segment_path(&name).unwrap(), [0u8; 128]).unwrap();
+        assert!(ShmSegment::open_readonly(&name).is_err());
+        // All zeroes is a segment still being created, not a foreign file
+        assert!(ShmSegment::try_open(&name).unwrap().is_none());
+        fs::write(segment_path(&name).unwrap(), [0xffu8; 128]).unwrap();
+        assert!(ShmSegment::try_open(&name).is_err());
+        ShmSegment::unlink(&name).unwrap();
+    }
+}
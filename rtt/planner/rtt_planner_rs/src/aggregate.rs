@@ -0,0 +1,280 @@
+//! Summary routes
+//!
+//! `--aggregate-by labels.cluster` writes a smaller plan for display and
+//! coarse transports: the routes of one batch that share a value of the
+//! label collapse into one summary route `cluster:<value> -> cluster:<value>`
+//! carrying the label, and the routes it stands for go to a sidecar,
+//! `<out>.aggregates.json`. Routes without the label, and values held by a
+//! single route in the batch, are kept as they are. Summary weights are the
+//! sum of their routes' weights.
+//!
+//! `rtt-planner expand-aggregates <plan.json> <sidecar.json> <out.json>`
+//! puts the detailed routes back in their original places, giving back the
+//! detailed plan and its `plan_id`. The sidecar records where each summary
+//! sits, so a real route shaped like one is left alone. The aggregate plan has its own
+//! `plan_id`; the sidecar records both, and the aggregate plan records the
+//! detailed one in its `aggregate.detail_plan_id` annotation, so signing the
+//! aggregate also vouches for the plan it stands for. `--with-rollback`
+//! inverts the detailed plan; `--receipt` cannot be combined with
+//! `--aggregate-by`.
+
+use crate::verify::load_plan;
+use crate::{compute_plan_id, plan_name, validate_path, Plan, Route};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Annotation of an aggregate plan naming the detailed plan it summarizes.
+pub const DETAIL_ANNOTATION: &str = "aggregate.detail_plan_id";
+
+/// The label name from `labels.<name>` (or `label.<name>`).
+pub fn parse_label(s: &str) -> Result<String> {
+    match s.strip_prefix("labels.").or_else(|| s.strip_prefix("label.")) {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => bail!("--aggregate-by expects labels.<name>, got: {}", s),
+    }
+}
+
+/// Where the sidecar for a plan written to `out` goes.
+pub fn sidecar_path(out: &Path) -> PathBuf {
+    out.with_extension("aggregates.json")
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Member {
+    /// Position in the detailed plan's `routes_add`.
+    pub index: usize,
+    pub route: Route,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Aggregate {
+    /// Position of the summary route in the aggregate plan's `routes_add`.
+    pub position: usize,
+    pub from: String,
+    pub batch: Option<String>,
+    pub members: Vec<Member>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Sidecar {
+    pub plan_id: String,
+    pub aggregate_plan_id: String,
+    pub label: String,
+    pub aggregates: Vec<Aggregate>,
+}
+
+fn rehash(plan: &mut Plan) -> Result<()> {
+    plan.plan_id = compute_plan_id(plan)?;
+    plan.name = plan_name(&plan.plan_id);
+    Ok(())
+}
+
+/// `plan` with other `routes_add`, rehashed and unsigned.
+fn with_routes(plan: &Plan, routes_add: Vec<Route>) -> Result<Plan> {
+    let mut out = Plan {
+        plan_id: String::new(),
+        routes_add,
+        routes_del: plan.routes_del.clone(),
+        order: plan.order.clone(),
+        annotations: plan.annotations.clone(),
+        batch_annotations: plan.batch_annotations.clone(),
+        batch_notes: plan.batch_notes.clone(),
+        valid_until: plan.valid_until,
+        manifests_digest: plan.manifests_digest.clone(),
+        depends_on: plan.depends_on.clone(),
+        ..Default::default()
+    };
+    rehash(&mut out)?;
+    Ok(out)
+}
+
+/// Summarize `plan` by `label`. The result is unsigned.
+pub fn aggregate_plan(plan: &Plan, label: &str) -> Result<(Plan, Sidecar)> {
+    if plan.annotations.contains_key(DETAIL_ANNOTATION) {
+        bail!("Plan is already an aggregate (it has a {} annotation)", DETAIL_ANNOTATION);
+    }
+    let batch_of = |r: &Route| r.batch.clone().or_else(|| plan.order.first().cloned());
+    let mut groups: BTreeMap<(String, Option<String>), Vec<usize>> = BTreeMap::new();
+    for (i, route) in plan.routes_add.iter().enumerate() {
+        if let Some(value) = route.labels.get(label) {
+            groups.entry((value.clone(), batch_of(route))).or_default().push(i);
+        }
+    }
+
+    // Each summary takes the place of its first route
+    let mut summaries = BTreeMap::new();
+    let mut aggregates = Vec::new();
+    for ((value, batch), indices) in groups.into_iter().filter(|(_, g)| g.len() > 1) {
+        let endpoint = format!("{}:{}", label, value);
+        let weights: Vec<f64> = indices.iter().filter_map(|&i| plan.routes_add[i].weight).collect();
+        let summary = Route {
+            from: endpoint.clone(),
+            to: endpoint.clone(),
+            labels: BTreeMap::from([(label.to_string(), value)]),
+            weight: (!weights.is_empty()).then(|| weights.iter().sum()),
+            batch: plan.routes_add[indices[0]].batch.clone(),
+            ..Default::default()
+        };
+        summaries.insert(indices[0], (summary, aggregates.len()));
+        let members = indices.iter().map(|&index| Member { index, route: plan.routes_add[index].clone() }).collect();
+        aggregates.push(Aggregate { position: 0, from: endpoint, batch, members });
+    }
+    let members: HashSet<usize> = aggregates.iter().flat_map(|a| a.members.iter().map(|m| m.index)).collect();
+    let mut routes_add = Vec::new();
+    for (i, route) in plan.routes_add.iter().enumerate() {
+        if let Some((summary, k)) = summaries.remove(&i) {
+            aggregates[k].position = routes_add.len();
+            routes_add.push(summary);
+        } else if !members.contains(&i) {
+            routes_add.push(route.clone());
+        }
+    }
+    let plan_id = compute_plan_id(plan)?;
+    let mut aggregated = with_routes(plan, routes_add)?;
+    aggregated.annotations.insert(DETAIL_ANNOTATION.to_string(), plan_id.clone());
+    rehash(&mut aggregated)?;
+    let sidecar = Sidecar {
+        plan_id,
+        aggregate_plan_id: aggregated.plan_id.clone(),
+        label: label.to_string(),
+        aggregates,
+    };
+    Ok((aggregated, sidecar))
+}
+
+/// Restore the detailed plan from an aggregate plan and its sidecar.
+pub fn expand_plan(plan: &Plan, sidecar: &Sidecar) -> Result<Plan> {
+    if compute_plan_id(plan)? != sidecar.aggregate_plan_id {
+        bail!("Sidecar is for aggregate plan {}, not this plan", sidecar.aggregate_plan_id);
+    }
+    if let Some(a) = sidecar.aggregates.iter().find(|a| a.members.len() < 2) {
+        bail!("Sidecar aggregate {} in {:?} has {} member(s); a summary stands for at least 2 routes", a.from, a.batch, a.members.len());
+    }
+    let total = plan.routes_add.len() + sidecar.aggregates.iter().map(|a| a.members.len() - 1).sum::<usize>();
+    let mut slots: Vec<Option<Route>> = vec![None; total];
+    for aggregate in &sidecar.aggregates {
+        for member in &aggregate.members {
+            let slot = slots
+                .get_mut(member.index)
+                .with_context(|| format!("Sidecar aggregate {}: position {} is out of range", aggregate.from, member.index))?;
+            if slot.is_some() {
+                bail!("Sidecar aggregate {}: position {} is taken twice", aggregate.from, member.index);
+            }
+            *slot = Some(member.route.clone());
+        }
+    }
+    // Summaries are found by position: a real route may have a summary's shape
+    let mut positions = HashSet::new();
+    for aggregate in &sidecar.aggregates {
+        match plan.routes_add.get(aggregate.position) {
+            Some(r) if r.from == aggregate.from && r.to == aggregate.from && positions.insert(aggregate.position) => {}
+            _ => bail!("Sidecar aggregate {}: position {} is not its summary route", aggregate.from, aggregate.position),
+        }
+    }
+    let mut rest = plan.routes_add.iter().enumerate().filter(|(i, _)| !positions.contains(i)).map(|(_, r)| r);
+    for slot in slots.iter_mut().filter(|s| s.is_none()) {
+        *slot = Some(rest.next().context("Sidecar does not match the plan's routes")?.clone());
+    }
+    if rest.next().is_some() {
+        bail!("Sidecar does not match the plan's routes");
+    }
+
+    if plan.annotations.get(DETAIL_ANNOTATION) != Some(&sidecar.plan_id) {
+        bail!("Aggregate plan does not name the sidecar's detailed plan {}", sidecar.plan_id);
+    }
+    let mut detailed = with_routes(plan, slots.into_iter().flatten().collect())?;
+    detailed.annotations.remove(DETAIL_ANNOTATION);
+    rehash(&mut detailed)?;
+    if detailed.plan_id != sidecar.plan_id {
+        bail!("Expanded plan hashes to {}, but the sidecar records {}", detailed.plan_id, sidecar.plan_id);
+    }
+    Ok(detailed)
+}
+
+pub fn write_sidecar(sidecar: &Sidecar, path: &Path) -> Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(sidecar)?).with_context(|| format!("Failed to write aggregate sidecar: {:?}", path))
+}
+
+pub fn cmd_expand_aggregates(args: &[String]) -> Result<()> {
+    let [plan_arg, sidecar_arg, out_arg] = args else {
+        eprintln!("usage: rtt-planner expand-aggregates <plan.json> <sidecar.json> <out.json>");
+        bail!("Invalid arguments");
+    };
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    let path = validate_path(sidecar_arg, "sidecar file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read sidecar: {:?}", path))?;
+    let sidecar: Sidecar = serde_json::from_str(&content).with_context(|| "Failed to parse sidecar JSON")?;
+    let out = validate_path(out_arg, "output file")?;
+    let detailed = expand_plan(&plan, &sidecar)?;
+    fs::write(&out, serde_json::to_vec_pretty(&detailed)?).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    println!("{}", detailed.plan_id);
+    eprintln!("[OK] Expanded {} summary route(s) into {} route(s), unsigned: {:?}", sidecar.aggregates.len(), detailed.routes_add.len(), out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_and_expand() {
+        let route = |from: &str, to: &str, cluster: Option<&str>, batch: &str| Route {
+            from: from.into(),
+            to: to.into(),
+            labels: cluster.map(|c| BTreeMap::from([("cluster".to_string(), c.to_string())])).unwrap_or_default(),
+            weight: Some(1.0),
+            batch: Some(batch.into()),
+            ..Default::default()
+        };
+        let mut plan = Plan {
+            routes_add: vec![
+                route("e1", "e2", Some("east"), "BATCH-1"),
+                route("x", "y", None, "BATCH-1"),
+                route("e2", "e3", Some("east"), "BATCH-1"),
+                route("w1", "w2", Some("west"), "BATCH-1"),
+                route("e3", "e1", Some("east"), "BATCH-1"),
+                route("e1", "e4", Some("east"), "BATCH-2"),
+                route("cluster:east", "cluster:east", None, "BATCH-1"),
+            ],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        rehash(&mut plan).unwrap();
+
+        let (aggregated, sidecar) = aggregate_plan(&plan, &parse_label("labels.cluster").unwrap()).unwrap();
+        let got: Vec<_> = aggregated.routes_add.iter().map(|r| format!("{}->{}", r.from, r.to)).collect();
+        assert_eq!(got, vec!["cluster:east->cluster:east", "x->y", "w1->w2", "e1->e4", "cluster:east->cluster:east"]);
+        assert_eq!(aggregated.routes_add[0].weight, Some(3.0));
+        assert_eq!(sidecar.aggregates.len(), 1);
+        assert_eq!(sidecar.aggregates[0].position, 0);
+        assert_eq!(sidecar.aggregates[0].members.iter().map(|m| m.index).collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(sidecar.plan_id, plan.plan_id);
+        assert_eq!(aggregated.annotations[DETAIL_ANNOTATION], plan.plan_id);
+        assert_eq!(aggregated.plan_id, compute_plan_id(&aggregated).unwrap());
+        aggregated.validate().unwrap();
+        assert!(aggregate_plan(&aggregated, "cluster").is_err());
+
+        // Expansion through the written sidecar restores the detailed plan
+        let reloaded: Sidecar = serde_json::from_slice(&serde_json::to_vec(&sidecar).unwrap()).unwrap();
+        let detailed = expand_plan(&aggregated, &reloaded).unwrap();
+        assert_eq!(detailed.plan_id, plan.plan_id);
+        assert_eq!(serde_json::to_value(&detailed.routes_add).unwrap(), serde_json::to_value(&plan.routes_add).unwrap());
+
+        assert!(expand_plan(&plan, &sidecar).is_err());
+
+        // A hand-edited sidecar is rejected, not trusted for arithmetic
+        let mut broken: Sidecar = serde_json::from_slice(&serde_json::to_vec(&sidecar).unwrap()).unwrap();
+        broken.aggregates[0].members.clear();
+        let Err(err) = expand_plan(&aggregated, &broken) else { panic!("expanded an empty aggregate") };
+        assert!(err.to_string().contains("cluster:east"), "{}", err);
+        let mut broken: Sidecar = serde_json::from_slice(&serde_json::to_vec(&sidecar).unwrap()).unwrap();
+        broken.aggregates[0].members[1].index = 0;
+        assert!(expand_plan(&aggregated, &broken).is_err());
+        broken.aggregates[0].members[1].index = 99;
+        assert!(expand_plan(&aggregated, &broken).is_err());
+        assert!(parse_label("cluster").is_err());
+    }
+}
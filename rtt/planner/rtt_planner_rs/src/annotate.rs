@@ -0,0 +1,111 @@
+//! Per-batch annotations
+//!
+//! `rtt-planner annotate-batch <plan.json> <batch> key=value... [--unsigned]`
+//! attaches operator metadata (owner, maintenance window) to one batch of
+//! an existing plan. By default it goes into `batch_annotations`, which is
+//! signed content: the plan gets a new `plan_id` and loses its signatures,
+//! and must be re-signed. With `--unsigned` it goes into `batch_notes`,
+//! which lies outside the canonical bytes, so `plan_id` and signatures stay
+//! valid but nothing vouches for the notes.
+
+use crate::verify::load_plan;
+use crate::{rehash_plan, validate_path, Plan};
+use anyhow::{bail, Context, Result};
+use std::fs;
+
+/// Add `pairs` to `batch`'s metadata. Returns whether signatures were
+/// dropped because signed content changed.
+pub fn annotate_batch(plan: &mut Plan, batch: &str, pairs: &[(String, String)], unsigned: bool) -> Result<bool> {
+    if !plan.order.iter().any(|b| b == batch) {
+        bail!("Batch {} is not in the plan order", batch);
+    }
+    let target = if unsigned { &mut plan.batch_notes } else { &mut plan.batch_annotations };
+    target.entry(batch.to_string()).or_default().extend(pairs.iter().cloned());
+    if unsigned {
+        return Ok(false);
+    }
+    let signed = plan.all_signatures().next().is_some();
+    Ok(rehash_plan(plan)?.is_some() && signed)
+}
+
+fn parse_pair(pair: &str) -> Result<(String, String)> {
+    pair.split_once('=')
+        .filter(|(k, _)| !k.is_empty())
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .with_context(|| format!("Expected key=value, got: {}", pair))
+}
+
+pub fn cmd_annotate_batch(args: &[String]) -> Result<()> {
+    let unsigned = args.iter().any(|a| a == "--unsigned");
+    let positional: Vec<&String> = args.iter().filter(|a| *a != "--unsigned").collect();
+    let [plan_arg, batch, pairs @ ..] = positional.as_slice() else {
+        eprintln!("usage: rtt-planner annotate-batch <plan.json> <batch> key=value... [--unsigned]");
+        bail!("Invalid arguments");
+    };
+    if pairs.is_empty() {
+        bail!("annotate-batch needs at least one key=value");
+    }
+    let pairs = pairs.iter().map(|p| parse_pair(p)).collect::<Result<Vec<_>>>()?;
+    let path = validate_path(plan_arg, "plan file")?;
+    let mut plan = load_plan(&path)?;
+    let dropped_signatures = annotate_batch(&mut plan, batch, &pairs, unsigned)?;
+    fs::write(&path, serde_json::to_vec_pretty(&plan)?).with_context(|| format!("Failed to write plan file: {:?}", path))?;
+
+    println!("{}", plan.plan_id);
+    if dropped_signatures {
+        eprintln!("[WARN] Signed content changed; signatures removed, re-sign the plan");
+    }
+    eprintln!("[OK] Annotated {} with {} key(s)", batch, pairs.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compute_plan_id, Route, Sign};
+
+    fn signed() -> Plan {
+        let mut plan = Plan {
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), batch: Some("BATCH-2".into()), ..Default::default() }],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(Sign { alg: "ed25519".into(), key_id: "dev".into(), sig: "c2ln".into(), signed_at: None });
+        plan
+    }
+
+    fn owner() -> Vec<(String, String)> {
+        vec![("owner".into(), "netops".into()), ("window".into(), "sat-02:00".into())]
+    }
+
+    #[test]
+    fn test_signed_batch_annotation_drops_signatures() {
+        let mut plan = signed();
+        let old_id = plan.plan_id.clone();
+        assert!(annotate_batch(&mut plan, "BATCH-2", &owner(), false).unwrap());
+        assert_eq!(plan.batch_annotations["BATCH-2"]["owner"], "netops");
+        assert!(plan.sign.is_none());
+        assert_ne!(plan.plan_id, old_id);
+
+        // Preserved through a save and reload, and covered by plan_id
+        let reloaded: Plan = serde_json::from_slice(&serde_json::to_vec(&plan).unwrap()).unwrap();
+        assert_eq!(reloaded.batch_annotations, plan.batch_annotations);
+        assert_eq!(compute_plan_id(&reloaded).unwrap(), plan.plan_id);
+
+        assert!(annotate_batch(&mut plan, "BATCH-9", &owner(), false).is_err());
+    }
+
+    #[test]
+    fn test_unsigned_batch_notes_keep_signatures() {
+        let mut plan = signed();
+        let old_id = plan.plan_id.clone();
+        assert!(!annotate_batch(&mut plan, "BATCH-2", &owner(), true).unwrap());
+        assert_eq!(plan.batch_notes["BATCH-2"]["window"], "sat-02:00");
+        assert!(plan.batch_annotations.is_empty());
+        assert!(plan.sign.is_some());
+        assert_eq!(plan.plan_id, old_id);
+        assert_eq!(compute_plan_id(&plan).unwrap(), old_id);
+        assert_eq!(parse_pair("k=v=w").unwrap(), ("k".to_string(), "v=w".to_string()));
+    }
+}
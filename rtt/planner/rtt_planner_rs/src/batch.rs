@@ -0,0 +1,511 @@
+//! Rollout batching
+//!
+//! Without `--batch-size` or `--batches`, every route goes into the single
+//! batch `BATCH-1` and routes stay untagged, which is the historical plan
+//! shape. Otherwise routes are split in plan order into `BATCH-1..BATCH-n`,
+//! so a route never lands in an earlier batch than one listed before it, and
+//! each route records its batch.
+//!
+//! `greedy` fills each batch to capacity and leaves the remainder in the last
+//! one. `balanced` uses the same number of batches (or `--batches`) and makes
+//! sizes differ by at most one, so rollout stages parallelize evenly.
+//!
+//! Routes may list prerequisites in `after` as `from->to`. Then every route
+//! is layered one batch past its latest prerequisite and each layer is
+//! filled greedily up to `--batch-size`. `--max-batches n` rejects layerings
+//! longer than `n` batches; with `--optimize` the solver instead searches for
+//! any precedence-respecting assignment into at most `n` batches. If the
+//! solver backend fails, as opposed to proving no assignment exists,
+//! batching warns and keeps the layering, over `--max-batches`;
+//! `--require-optimize` makes that an error instead. A model of more than
+//! `MAX_ASSIGNMENT_VARS` variables (routes times `--max-batches`), or a
+//! search that visits more than `SOLVER_NODE_BUDGET` nodes without settling
+//! the question, counts as a backend failure.
+
+use crate::identity::IdentityKeys;
+use crate::Route;
+use anyhow::{bail, Context, Result};
+use rtt_solver::{Cmp, Control, Sense, SolveOptions, Solver, Status, VarId};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BatchStrategy {
+    #[default]
+    Greedy,
+    Balanced,
+}
+
+impl FromStr for BatchStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "greedy" => Ok(Self::Greedy),
+            "balanced" => Ok(Self::Balanced),
+            _ => bail!("Unknown batch strategy: {} (expected greedy or balanced)", s),
+        }
+    }
+}
+
+/// Batching flags, as given on the command line.
+#[derive(Clone, Debug, Default)]
+pub struct BatchConfig {
+    pub strategy: BatchStrategy,
+    pub batch_size: Option<usize>,
+    pub batches: Option<usize>,
+    pub max_batches: Option<usize>,
+    /// Search for an assignment within `max_batches` when layering exceeds it.
+    pub optimize: bool,
+    /// Fail rather than fall back to layering when the solver backend fails.
+    pub require_optimize: bool,
+}
+
+impl BatchConfig {
+    /// Whether any flag asks for more than the historical single batch.
+    pub fn is_set(&self) -> bool {
+        self.batch_size.is_some()
+            || self.batches.is_some()
+            || self.max_batches.is_some()
+            || self.optimize
+            || self.strategy != BatchStrategy::Greedy
+    }
+}
+
+/// Batch of each route in a plan's `routes_add`, indexed once for repeated
+/// lookups. Routes are matched on their identity, so a copy of a planned
+/// route finds its batch whether or not it is tagged, relabeled outside the
+/// identity keys or reweighted. Untagged planned routes belong to the first
+/// batch, as elsewhere.
+pub struct RouteBatches<'a> {
+    identity: &'a IdentityKeys,
+    batches: HashMap<String, &'a str>,
+}
+
+/// A route's content without its batch tag, for matching copies of it.
+pub fn untagged_key(route: &Route) -> String {
+    let mut value = serde_json::to_value(route).expect("routes serialize");
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("batch");
+    }
+    value.to_string()
+}
+
+impl<'a> RouteBatches<'a> {
+    pub fn new(routes: &'a [Route], order: &'a [String], identity: &'a IdentityKeys) -> Self {
+        let batches = routes
+            .iter()
+            .filter_map(|r| Some((identity.route_id(r), r.batch.as_deref().or(order.first().map(String::as_str))?)))
+            .collect();
+        Self { identity, batches }
+    }
+
+    /// The batch `route` is planned in, or `None` if it is not planned.
+    pub fn batch_of(&self, route: &Route) -> Option<&'a str> {
+        self.batches.get(&self.identity.route_id(route)).copied()
+    }
+}
+
+pub fn batch_name(index: usize) -> String {
+    format!("BATCH-{}", index + 1)
+}
+
+fn batch_sizes(
+    n: usize,
+    strategy: BatchStrategy,
+    batch_size: Option<usize>,
+    batches: Option<usize>,
+) -> Vec<usize> {
+    if n == 0 {
+        return vec![];
+    }
+    // Capacity implied by each flag; `--batch-size` is a hard cap either way
+    let from_count = batches.map(|b| n.div_ceil(b));
+    let cap = match (batch_size, from_count) {
+        (Some(s), Some(c)) => s.min(c),
+        (Some(s), None) => s,
+        (None, Some(c)) => c,
+        (None, None) => n,
+    };
+    let count = n.div_ceil(cap);
+
+    match strategy {
+        BatchStrategy::Greedy => (0..count).map(|i| cap.min(n - i * cap)).collect(),
+        BatchStrategy::Balanced => (0..count).map(|i| n / count + usize::from(i < n % count)).collect(),
+    }
+}
+
+/// How `after` names a route: `from->to`.
+pub fn route_key(route: &Route) -> String {
+    format!("{}->{}", route.from, route.to)
+}
+
+/// Prerequisite indices of each route, or `None` when no route has `after`.
+fn prerequisites(routes: &[Route]) -> Result<Option<Vec<Vec<usize>>>> {
+    if routes.iter().all(|r| r.after.is_empty()) {
+        return Ok(None);
+    }
+    let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, route) in routes.iter().enumerate() {
+        by_key.entry(route_key(route)).or_default().push(i);
+    }
+    let mut deps = Vec::with_capacity(routes.len());
+    for route in routes {
+        let mut prereqs = Vec::new();
+        for key in &route.after {
+            let found = by_key
+                .get(key)
+                .with_context(|| format!("Route {} is after {}, which is not in the plan", route_key(route), key))?;
+            prereqs.extend(found);
+        }
+        deps.push(prereqs);
+    }
+    Ok(Some(deps))
+}
+
+/// Earliest layer of each route: one past its latest prerequisite.
+fn layers(routes: &[Route], deps: &[Vec<usize>]) -> Result<Vec<usize>> {
+    let mut dependents = vec![Vec::new(); deps.len()];
+    let mut pending: Vec<usize> = deps.iter().map(Vec::len).collect();
+    for (r, prereqs) in deps.iter().enumerate() {
+        for &p in prereqs {
+            dependents[p].push(r);
+        }
+    }
+    let mut layer = vec![0; deps.len()];
+    let mut ready: Vec<usize> = (0..deps.len()).filter(|&r| pending[r] == 0).collect();
+    let mut visited = 0;
+    while let Some(p) = ready.pop() {
+        visited += 1;
+        for &r in &dependents[p] {
+            layer[r] = layer[r].max(layer[p] + 1);
+            pending[r] -= 1;
+            if pending[r] == 0 {
+                ready.push(r);
+            }
+        }
+    }
+    if visited < deps.len() {
+        let stuck = (0..deps.len()).find(|&r| pending[r] > 0).unwrap();
+        bail!("Route {} is part of an `after` cycle", route_key(&routes[stuck]));
+    }
+    Ok(layer)
+}
+
+/// Fill each layer in plan order, starting a new batch when it is full.
+fn layered_assignment(layer: &[usize], cap: usize) -> Vec<usize> {
+    let mut assignment = vec![0; layer.len()];
+    let mut next = 0;
+    for l in 0..=layer.iter().copied().max().unwrap_or(0) {
+        let members: Vec<usize> = (0..layer.len()).filter(|&r| layer[r] == l).collect();
+        for chunk in members.chunks(cap) {
+            for &r in chunk {
+                assignment[r] = next;
+            }
+            next += 1;
+        }
+    }
+    assignment
+}
+
+/// Search nodes an `--optimize` solve may visit before giving up.
+pub const SOLVER_NODE_BUDGET: u64 = 1_000_000;
+
+/// Largest assignment model `solve_assignment` builds: one variable per
+/// route and batch.
+pub const MAX_ASSIGNMENT_VARS: usize = 100_000;
+
+/// Searches for a batch assignment; see `solve_assignment`.
+type AssignmentSolver = fn(&[Vec<usize>], usize, usize) -> Result<Option<Vec<usize>>>;
+
+/// Assign routes to at most `max_batches` batches of at most `cap` routes
+/// with every route in a later batch than its prerequisites, keeping routes
+/// as early as possible. `None` if no such assignment exists; an error
+/// means the backend itself failed, including running out of its node
+/// budget.
+fn solve_assignment(deps: &[Vec<usize>], cap: usize, max_batches: usize) -> Result<Option<Vec<usize>>> {
+    solve_assignment_within(deps, cap, max_batches, SOLVER_NODE_BUDGET)
+}
+
+fn solve_assignment_within(deps: &[Vec<usize>], cap: usize, max_batches: usize, max_nodes: u64) -> Result<Option<Vec<usize>>> {
+    let vars = deps.len().saturating_mul(max_batches);
+    if vars > MAX_ASSIGNMENT_VARS {
+        bail!(
+            "Solver backend failed: {} routes in {} batches is {} variables, over the limit of {}",
+            deps.len(),
+            max_batches,
+            vars,
+            MAX_ASSIGNMENT_VARS
+        );
+    }
+    let mut solver = Solver::new();
+    let x: Vec<Vec<VarId>> = (0..deps.len())
+        .map(|r| (0..max_batches).map(|k| solver.add_binary(&format!("x:{}:{}", r, k))).collect())
+        .collect();
+    let position = |r: usize, sign: f64| x[r].iter().enumerate().map(move |(k, &v)| (v, sign * k as f64));
+
+    for (r, vars) in x.iter().enumerate() {
+        let terms: Vec<_> = vars.iter().map(|&v| (v, 1.0)).collect();
+        solver.add_constraint(&format!("assign:{}", r), &terms, Cmp::Eq, 1.0);
+    }
+    for k in 0..max_batches {
+        let terms: Vec<_> = x.iter().map(|vars| (vars[k], 1.0)).collect();
+        solver.add_constraint(&format!("cap:{}", k), &terms, Cmp::Le, cap as f64);
+    }
+    for (r, prereqs) in deps.iter().enumerate() {
+        for &p in prereqs {
+            let terms: Vec<_> = position(r, 1.0).chain(position(p, -1.0)).collect();
+            solver.add_constraint(&format!("prec:{}:{}", r, p), &terms, Cmp::Ge, 1.0);
+        }
+    }
+    let objective: Vec<_> = (0..deps.len()).flat_map(|r| position(r, 1.0)).collect();
+    solver.set_objective(Sense::Minimize, &objective);
+
+    let opts = SolveOptions { max_nodes: Some(max_nodes), ..Default::default() };
+    let solution = solver.solve_with_options(&opts, |_| Control::Continue).context("Solver backend failed")?;
+    match solution.status {
+        Status::Infeasible => return Ok(None),
+        Status::BudgetExhausted => bail!("Solver backend failed: no answer within {} search nodes", max_nodes),
+        _ => {}
+    }
+    Ok(Some(
+        x.iter()
+            .map(|vars| vars.iter().position(|&v| solution.is_selected(v)).unwrap())
+            .collect(),
+    ))
+}
+
+/// Tag routes with their batch and return the batch order. Routes with
+/// prerequisites are reordered so batches stay contiguous.
+pub fn assign_batches(routes: &mut Vec<Route>, config: &BatchConfig) -> Result<Vec<String>> {
+    assign_batches_with(routes, config, solve_assignment)
+}
+
+fn assign_batches_with(routes: &mut Vec<Route>, config: &BatchConfig, solver: AssignmentSolver) -> Result<Vec<String>> {
+    let Some(deps) = prerequisites(routes)? else {
+        return assign_unordered(routes, config);
+    };
+    if config.strategy == BatchStrategy::Balanced || config.batches.is_some() {
+        bail!("`after` prerequisites only support greedy batching with --batch-size");
+    }
+
+    let cap = config.batch_size.unwrap_or(routes.len()).max(1);
+    let mut assignment = layered_assignment(&layers(routes, &deps)?, cap);
+    let count = assignment.iter().max().map_or(0, |&m| m + 1);
+    match config.max_batches {
+        Some(max) if count > max && config.optimize => match solver(&deps, cap, max) {
+            Ok(Some(solved)) => {
+                // Renumber so unused batch indices leave no gaps
+                let mut used: Vec<usize> = solved.clone();
+                used.sort_unstable();
+                used.dedup();
+                assignment = solved.iter().map(|k| used.binary_search(k).unwrap()).collect();
+                eprintln!("[INFO] Solver fit {} layered batches into {}", count, used.len());
+            }
+            Ok(None) => bail!("No assignment of routes into {} batch(es) respects --batch-size and `after`", max),
+            Err(e) if config.require_optimize => return Err(e.context("--require-optimize: cannot fall back to layering")),
+            Err(e) => eprintln!(
+                "[WARN] {:#}; falling back to layered batching in {} batches, over --max-batches {}",
+                e, count, max
+            ),
+        },
+        Some(max) if count > max => bail!(
+            "Layering needs {} batches, over --max-batches {}; --optimize searches for a tighter assignment",
+            count,
+            max
+        ),
+        _ => {}
+    }
+
+    let mut tagged: Vec<(usize, Route)> = assignment.into_iter().zip(routes.drain(..)).collect();
+    tagged.sort_by_key(|(k, _)| *k);
+    let count = tagged.last().map_or(0, |(k, _)| k + 1);
+    routes.extend(tagged.into_iter().map(|(k, mut route)| {
+        route.batch = Some(batch_name(k));
+        route
+    }));
+    Ok((0..count.max(1)).map(batch_name).collect())
+}
+
+fn assign_unordered(routes: &mut [Route], config: &BatchConfig) -> Result<Vec<String>> {
+    if config.batch_size.is_none() && config.batches.is_none() {
+        if config.strategy == BatchStrategy::Balanced {
+            bail!("--batch-strategy balanced needs --batches or --batch-size");
+        }
+        return Ok(vec![batch_name(0)]);
+    }
+
+    let sizes = batch_sizes(routes.len(), config.strategy, config.batch_size, config.batches);
+    if sizes.is_empty() {
+        return Ok(vec![batch_name(0)]);
+    }
+    // Without prerequisites a tighter split would break --batch-size
+    if let Some(max) = config.max_batches.filter(|&max| sizes.len() > max) {
+        bail!("Batching needs {} batches, over --max-batches {}", sizes.len(), max);
+    }
+
+    let mut rest = routes;
+    for (i, &size) in sizes.iter().enumerate() {
+        let (chunk, tail) = rest.split_at_mut(size);
+        for route in chunk {
+            route.batch = Some(batch_name(i));
+        }
+        rest = tail;
+    }
+    Ok((0..sizes.len()).map(batch_name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes(n: usize) -> Vec<Route> {
+        (0..n)
+            .map(|i| Route { from: format!("src-{}", i), to: "sink".into(), ..Default::default() })
+            .collect()
+    }
+
+    fn config(strategy: BatchStrategy, batch_size: Option<usize>, batches: Option<usize>) -> BatchConfig {
+        BatchConfig { strategy, batch_size, batches, ..Default::default() }
+    }
+
+    fn sizes_of(routes: &[Route], order: &[String]) -> Vec<usize> {
+        order
+            .iter()
+            .map(|b| routes.iter().filter(|r| r.batch.as_ref() == Some(b)).count())
+            .collect()
+    }
+
+    #[test]
+    fn test_balanced_divisible() {
+        let mut rs = routes(12);
+        let order = assign_batches(&mut rs, &config(BatchStrategy::Balanced, None, Some(4))).unwrap();
+        assert_eq!(order, vec!["BATCH-1", "BATCH-2", "BATCH-3", "BATCH-4"]);
+        assert_eq!(sizes_of(&rs, &order), vec![3, 3, 3, 3]);
+        // Batches follow route order
+        assert_eq!(rs[2].batch.as_deref(), Some("BATCH-1"));
+        assert_eq!(rs[3].batch.as_deref(), Some("BATCH-2"));
+    }
+
+    #[test]
+    fn test_greedy_vs_balanced() {
+        let mut rs = routes(10);
+        let order = assign_batches(&mut rs, &config(BatchStrategy::Greedy, Some(4), None)).unwrap();
+        assert_eq!(sizes_of(&rs, &order), vec![4, 4, 2]);
+
+        let order = assign_batches(&mut rs, &config(BatchStrategy::Balanced, Some(4), None)).unwrap();
+        assert_eq!(sizes_of(&rs, &order), vec![4, 3, 3]);
+    }
+
+    #[test]
+    fn test_default_single_batch() {
+        let mut rs = routes(3);
+        let order = assign_batches(&mut rs, &config(BatchStrategy::Greedy, None, None)).unwrap();
+        assert_eq!(order, vec!["BATCH-1"]);
+        assert!(rs.iter().all(|r| r.batch.is_none()));
+        assert!(assign_batches(&mut rs, &config(BatchStrategy::Balanced, None, None)).is_err());
+    }
+
+    #[test]
+    fn test_max_batches_with_optimize() {
+        // `b` must follow `a`. Layering fills [a, c], [d], then [b]; two
+        // batches suffice if `d` waits alongside `b`.
+        let mut rs: Vec<Route> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|n| Route { from: n.to_string(), to: "sink".into(), ..Default::default() })
+            .collect();
+        rs[1].after = vec!["a->sink".into()];
+
+        let mut cfg = config(BatchStrategy::Greedy, Some(2), None);
+        let order = assign_batches(&mut rs.clone(), &cfg).unwrap();
+        assert_eq!(order.len(), 3);
+
+        cfg.max_batches = Some(2);
+        let err = assign_batches(&mut rs.clone(), &cfg).unwrap_err();
+        assert!(err.to_string().contains("Layering needs 3 batches"), "{}", err);
+
+        cfg.optimize = true;
+        let order = assign_batches(&mut rs, &cfg).unwrap();
+        assert_eq!(order, vec!["BATCH-1", "BATCH-2"]);
+        assert_eq!(sizes_of(&rs, &order), vec![2, 2]);
+        let batch_of = |from: &str| rs.iter().find(|r| r.from == from).unwrap().batch.clone().unwrap();
+        assert_eq!(batch_of("a"), "BATCH-1");
+        assert_eq!(batch_of("b"), "BATCH-2");
+
+        // One batch cannot hold `a` before `b`
+        cfg.max_batches = Some(1);
+        assert!(assign_batches(&mut rs, &cfg).is_err());
+
+        // Oversized models are refused before they are built
+        let err = solve_assignment(&vec![Vec::new(); MAX_ASSIGNMENT_VARS / 2 + 1], 1, 2).unwrap_err();
+        assert!(err.to_string().contains("over the limit"), "{}", err);
+    }
+
+    #[test]
+    fn test_solver_failure_falls_back_to_layering() {
+        let mut rs: Vec<Route> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|n| Route { from: n.to_string(), to: "sink".into(), ..Default::default() })
+            .collect();
+        rs[1].after = vec!["a->sink".into()];
+        let unavailable: AssignmentSolver = |_, _, _| bail!("Solver backend failed: not available");
+        let mut cfg = BatchConfig { batch_size: Some(2), max_batches: Some(2), optimize: true, ..Default::default() };
+
+        // Same as without --optimize, but a warning instead of an error
+        let order = assign_batches_with(&mut rs.clone(), &cfg, unavailable).unwrap();
+        assert_eq!(order.len(), 3);
+        let mut layered = rs.clone();
+        let unbounded = BatchConfig { batch_size: Some(2), ..Default::default() };
+        assert_eq!(assign_batches(&mut layered, &unbounded).unwrap(), order);
+
+        cfg.require_optimize = true;
+        let err = assign_batches_with(&mut rs.clone(), &cfg, unavailable).unwrap_err();
+        assert!(format!("{:#}", err).contains("--require-optimize: cannot fall back to layering: Solver backend failed"), "{:#}", err);
+        // A working backend satisfies --require-optimize
+        assert_eq!(assign_batches(&mut rs.clone(), &cfg).unwrap().len(), 2);
+
+        // Running out of search nodes is a backend failure too
+        let starved: AssignmentSolver = |deps, cap, max| solve_assignment_within(deps, cap, max, 1);
+        let err = assign_batches_with(&mut rs.clone(), &cfg, starved).unwrap_err();
+        assert!(format!("{:#}", err).contains("no answer within 1 search nodes"), "{:#}", err);
+        cfg.require_optimize = false;
+        assert_eq!(assign_batches_with(&mut rs, &cfg, starved).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_batch_lookup() {
+        let mut rs = routes(5);
+        let order = assign_batches(&mut rs, &config(BatchStrategy::Greedy, Some(2), None)).unwrap();
+        let identity = IdentityKeys::default();
+        let lookup = RouteBatches::new(&rs, &order, &identity);
+        assert_eq!(lookup.batch_of(&rs[1]), Some("BATCH-1"));
+        assert_eq!(lookup.batch_of(&rs[4]), Some("BATCH-3"));
+        // Untagged copies find their batch; unplanned routes have none
+        let copy = Route { batch: None, ..rs[2].clone() };
+        assert_eq!(lookup.batch_of(&copy), Some("BATCH-2"));
+        assert_eq!(lookup.batch_of(&Route { from: "src-9".into(), to: "sink".into(), ..Default::default() }), None);
+        // Content outside the identity does not matter; the identity does
+        let relabeled = Route { labels: [("env".to_string(), "dev".to_string())].into(), ..rs[2].clone() };
+        assert_eq!(lookup.batch_of(&relabeled), Some("BATCH-2"));
+        assert_eq!(lookup.batch_of(&Route { weight: Some(7.0), ..rs[2].clone() }), Some("BATCH-2"));
+        let by_env: IdentityKeys = "from,to,labels.env".parse().unwrap();
+        assert_eq!(RouteBatches::new(&rs, &order, &by_env).batch_of(&relabeled), None);
+
+        // In the historical single batch, untagged routes belong to it
+        let single = routes(2);
+        let order = vec![batch_name(0)];
+        assert_eq!(RouteBatches::new(&single, &order, &identity).batch_of(&single[1]), Some("BATCH-1"));
+    }
+
+    #[test]
+    fn test_after_rejects_unknown_and_cycles() {
+        let mut rs = routes(2);
+        rs[0].after = vec!["nowhere->sink".into()];
+        assert!(assign_batches(&mut rs, &BatchConfig::default()).is_err());
+
+        rs[0].after = vec!["src-1->sink".into()];
+        rs[1].after = vec!["src-0->sink".into()];
+        let err = assign_batches(&mut rs, &BatchConfig::default()).unwrap_err();
+        assert!(err.to_string().contains("cycle"), "{}", err);
+    }
+}
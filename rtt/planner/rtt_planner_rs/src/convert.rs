@@ -0,0 +1,205 @@
+//! Executor plan format
+//!
+//! The executor reads `add`/`remove` instead of `routes_add`/`routes_del` and
+//! applies a flat `order` of steps, `remove:<from>-><to>` or
+//! `add:<from>-><to>`, rather than walking batches. Batch by batch in plan
+//! order, removals come before additions, as in `simulate`.
+//!
+//! Conversion keeps the route lists, batch names, annotations and
+//! signatures intact, so converting back reproduces the plan exactly and
+//! `plan_id` still matches. Both directions check the id.
+//!
+//! `rtt-planner convert --to executor <plan.json> [out.json]`
+//! `rtt-planner convert --from executor <executor.json> [out.json]`
+
+use crate::verify::check_plan_id;
+use crate::{validate_path, Plan, Route, Sign};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+
+#[derive(Serialize, Deserialize)]
+pub struct ExecutorPlan {
+    pub plan_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub add: Vec<Route>,
+    pub remove: Vec<Route>,
+    /// Steps in apply order.
+    pub order: Vec<String>,
+    /// The plan's batch order, kept so the plan can be restored.
+    pub batches: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub batch_annotations: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub batch_notes: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifests_digest: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sign: Option<Sign>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<Sign>,
+}
+
+/// Flat apply order of `add` and `remove` under `batches`. Untagged routes
+/// belong to the first batch.
+fn steps(add: &[Route], remove: &[Route], batches: &[String]) -> Result<Vec<String>> {
+    let batch_of = |r: &Route| r.batch.clone().or_else(|| batches.first().cloned());
+    for route in add.iter().chain(remove) {
+        match batch_of(route) {
+            Some(b) if batches.contains(&b) => {}
+            _ => bail!("Route {} -> {} is not in any batch of the plan order", route.from, route.to),
+        }
+    }
+    let mut order = Vec::with_capacity(add.len() + remove.len());
+    for batch in batches {
+        for (op, routes) in [("remove", remove), ("add", add)] {
+            for route in routes.iter().filter(|r| batch_of(r).as_ref() == Some(batch)) {
+                order.push(format!("{}:{}->{}", op, route.from, route.to));
+            }
+        }
+    }
+    Ok(order)
+}
+
+pub fn to_executor(plan: Plan) -> Result<ExecutorPlan> {
+    check_plan_id(&plan)?;
+    Ok(ExecutorPlan {
+        order: steps(&plan.routes_add, &plan.routes_del, &plan.order)?,
+        plan_id: plan.plan_id,
+        name: plan.name,
+        add: plan.routes_add,
+        remove: plan.routes_del,
+        batches: plan.order,
+        annotations: plan.annotations,
+        batch_annotations: plan.batch_annotations,
+        batch_notes: plan.batch_notes,
+        valid_until: plan.valid_until,
+        manifests_digest: plan.manifests_digest,
+        depends_on: plan.depends_on,
+        sign: plan.sign,
+        signatures: plan.signatures,
+    })
+}
+
+pub fn from_executor(exec: ExecutorPlan) -> Result<Plan> {
+    if steps(&exec.add, &exec.remove, &exec.batches)? != exec.order {
+        bail!("Executor order does not match its routes and batches");
+    }
+    let plan = Plan {
+        plan_id: exec.plan_id,
+        name: exec.name,
+        routes_add: exec.add,
+        routes_del: exec.remove,
+        order: exec.batches,
+        annotations: exec.annotations,
+        batch_annotations: exec.batch_annotations,
+        batch_notes: exec.batch_notes,
+        valid_until: exec.valid_until,
+        manifests_digest: exec.manifests_digest,
+        depends_on: exec.depends_on,
+        sign: exec.sign,
+        signatures: exec.signatures,
+    };
+    check_plan_id(&plan)?;
+    Ok(plan)
+}
+
+pub fn cmd_convert(args: &[String]) -> Result<()> {
+    let (to, input, output) = match args {
+        [flag, format, input, rest @ ..] if (flag == "--to" || flag == "--from") && format == "executor" && rest.len() <= 1 => {
+            (flag == "--to", input, rest.first())
+        }
+        _ => {
+            eprintln!("usage: rtt-planner convert --to executor <plan.json> [out.json]");
+            eprintln!("       rtt-planner convert --from executor <executor.json> [out.json]");
+            bail!("Invalid arguments");
+        }
+    };
+    let path = validate_path(input, "input file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read input file: {:?}", path))?;
+    let json = if to {
+        let plan: Plan = serde_json::from_str(&content).with_context(|| "Failed to parse plan JSON")?;
+        serde_json::to_vec_pretty(&to_executor(plan)?)?
+    } else {
+        let exec: ExecutorPlan = serde_json::from_str(&content).with_context(|| "Failed to parse executor plan JSON")?;
+        serde_json::to_vec_pretty(&from_executor(exec)?)?
+    };
+
+    match output {
+        Some(out) => {
+            let out = validate_path(out, "output file")?;
+            fs::write(&out, json).with_context(|| format!("Failed to write output file: {:?}", out))?;
+            eprintln!("[OK] Converted {:?} -> {:?}", path, out);
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&json)?;
+            stdout.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_plan_id;
+
+    fn route(from: &str, to: &str, batch: &str) -> Route {
+        Route { from: from.into(), to: to.into(), batch: Some(batch.into()), ..Default::default() }
+    }
+
+    fn sample() -> Plan {
+        let mut plan = Plan {
+            routes_add: vec![route("a", "b", "BATCH-1"), route("c", "d", "BATCH-2"), route("e", "f", "BATCH-2")],
+            routes_del: vec![route("old", "b", "BATCH-2")],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            annotations: BTreeMap::from([("ticket".into(), "CHG-7".into())]),
+            valid_until: Some(1_700_000_000),
+            manifests_digest: Some("sha256-00".into()),
+            depends_on: vec!["sha256-11".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(Sign { alg: "ed25519".into(), key_id: "dev".into(), sig: "c2ln".into(), signed_at: Some(1) });
+        plan
+    }
+
+    #[test]
+    fn test_executor_round_trip() {
+        let original = serde_json::to_value(sample()).unwrap();
+        let exec = to_executor(sample()).unwrap();
+        assert_eq!(exec.order, vec!["add:a->b", "remove:old->b", "add:c->d", "add:e->f"]);
+
+        let exec_json = serde_json::to_value(&exec).unwrap();
+        let back = from_executor(exec).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), original);
+
+        // And executor -> plan -> executor
+        let exec: ExecutorPlan = serde_json::from_value(exec_json.clone()).unwrap();
+        let again = to_executor(from_executor(exec).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&again).unwrap(), exec_json);
+    }
+
+    #[test]
+    fn test_executor_edits_are_rejected() {
+        let mut exec = to_executor(sample()).unwrap();
+        exec.order.swap(0, 1);
+        assert!(from_executor(exec).is_err());
+
+        let mut exec = to_executor(sample()).unwrap();
+        exec.add[0].to = "z".into();
+        exec.order = steps(&exec.add, &exec.remove, &exec.batches).unwrap();
+        let err = from_executor(exec).err().unwrap().to_string();
+        assert!(err.contains("plan_id mismatch"), "{}", err);
+    }
+}
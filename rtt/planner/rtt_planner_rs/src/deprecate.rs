@@ -0,0 +1,130 @@
+//! Endpoint deprecations
+//!
+//! `--deprecations <file>` lists endpoints being phased out. Planned routes
+//! touching one log a warning with its message; once the optional
+//! `removal_date` (`YYYY-MM-DD`, UTC) has passed, they fail planning:
+//!
+//! ```json
+//! {"deprecations": [{"endpoint": "db-old", "message": "use db-new", "removal_date": "2026-06-30"}]}
+//! ```
+//!
+//! A bare JSON array of entries is accepted as well.
+
+use crate::Route;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Deprecation {
+    pub endpoint: String,
+    pub message: String,
+    #[serde(default)]
+    pub removal_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DeprecationFile {
+    Entries { deprecations: Vec<Deprecation> },
+    List(Vec<Deprecation>),
+}
+
+/// Days since 1970-01-01 for a `YYYY-MM-DD` date.
+pub fn parse_date(s: &str) -> Result<i64> {
+    let parts: Vec<i64> = s
+        .split('-')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid date (expected YYYY-MM-DD): {}", s))?;
+    let [y, m, d] = parts[..] else {
+        bail!("Invalid date (expected YYYY-MM-DD): {}", s);
+    };
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) {
+        bail!("Invalid date (expected YYYY-MM-DD): {}", s);
+    }
+    // Days-from-civil over 400-year eras starting in March
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok(era * 146_097 + doe - 719_468)
+}
+
+pub fn load_deprecations(path: &Path) -> Result<Vec<Deprecation>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read deprecations file: {:?}", path))?;
+    let file: DeprecationFile = serde_json::from_str(&content)
+        .with_context(|| "Failed to parse deprecations JSON")?;
+    let entries = match file {
+        DeprecationFile::Entries { deprecations } | DeprecationFile::List(deprecations) => deprecations,
+    };
+    for entry in &entries {
+        if let Some(date) = &entry.removal_date {
+            parse_date(date).with_context(|| format!("Deprecation of {}", entry.endpoint))?;
+        }
+    }
+    Ok(entries)
+}
+
+/// Warn about routes on deprecated endpoints and fail if any endpoint is past
+/// its removal date on day `today` (days since the epoch). Returns the
+/// number of routes warned about.
+pub fn check_deprecations(routes: &[Route], deprecations: &[Deprecation], today: i64) -> Result<usize> {
+    let mut warned = 0;
+    let mut removed = Vec::new();
+    for route in routes {
+        for dep in deprecations {
+            if route.from != dep.endpoint && route.to != dep.endpoint {
+                continue;
+            }
+            let date = dep.removal_date.as_deref();
+            if date.is_some_and(|d| parse_date(d).is_ok_and(|d| today > d)) {
+                removed.push(format!("{} -> {} ({} was removed {})", route.from, route.to, dep.endpoint, date.unwrap()));
+                continue;
+            }
+            let removal = date.map(|d| format!(", removal {}", d)).unwrap_or_default();
+            eprintln!(
+                "[WARN] Route {} -> {} uses deprecated endpoint {}: {}{}",
+                route.from, route.to, dep.endpoint, dep.message, removal
+            );
+            warned += 1;
+        }
+    }
+    if !removed.is_empty() {
+        bail!("{} route(s) use endpoints past their removal date: {}", removed.len(), removed.join("; "));
+    }
+    Ok(warned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_deprecation_warns_then_errors() {
+        let deps: Vec<Deprecation> = serde_json::from_str(
+            r#"[{"endpoint": "db-old", "message": "use db-new", "removal_date": "2026-06-30"},
+                {"endpoint": "cache-v1", "message": "use cache-v2"}]"#,
+        )
+        .unwrap();
+        let routes = vec![route("api", "db-old"), route("api", "db-new"), route("cache-v1", "api")];
+
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2000-03-01").unwrap(), 11_017);
+        assert!(parse_date("2026-13-01").is_err() && parse_date("yesterday").is_err());
+
+        let before = parse_date("2026-06-30").unwrap();
+        assert_eq!(check_deprecations(&routes, &deps, before).unwrap(), 2);
+
+        let err = check_deprecations(&routes, &deps, before + 1).unwrap_err().to_string();
+        assert!(err.contains("api -> db-old (db-old was removed 2026-06-30)"), "{}", err);
+        assert!(!err.contains("cache-v1"), "{}", err);
+    }
+}
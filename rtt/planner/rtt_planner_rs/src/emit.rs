@@ -0,0 +1,97 @@
+//! Per-batch plan files
+//!
+//! `--emit-per-batch <dir>` writes, next to the plan, one routes file per
+//! batch (`<dir>/BATCH-1.json`, ...) holding that batch's `routes_add`, and
+//! `<dir>/index.json` listing the batches in plan order with each file's
+//! hash, for executors that apply one file per batch:
+//!
+//! ```json
+//! {"plan_id": "sha256-...", "batches": [{"batch": "BATCH-1", "file": "BATCH-1.json", "sha256": "sha256-..."}]}
+//! ```
+//!
+//! Routes without a batch tag belong to the first batch, as elsewhere. The
+//! files together hold exactly the plan's `routes_add`.
+
+use crate::{hash_bytes, Plan, Route, Routes};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchFile {
+    pub batch: String,
+    pub file: String,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchIndex {
+    pub plan_id: String,
+    pub batches: Vec<BatchFile>,
+}
+
+/// Write the batch files and index for `plan` into `dir`.
+pub fn write_per_batch(plan: &Plan, dir: &Path) -> Result<BatchIndex> {
+    let batch_of = |r: &Route| r.batch.clone().or_else(|| plan.order.first().cloned());
+    if let Some(route) = plan.routes_add.iter().find(|r| !batch_of(r).is_some_and(|b| plan.order.contains(&b))) {
+        bail!("Route {} -> {} is not in any batch of the plan order", route.from, route.to);
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create batch directory: {:?}", dir))?;
+
+    let mut index = BatchIndex { plan_id: plan.plan_id.clone(), batches: Vec::with_capacity(plan.order.len()) };
+    for batch in &plan.order {
+        let routes = plan.routes_add.iter().filter(|r| batch_of(r).as_ref() == Some(batch)).cloned().collect();
+        let json = serde_json::to_vec_pretty(&Routes { routes })?;
+        let file = format!("{}.json", batch);
+        fs::write(dir.join(&file), &json).with_context(|| format!("Failed to write batch file: {:?}", dir.join(&file)))?;
+        index.batches.push(BatchFile { batch: batch.clone(), file, sha256: hash_bytes(&json) });
+    }
+    fs::write(dir.join("index.json"), serde_json::to_vec_pretty(&index)?)
+        .with_context(|| format!("Failed to write batch index: {:?}", dir.join("index.json")))?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_plan_id;
+
+    #[test]
+    fn test_per_batch_files_reassemble() {
+        let route = |from: &str, batch: Option<&str>| Route {
+            from: from.into(),
+            to: "sink".into(),
+            batch: batch.map(Into::into),
+            ..Default::default()
+        };
+        let mut plan = Plan {
+            routes_add: vec![route("a", None), route("b", Some("BATCH-2")), route("c", Some("BATCH-1")), route("d", Some("BATCH-2"))],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("rtt-emit-{}", std::process::id()));
+        write_per_batch(&plan, &dir).unwrap();
+
+        let index: BatchIndex = serde_json::from_slice(&fs::read(dir.join("index.json")).unwrap()).unwrap();
+        assert_eq!(index.plan_id, plan.plan_id);
+        assert_eq!(index.batches.iter().map(|b| b.batch.as_str()).collect::<Vec<_>>(), plan.order);
+        let mut reassembled = Vec::new();
+        for entry in &index.batches {
+            let bytes = fs::read(dir.join(&entry.file)).unwrap();
+            assert_eq!(hash_bytes(&bytes), entry.sha256);
+            reassembled.extend(serde_json::from_slice::<Routes>(&bytes).unwrap().routes);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let key = |r: &Route| format!("{}->{}", r.from, r.to);
+        let mut expected: Vec<_> = plan.routes_add.iter().map(key).collect();
+        let mut got: Vec<_> = reassembled.iter().map(key).collect();
+        assert_eq!(got, vec!["a->sink", "c->sink", "b->sink", "d->sink"]);
+        expected.sort();
+        got.sort();
+        assert_eq!(got, expected);
+    }
+}
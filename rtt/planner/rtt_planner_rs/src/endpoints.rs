@@ -0,0 +1,140 @@
+//! Endpoint census
+//!
+//! `rtt-planner endpoints <routes.json> [--output-format text|json]` lists
+//! every distinct endpoint of the routes, after range and bidirectional
+//! expansion, with how many routes leave (`out`) and enter (`in`) it and its
+//! role: a `source` only has routes leaving it, a `sink` only routes
+//! entering it, and a `transit` endpoint has both. No plan is built, so
+//! duplicate routes count once per occurrence.
+
+use crate::{expand, input, validate_path, Route};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Source,
+    Sink,
+    Transit,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Source => "source",
+            Self::Sink => "sink",
+            Self::Transit => "transit",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Endpoint {
+    pub endpoint: String,
+    pub out: usize,
+    #[serde(rename = "in")]
+    pub inbound: usize,
+    pub role: Role,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unknown output format: {} (expected text or json)", s),
+        }
+    }
+}
+
+/// Endpoints of `routes` by name, with their route counts and roles.
+pub fn census(routes: &[Route]) -> Vec<Endpoint> {
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for route in routes {
+        counts.entry(&route.from).or_default().0 += 1;
+        counts.entry(&route.to).or_default().1 += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(endpoint, (out, inbound))| {
+            let role = match (out, inbound) {
+                (_, 0) => Role::Source,
+                (0, _) => Role::Sink,
+                _ => Role::Transit,
+            };
+            Endpoint { endpoint: endpoint.to_string(), out, inbound, role }
+        })
+        .collect()
+}
+
+pub fn cmd_endpoints(args: &[String]) -> Result<()> {
+    let mut format = OutputFormat::default();
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--output-format" => format = it.next().context("--output-format requires a value")?.parse()?,
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg),
+        }
+    }
+    let [routes_arg] = positional.as_slice() else {
+        eprintln!("usage: rtt-planner endpoints <routes.json> [--output-format text|json]");
+        bail!("Invalid arguments");
+    };
+    let path = validate_path(routes_arg, "routes file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read routes file: {:?}", path))?;
+    let routes = input::parse_routes(&content, input::InputFormat::Json)?.routes;
+    let routes = expand::expand_routes(routes, expand::MAX_EXPANDED_ROUTES)?;
+    let routes = expand::expand_bidirectional(routes, expand::MAX_EXPANDED_ROUTES, &mut Vec::new())?;
+
+    let endpoints = census(&routes);
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "endpoints": endpoints }))?),
+        OutputFormat::Text => {
+            for e in &endpoints {
+                println!("{:<8} out {:>4}  in {:>4}  {}", e.role.as_str(), e.out, e.inbound, e.endpoint);
+            }
+        }
+    }
+    eprintln!("[OK] {} endpoint(s) across {} route(s)", endpoints.len(), routes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_roles() {
+        let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+        // src feeds hub twice, hub feeds sink
+        let routes = vec![route("src", "hub"), route("hub", "sink"), route("src", "hub")];
+        let got: Vec<_> = census(&routes).into_iter().map(|e| (e.endpoint, e.out, e.inbound, e.role)).collect();
+        assert_eq!(
+            got,
+            vec![
+                ("hub".to_string(), 1, 2, Role::Transit),
+                ("sink".to_string(), 0, 1, Role::Sink),
+                ("src".to_string(), 2, 0, Role::Source),
+            ]
+        );
+
+        let json = serde_json::to_value(census(&routes)).unwrap();
+        assert_eq!(json[0], serde_json::json!({"endpoint": "hub", "out": 1, "in": 2, "role": "transit"}));
+        assert!(census(&[]).is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+//! Compact range syntax for route endpoints
+//!
+//! An endpoint such as `node-[1..3]` expands to `node-1`, `node-2`, `node-3`.
+//! Several groups may appear in one endpoint (`rack-[1..2]-node-[1..4]`), and a
+//! route expands to the cartesian product of its expanded `from` and `to`.
+//! A start bound written with leading zeros (`[01..10]`) pads every value to
+//! the same width. Brackets that do not hold `<start>..<end>` are left as-is.
+//!
+//! A route marked `bidirectional` also plans its reverse. When the routes
+//! file lists that reverse explicitly, an identical copy is collapsed
+//! silently, while one with different labels, requirements or weight is a
+//! conflict: the explicit route wins and the generated one is reported.
+
+use crate::prune::{metadata, DropReason, DroppedRoute};
+use crate::Route;
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+/// Upper bound on the number of routes a single routes file may expand to.
+pub const MAX_EXPANDED_ROUTES: usize = 10_000;
+
+struct RangeGroup {
+    start: u64,
+    end: u64,
+    width: usize,
+}
+
+enum Part {
+    Literal(String),
+    Range(RangeGroup),
+}
+
+fn parse_range(inner: &str, endpoint: &str) -> Result<Option<RangeGroup>> {
+    let Some((lo, hi)) = inner.split_once("..") else {
+        return Ok(None);
+    };
+    if lo.is_empty() || hi.is_empty() {
+        return Ok(None);
+    }
+    if !lo.bytes().all(|b| b.is_ascii_digit()) || !hi.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(None);
+    }
+    let width = if lo.len() > 1 && lo.starts_with('0') { lo.len() } else { 0 };
+    let (Ok(start), Ok(end)) = (lo.parse(), hi.parse()) else {
+        bail!("Range bound out of range in endpoint: {}", endpoint);
+    };
+    Ok(Some(RangeGroup { start, end, width }))
+}
+
+fn parse_endpoint(endpoint: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = endpoint;
+
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find(']').map(|c| open + c) else {
+            break;
+        };
+        match parse_range(&rest[open + 1..close], endpoint)? {
+            Some(group) => {
+                if group.start > group.end {
+                    bail!("Descending range in endpoint: {}", endpoint);
+                }
+                literal.push_str(&rest[..open]);
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+                parts.push(Part::Range(group));
+            }
+            None => literal.push_str(&rest[..=close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    literal.push_str(rest);
+    parts.push(Part::Literal(literal));
+    Ok(parts)
+}
+
+/// Number of endpoints `parts` expands to, or `None` on overflow.
+fn expansion_len(parts: &[Part]) -> Option<usize> {
+    parts.iter().try_fold(1usize, |acc, p| match p {
+        Part::Literal(_) => Some(acc),
+        Part::Range(g) => {
+            let n = usize::try_from(g.end - g.start).ok()?.checked_add(1)?;
+            acc.checked_mul(n)
+        }
+    })
+}
+
+fn render(parts: &[Part]) -> Vec<String> {
+    let mut out = vec![String::new()];
+    for part in parts {
+        out = match part {
+            Part::Literal(s) => out.into_iter().map(|prefix| prefix + s).collect(),
+            Part::Range(g) => out
+                .iter()
+                .flat_map(|prefix| {
+                    (g.start..=g.end).map(move |n| format!("{}{:0width$}", prefix, n, width = g.width))
+                })
+                .collect(),
+        };
+    }
+    out
+}
+
+/// Expand every route, failing before materialization if the result would
+/// exceed `cap` routes.
+pub fn expand_routes(routes: Vec<Route>, cap: usize) -> Result<Vec<Route>> {
+    let mut parsed = Vec::with_capacity(routes.len());
+    let mut total = 0usize;
+
+    for route in routes {
+        let from = parse_endpoint(&route.from)?;
+        let to = parse_endpoint(&route.to)?;
+        let n = expansion_len(&from)
+            .zip(expansion_len(&to))
+            .and_then(|(f, t)| f.checked_mul(t))
+            .and_then(|n| total.checked_add(n));
+        match n {
+            Some(n) if n <= cap => total = n,
+            _ => bail!(
+                "Route expansion exceeds limit of {} routes (at {} -> {})",
+                cap,
+                route.from,
+                route.to
+            ),
+        }
+        parsed.push((route, from, to));
+    }
+
+    let mut out = Vec::with_capacity(total);
+    for (route, from, to) in parsed {
+        let tos = render(&to);
+        for f in render(&from) {
+            for t in &tos {
+                let mut r = route.clone();
+                r.from = f.clone();
+                r.to = t.clone();
+                out.push(r);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Add the reverse of every `bidirectional` route, after its forward route.
+/// Generated reverses that conflict with an explicit route are moved to
+/// `dropped`. Output routes are plain directed routes.
+pub fn expand_bidirectional(routes: Vec<Route>, cap: usize, dropped: &mut Vec<DroppedRoute>) -> Result<Vec<Route>> {
+    let total = routes.len() + routes.iter().filter(|r| r.bidirectional).count();
+    if total > cap {
+        bail!("Bidirectional routes expand to {} routes, over the limit of {}", total, cap);
+    }
+
+    // Every route as listed is explicit, including the forward half of a
+    // bidirectional route
+    let mut explicit: HashMap<(&str, &str), &Route> = HashMap::new();
+    for route in &routes {
+        explicit.entry((&route.from, &route.to)).or_insert(route);
+    }
+
+    let mut reverses = Vec::new();
+    let mut conflicts = Vec::new();
+    for (i, route) in routes.iter().enumerate().filter(|(_, r)| r.bidirectional) {
+        let reverse = Route {
+            from: route.to.clone(),
+            to: route.from.clone(),
+            bidirectional: false,
+            ..route.clone()
+        };
+        match explicit.get(&(reverse.from.as_str(), reverse.to.as_str())) {
+            Some(existing) if metadata(existing) == metadata(&reverse) => {}
+            Some(_) => conflicts.push(reverse),
+            None => reverses.push((i, reverse)),
+        }
+    }
+    for route in &conflicts {
+        eprintln!(
+            "[WARN] Bidirectional reverse {} -> {} conflicts with an explicit route; keeping the explicit one",
+            route.from, route.to
+        );
+    }
+    dropped.extend(conflicts.into_iter().map(|route| DroppedRoute { route, reason: DropReason::Conflict }));
+
+    let mut reverses = reverses.into_iter().peekable();
+    let mut out = Vec::with_capacity(total);
+    for (i, route) in routes.into_iter().enumerate() {
+        out.push(Route { bidirectional: false, ..route });
+        if let Some((_, reverse)) = reverses.next_if(|(j, _)| *j == i) {
+            out.push(reverse);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), ..Default::default() }
+    }
+
+    fn expand_endpoint(endpoint: &str) -> Vec<String> {
+        render(&parse_endpoint(endpoint).unwrap())
+    }
+
+    #[test]
+    fn test_expand_cartesian() {
+        let out = expand_routes(vec![route("node-[1..3]", "gw-[1..2]")], MAX_EXPANDED_ROUTES).unwrap();
+        assert_eq!(out.len(), 6);
+        assert_eq!(out[3].from, "node-2");
+        assert_eq!(out[3].to, "gw-2");
+    }
+
+    #[test]
+    fn test_expand_literal_and_padding() {
+        assert_eq!(expand_endpoint("rtt://core/api"), vec!["rtt://core/api"]);
+        assert_eq!(expand_endpoint("svc[prod]"), vec!["svc[prod]"]);
+        assert_eq!(expand_endpoint("n[08..10]"), vec!["n08", "n09", "n10"]);
+        assert_eq!(expand_endpoint("r[1..2]-n[1..2]").len(), 4);
+    }
+
+    #[test]
+    fn test_expand_cap() {
+        let err = expand_routes(vec![route("a-[1..200]", "b-[1..100]")], MAX_EXPANDED_ROUTES).unwrap_err();
+        assert!(err.to_string().contains("exceeds limit"));
+        assert!(expand_routes(vec![route("a-[3..1]", "b")], MAX_EXPANDED_ROUTES).is_err());
+        let err = expand_routes(vec![route("node-[1..99999999999999999999]", "b")], MAX_EXPANDED_ROUTES).unwrap_err();
+        assert!(err.to_string().contains("node-[1..99999999999999999999]"), "{}", err);
+    }
+
+    fn bidi(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), bidirectional: true, ..Default::default() }
+    }
+
+    fn plain(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_bidirectional_collapses_exact_reverse() {
+        let mut dropped = Vec::new();
+        let routes = expand_bidirectional(vec![bidi("a", "b"), plain("b", "a"), bidi("c", "d")], 100, &mut dropped).unwrap();
+        let pairs: Vec<_> = routes.iter().map(|r| format!("{}->{}", r.from, r.to)).collect();
+        assert_eq!(pairs, vec!["a->b", "b->a", "c->d", "d->c"]);
+        assert!(routes.iter().all(|r| !r.bidirectional));
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn test_bidirectional_metadata_conflict() {
+        let mut explicit = plain("b", "a");
+        explicit.labels.insert("env".into(), "prod".into());
+        let mut dropped = Vec::new();
+        let routes = expand_bidirectional(vec![bidi("a", "b"), explicit], 100, &mut dropped).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[1].labels["env"], "prod");
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].reason, DropReason::Conflict);
+        assert!(dropped[0].route.labels.is_empty());
+
+        assert!(expand_bidirectional(vec![bidi("a", "b")], 1, &mut dropped).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Route identity
+//!
+//! Which fields make two routes "the same route". The default is
+//! `from,to`; `--identity-keys from,to,labels.env` also distinguishes routes
+//! whose `env` label differs. A label missing from a route is distinct from
+//! every present value, including the empty string.
+//!
+//! A route's `route_id` is derived from its identity alone, so the same
+//! route gets the same id on every run and logs, reports and solver models
+//! can be correlated by it. It is `r-` and the first 16 hex digits of the
+//! SHA-256 of the identity as compact JSON with sorted keys, e.g.
+//! `{"from":"a","to":"b"}`; it changes only if the identity keys do.
+
+use crate::{hash_bytes, Route};
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyField {
+    From,
+    To,
+    Label(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityKeys(Vec<KeyField>);
+
+impl Default for IdentityKeys {
+    fn default() -> Self {
+        Self(vec![KeyField::From, KeyField::To])
+    }
+}
+
+impl FromStr for IdentityKeys {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut fields = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let field = match part {
+                "from" => KeyField::From,
+                "to" => KeyField::To,
+                _ => match part.strip_prefix("labels.") {
+                    Some(label) if !label.is_empty() => KeyField::Label(label.into()),
+                    _ => bail!("Unknown identity key: {:?} (expected from, to or labels.<name>)", part),
+                },
+            };
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        Ok(Self(fields))
+    }
+}
+
+impl KeyField {
+    fn name(&self) -> String {
+        match self {
+            Self::From => "from".into(),
+            Self::To => "to".into(),
+            Self::Label(name) => format!("labels.{}", name),
+        }
+    }
+}
+
+impl IdentityKeys {
+    /// The identity of `route` under these keys.
+    pub fn key(&self, route: &Route) -> Vec<Option<String>> {
+        self.0
+            .iter()
+            .map(|field| match field {
+                KeyField::From => Some(route.from.clone()),
+                KeyField::To => Some(route.to.clone()),
+                KeyField::Label(name) => route.labels.get(name).cloned(),
+            })
+            .collect()
+    }
+
+    /// The stable content-derived id of `route`; see the module docs.
+    pub fn route_id(&self, route: &Route) -> String {
+        let identity: BTreeMap<String, Option<String>> =
+            self.0.iter().map(KeyField::name).zip(self.key(route)).collect();
+        let json = serde_json::to_vec(&identity).expect("string map serializes");
+        format!("r-{}", &hash_bytes(&json)["sha256-".len()..][..16])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_key() {
+        let keys: IdentityKeys = "from, to,labels.env".parse().unwrap();
+        let route = Route {
+            from: "a".into(),
+            to: "b".into(),
+            labels: BTreeMap::from([("env".into(), "prod".into())]),
+            ..Default::default()
+        };
+        assert_eq!(keys.key(&route), vec![Some("a".into()), Some("b".into()), Some("prod".into())]);
+        assert_eq!(IdentityKeys::default().key(&route).len(), 2);
+
+        assert!("from,weight".parse::<IdentityKeys>().is_err());
+        assert!("labels.".parse::<IdentityKeys>().is_err());
+    }
+
+    #[test]
+    fn test_route_id_is_stable() {
+        let route = |from: &str, env: &str| Route {
+            from: from.into(),
+            to: "b".into(),
+            labels: BTreeMap::from([("env".into(), env.into())]),
+            ..Default::default()
+        };
+        let keys = IdentityKeys::default();
+        assert_eq!(keys.route_id(&route("a", "prod")), keys.route_id(&route("a", "prod")));
+        // Pinned: ids must not drift between releases
+        assert_eq!(keys.route_id(&route("a", "prod")), "r-806b48ee2b62d78e");
+        // Fields outside the identity do not matter
+        let mut weighted = route("a", "dev");
+        weighted.weight = Some(3.0);
+        assert_eq!(keys.route_id(&weighted), keys.route_id(&route("a", "prod")));
+        assert_ne!(keys.route_id(&route("c", "prod")), keys.route_id(&route("a", "prod")));
+
+        let by_env: IdentityKeys = "labels.env,to,from".parse().unwrap();
+        assert_ne!(by_env.route_id(&route("a", "prod")), by_env.route_id(&route("a", "dev")));
+        // Key order on the command line does not change ids
+        let reordered: IdentityKeys = "from,to,labels.env".parse().unwrap();
+        assert_eq!(by_env.route_id(&route("a", "dev")), reordered.route_id(&route("a", "dev")));
+    }
+}
@@ -0,0 +1,129 @@
+//! Routes input formats
+//!
+//! Routes arrive as a `{"routes": [...]}` document by default. With
+//! `--from-stdin-format ndjson` each non-blank line is one route instead,
+//! which suits pipelines that emit routes as they go.
+//!
+//! Either way a route may carry a `checksum`: the SHA-256 of its own
+//! compact, key-sorted JSON without `checksum`, as `route_checksum`
+//! computes it. A route whose content no longer matches is rejected, naming
+//! the route. Checksums guard the source only and never reach the plan.
+
+use crate::{hash_bytes, Route, Routes};
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    #[default]
+    Json,
+    Ndjson,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => bail!("Unknown input format: {} (expected json or ndjson)", s),
+        }
+    }
+}
+
+/// The checksum a route must declare for its current content.
+pub fn route_checksum(route: &Route) -> Result<String> {
+    let mut value = serde_json::to_value(route)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("checksum");
+    }
+    Ok(hash_bytes(&serde_json::to_vec(&value)?))
+}
+
+/// Reject routes whose declared checksum does not match, then drop the
+/// checksums.
+fn check_checksums(routes: &mut [Route]) -> Result<()> {
+    for (i, route) in routes.iter_mut().enumerate() {
+        if let Some(declared) = route.checksum.take() {
+            let actual = route_checksum(route)?;
+            if declared != actual {
+                bail!(
+                    "Route {} ({} -> {}) does not match its checksum: declared {}, content hashes to {}",
+                    i + 1, route.from, route.to, declared, actual
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_routes(content: &str, format: InputFormat) -> Result<Routes> {
+    let mut routes = parse_unchecked(content, format)?;
+    check_checksums(&mut routes.routes)?;
+    Ok(routes)
+}
+
+fn parse_unchecked(content: &str, format: InputFormat) -> Result<Routes> {
+    match format {
+        InputFormat::Json => serde_json::from_str(content).with_context(|| "Failed to parse routes JSON"),
+        InputFormat::Ndjson => {
+            let mut routes = Vec::new();
+            for (i, line) in content.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let route: Route = serde_json::from_str(line)
+                    .with_context(|| format!("Failed to parse route on line {}", i + 1))?;
+                routes.push(route);
+            }
+            Ok(Routes { routes })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ndjson_matches_json() {
+        let json = r#"{"routes": [
+            {"from": "a", "to": "b", "labels": {"env": "prod"}},
+            {"from": "b", "to": "c", "weight": 2.5}
+        ]}"#;
+        let ndjson = "{\"from\": \"a\", \"to\": \"b\", \"labels\": {\"env\": \"prod\"}}\n\n  \n{\"from\": \"b\", \"to\": \"c\", \"weight\": 2.5}\n";
+
+        let from_json = parse_routes(json, InputFormat::Json).unwrap();
+        let from_ndjson = parse_routes(ndjson, InputFormat::Ndjson).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_ndjson).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+
+        let Err(err) = parse_routes("{\"from\": \"a\", \"to\": \"b\"}\n\n{\"from\": \"a\"\n", InputFormat::Ndjson) else {
+            panic!("malformed line accepted");
+        };
+        assert_eq!(err.to_string(), "Failed to parse route on line 3");
+    }
+
+    #[test]
+    fn test_route_checksums() {
+        let route = Route { from: "a".into(), to: "b".into(), weight: Some(2.0), ..Default::default() };
+        let sum = route_checksum(&route).unwrap();
+        assert_eq!(sum, hash_bytes(br#"{"from":"a","to":"b","weight":2.0}"#));
+
+        // Key order in the source does not matter; the checksum is dropped
+        let good = format!(r#"{{"routes": [{{"weight": 2.0, "to": "b", "from": "a", "checksum": "{}"}}, {{"from": "b", "to": "c"}}]}}"#, sum);
+        let routes = parse_routes(&good, InputFormat::Json).unwrap().routes;
+        assert!(routes.iter().all(|r| r.checksum.is_none()));
+
+        // Tampered content is rejected by position and endpoints
+        let bad = format!("{{\"from\": \"b\", \"to\": \"c\"}}\n{{\"from\": \"a\", \"to\": \"b\", \"weight\": 3.0, \"checksum\": \"{}\"}}\n", sum);
+        let Err(err) = parse_routes(&bad, InputFormat::Ndjson) else {
+            panic!("tampered route accepted");
+        };
+        let err = err.to_string();
+        assert!(err.starts_with("Route 2 (a -> b) does not match its checksum"), "{}", err);
+    }
+}
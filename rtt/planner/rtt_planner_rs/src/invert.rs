@@ -0,0 +1,82 @@
+//! Plan inversion
+//!
+//! `rtt-planner invert <plan.json> <out.json>` writes the plan that undoes
+//! a plan: its adds become deletes and its deletes adds, and batches run in
+//! reverse order, so the last batch applied is the first rolled back.
+//! Annotations, notes and expiry carry over. The inverse is a new plan, with
+//! its own `plan_id` and no signatures; it must be signed like any other.
+//! Inverting twice gives back the original content and `plan_id`.
+//!
+//! `--with-rollback <file>` on the main planner command writes the same
+//! inverse alongside a freshly generated plan.
+
+use crate::verify::load_plan;
+use crate::{compute_plan_id, plan_name, validate_path, Plan, Route};
+use anyhow::{bail, Context, Result};
+use std::fs;
+
+pub fn invert_plan(plan: &Plan) -> Result<Plan> {
+    // Untagged routes belong to the first batch, which reversal would change
+    let untagged = |r: &&Route| r.batch.is_none();
+    if plan.order.len() > 1 {
+        if let Some(route) = plan.routes_add.iter().chain(&plan.routes_del).find(untagged) {
+            bail!("Cannot invert: route {} -> {} has no batch in a multi-batch plan", route.from, route.to);
+        }
+    }
+    let mut inverse = Plan {
+        plan_id: String::new(),
+        routes_add: plan.routes_del.clone(),
+        routes_del: plan.routes_add.clone(),
+        order: plan.order.iter().rev().cloned().collect(),
+        annotations: plan.annotations.clone(),
+        batch_annotations: plan.batch_annotations.clone(),
+        batch_notes: plan.batch_notes.clone(),
+        valid_until: plan.valid_until,
+        manifests_digest: plan.manifests_digest.clone(),
+        ..Default::default()
+    };
+    inverse.plan_id = compute_plan_id(&inverse)?;
+    inverse.name = plan_name(&inverse.plan_id);
+    Ok(inverse)
+}
+
+pub fn cmd_invert(args: &[String]) -> Result<()> {
+    let [plan_arg, out_arg] = args else {
+        eprintln!("usage: rtt-planner invert <plan.json> <out.json>");
+        bail!("Invalid arguments");
+    };
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    let out = validate_path(out_arg, "output file")?;
+    let inverse = invert_plan(&plan)?;
+    fs::write(&out, serde_json::to_vec_pretty(&inverse)?).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    println!("{}", inverse.plan_id);
+    eprintln!("[OK] Inverse of {} written unsigned: {:?}", plan.plan_id, out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invert_reverses_batches() {
+        let route = |from: &str, batch: &str| Route { from: from.into(), to: "z".into(), batch: Some(batch.into()), ..Default::default() };
+        let mut plan = Plan {
+            routes_add: vec![route("a", "BATCH-1"), route("b", "BATCH-2")],
+            routes_del: vec![route("old", "BATCH-2")],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+
+        let inverse = invert_plan(&plan).unwrap();
+        assert_eq!(inverse.order, vec!["BATCH-2", "BATCH-1"]);
+        assert_eq!(inverse.routes_add[0].from, "old");
+        assert_eq!(inverse.routes_del.len(), 2);
+        assert_ne!(inverse.plan_id, plan.plan_id);
+        assert_eq!(invert_plan(&inverse).unwrap().plan_id, plan.plan_id);
+
+        plan.routes_add[0].batch = None;
+        assert!(invert_plan(&plan).is_err());
+    }
+}
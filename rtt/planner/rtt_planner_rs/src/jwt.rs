@@ -0,0 +1,153 @@
+//! Plans as signed JWTs
+//!
+//! `--format jwt` writes the plan as a compact JWS instead of a JSON file,
+//! for systems that already consume JWTs:
+//!
+//! ```text
+//! base64url({"alg":"EdDSA","kid":"dev","typ":"JWT"}) . base64url(plan + "iat") . base64url(sig)
+//! ```
+//!
+//! The payload is the unsigned plan, so its `plan_id` is a claim, plus
+//! `iat`, the signing time. The ed25519 signature covers the JWS signing
+//! input, the first two segments joined by `.`, rather than the plan's
+//! canonical bytes; `verify` recognises tokens and checks them as JWS, so
+//! `alg` must be `EdDSA` and `kid` picks the keyring entry.
+//! `--deterministic-signing` leaves `iat` out.
+
+use crate::{Plan, Sign};
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlanFormat {
+    #[default]
+    Json,
+    Jwt,
+}
+
+impl FromStr for PlanFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "jwt" => Ok(Self::Jwt),
+            _ => bail!("Unknown plan format: {} (expected json or jwt)", s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    kid: String,
+    typ: String,
+}
+
+/// A decoded token, not yet verified.
+pub struct Token {
+    pub plan: Plan,
+    pub key_id: String,
+    pub issued_at: Option<u64>,
+    /// What the signature covers: `header.payload`, as in the token.
+    pub signing_input: String,
+    sig: Vec<u8>,
+}
+
+impl Token {
+    /// The token's signature in the plan's own form, for key lookup and
+    /// age checks.
+    pub fn sign(&self) -> Sign {
+        Sign { alg: "ed25519".into(), key_id: self.key_id.clone(), sig: STANDARD.encode(&self.sig), signed_at: self.issued_at }
+    }
+}
+
+/// The JWS signing input for `plan`, signed by `key_id` at `issued_at`.
+pub fn signing_input(plan: &Plan, key_id: &str, issued_at: Option<u64>) -> Result<String> {
+    let header = Header { alg: "EdDSA".into(), kid: key_id.into(), typ: "JWT".into() };
+    let mut payload = serde_json::to_value(plan)?;
+    if let Some(obj) = payload.as_object_mut() {
+        obj.remove("sign");
+        obj.remove("signatures");
+        if let Some(iat) = issued_at {
+            obj.insert("iat".into(), iat.into());
+        }
+    }
+    let encode = |bytes: Vec<u8>| URL_SAFE_NO_PAD.encode(bytes);
+    Ok(format!("{}.{}", encode(serde_json::to_vec(&header)?), encode(serde_json::to_vec(&payload)?)))
+}
+
+/// The compact token from a signing input and its base64 signature.
+pub fn encode(signing_input: &str, sig_b64: &str) -> Result<String> {
+    let sig = STANDARD.decode(sig_b64.trim()).context("Signature is not valid base64")?;
+    Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(sig)))
+}
+
+/// Whether `content` is a compact JWS rather than a JSON plan.
+pub fn is_token(content: &str) -> bool {
+    let content = content.trim();
+    content.split('.').count() == 3
+        && content.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+pub fn decode(token: &str) -> Result<Token> {
+    let [header, payload, sig] = token.trim().split('.').collect::<Vec<_>>()[..] else {
+        bail!("Token must have three dot-separated segments");
+    };
+    let segment = |name: &str, s: &str| URL_SAFE_NO_PAD.decode(s).with_context(|| format!("Token {} is not base64url", name));
+    let header: Header = serde_json::from_slice(&segment("header", header)?).context("Failed to parse token header")?;
+    if header.alg != "EdDSA" {
+        bail!("Token alg is {}; only EdDSA tokens are supported", header.alg);
+    }
+    let claims: serde_json::Value = serde_json::from_slice(&segment("payload", payload)?).context("Failed to parse token payload")?;
+    let issued_at = claims.get("iat").and_then(serde_json::Value::as_u64);
+    let plan = serde_json::from_value(claims).context("Token payload is not a plan")?;
+    let signing_input = token.trim()[..token.trim().rfind('.').expect("three segments")].to_string();
+    Ok(Token { plan, key_id: header.kid, issued_at, signing_input, sig: segment("signature", sig)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::{verify_token, PublicKeys};
+    use crate::{compute_plan_id, plan_name, Route};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_jwt_round_trip() {
+        let mut plan = Plan {
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), ..Default::default() }],
+            order: vec!["BATCH-1".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.name = plan_name(&plan.plan_id);
+
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let input = signing_input(&plan, "dev", Some(1_700_000_000)).unwrap();
+        let sig = STANDARD.encode(sk.sign(input.as_bytes()).to_bytes());
+        let token = encode(&input, &sig).unwrap();
+        assert!(is_token(&token) && !is_token(&serde_json::to_string(&plan).unwrap()));
+
+        let header: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(token.split('.').next().unwrap()).unwrap()).unwrap();
+        assert_eq!(header, serde_json::json!({"alg": "EdDSA", "kid": "dev", "typ": "JWT"}));
+        let decoded = decode(&token).unwrap();
+        assert_eq!(decoded.plan.plan_id, plan.plan_id);
+        assert_eq!(decoded.issued_at, Some(1_700_000_000));
+
+        let keys = PublicKeys::Any(vec![STANDARD.encode(sk.verifying_key().to_bytes())]);
+        let verified = verify_token(&decoded, &keys, None);
+        assert_eq!(verified.error, None);
+
+        // A payload swapped under the signature fails
+        let mut other = plan;
+        other.routes_add[0].to = "c".into();
+        other.plan_id = compute_plan_id(&other).unwrap();
+        let forged = format!("{}.{}", signing_input(&other, "dev", Some(1_700_000_000)).unwrap(), token.rsplit('.').next().unwrap());
+        assert!(verify_token(&decode(&forged).unwrap(), &keys, None).error.is_some());
+        assert!(decode("a.b").is_err());
+    }
+}
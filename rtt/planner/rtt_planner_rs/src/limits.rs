@@ -0,0 +1,92 @@
+//! Endpoint name limits
+//!
+//! `--max-endpoint-len N` rejects routes with a `from` or `to` longer than
+//! N, for fabric components that cap identifier length. Names are measured
+//! after range and bidirectional expansion, in bytes by default or in
+//! grapheme clusters with `--endpoint-len-unit graphemes`, where `é`
+//! written as `e` plus a combining accent counts as one.
+
+use crate::Route;
+use anyhow::{bail, Result};
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LengthUnit {
+    #[default]
+    Bytes,
+    Graphemes,
+}
+
+impl LengthUnit {
+    fn measure(self, s: &str) -> usize {
+        match self {
+            Self::Bytes => s.len(),
+            Self::Graphemes => s.graphemes(true).count(),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Bytes => "bytes",
+            Self::Graphemes => "graphemes",
+        }
+    }
+}
+
+impl FromStr for LengthUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bytes" => Ok(Self::Bytes),
+            "graphemes" => Ok(Self::Graphemes),
+            _ => bail!("Unknown endpoint length unit: {} (expected bytes or graphemes)", s),
+        }
+    }
+}
+
+/// Fail on the first route with an endpoint longer than `max` `unit`s.
+pub fn check_endpoint_lengths(routes: &[Route], max: usize, unit: LengthUnit) -> Result<()> {
+    for (i, route) in routes.iter().enumerate() {
+        for endpoint in [&route.from, &route.to] {
+            let len = unit.measure(endpoint);
+            if len > max {
+                bail!(
+                    "Route {} ({} -> {}): endpoint {:?} is {} {}, over --max-endpoint-len {}",
+                    i + 1,
+                    route.from,
+                    route.to,
+                    endpoint,
+                    len,
+                    unit.as_str(),
+                    max
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_length_limits() {
+        let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+        // "café" with a combining accent: 6 bytes, 4 graphemes
+        let routes = vec![route("a", "b"), route("a", "cafe\u{301}")];
+
+        check_endpoint_lengths(&routes, 6, LengthUnit::Bytes).unwrap();
+        check_endpoint_lengths(&routes, 4, LengthUnit::Graphemes).unwrap();
+
+        // Five is too short in bytes but enough in graphemes
+        let err = check_endpoint_lengths(&routes, 5, LengthUnit::Bytes).unwrap_err().to_string();
+        assert_eq!(err, "Route 2 (a -> cafe\u{301}): endpoint \"cafe\\u{301}\" is 6 bytes, over --max-endpoint-len 5");
+        check_endpoint_lengths(&routes, 5, LengthUnit::Graphemes).unwrap();
+        let err = check_endpoint_lengths(&routes, 3, LengthUnit::Graphemes).unwrap_err().to_string();
+        assert!(err.starts_with("Route 2 ") && err.contains("is 4 graphemes, over --max-endpoint-len 3"), "{}", err);
+        assert!("chars".parse::<LengthUnit>().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Output locking
+//!
+//! `--lockfile` takes an advisory lock on `<out_plan.json>.lock` for the
+//! whole run, so overlapping runs cannot interleave writes to the same plan.
+//! A second run fails at once, or with `--lock-wait <secs>` retries until the
+//! lock frees up or the wait runs out. The lock file is left in place;
+//! removing it would let a third run lock a fresh file while the second
+//! still holds the old one.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Held for the duration of a run; the lock is released on drop.
+pub struct OutputLock {
+    _file: File,
+}
+
+pub fn lock_path(out: &Path) -> PathBuf {
+    let mut path = out.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+pub fn acquire(out: &Path, wait: Option<Duration>) -> Result<OutputLock> {
+    let path = lock_path(out);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file: {:?}", path))?;
+    let deadline = Instant::now() + wait.unwrap_or_default();
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(OutputLock { _file: file }),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => std::thread::sleep(RETRY_INTERVAL),
+            Err(TryLockError::WouldBlock) => {
+                bail!("Output {:?} is locked by another planner run ({:?})", out, path)
+            }
+            Err(TryLockError::Error(e)) => return Err(e).with_context(|| format!("Failed to lock {:?}", path)),
+        }
+    }
+}
@@ -1,15 +1,83 @@
 // RTT Planner - SECURITY HARDENED
 // Generates execution plans with security validation
 
-use anyhow::*;
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
-use std::{fs, path::PathBuf};
+use std::{collections::{BTreeMap, BTreeSet}, fs, io::Write, path::{Path, PathBuf}, time::SystemTime};
 
-#[derive(Serialize, Deserialize, Clone)]
+mod aggregate;
+mod annotate;
+mod batch;
+mod convert;
+mod deprecate;
+mod emit;
+mod endpoints;
+mod expand;
+mod identity;
+mod input;
+mod invert;
+mod jwt;
+mod limits;
+mod lock;
+mod manifest;
+mod matrix;
+mod merge;
+mod normalize;
+mod overlay;
+mod prune;
+mod receipt;
+mod reconcile;
+mod resolve;
+mod schema;
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "shm")]
+mod shm;
+mod simulate;
+mod sshagent;
+mod state;
+mod stream;
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))] // recorded, but only exported with otlp
+mod telemetry;
+mod trace_route;
+mod verify;
+mod visualize;
+mod weights;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 struct Route {
     from: String,
     to: String,
+    /// Free-form route metadata, e.g. `env` or `owner`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    /// Feature flags that must all be enabled with `--enable-feature` for
+    /// the route to be planned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    requires: Vec<String>,
+    /// Also plan the reverse route `to -> from`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    bidirectional: bool,
+    /// Relative importance; heavier routes are batched first. See `weights`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<f64>,
+    /// Which routes to keep when capacities force a choice between equally
+    /// good selections; higher wins. Unlike `weight`, never changes how many
+    /// routes are admitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<i32>,
+    /// Routes (`from->to`) that must be applied in an earlier batch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    after: Vec<String>,
+    /// Batch this route is applied in; set by `--batch-size`/`--batches`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    batch: Option<String>,
+    /// Expected hash of the route's source content, checked and cleared on
+    /// ingest; see `input::route_checksum`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,20 +85,128 @@ struct Routes {
     routes: Vec<Route>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct Plan {
     plan_id: String,
+    /// Short human-friendly name derived from `plan_id`; see `plan_name`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    name: String,
     routes_add: Vec<Route>,
     routes_del: Vec<Route>,
     order: Vec<String>,
+    /// Free-form operator metadata (ticket id, change request). Part of the
+    /// canonical bytes, so it is covered by `plan_id` and the signature.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    annotations: BTreeMap<String, String>,
+    /// Per-batch operator metadata added by `annotate-batch`. Signed like
+    /// `annotations`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    batch_annotations: BTreeMap<String, BTreeMap<String, String>>,
+    /// Per-batch notes added by `annotate-batch --unsigned`. Outside the
+    /// canonical bytes, so editing them keeps `plan_id` and signatures.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    batch_notes: BTreeMap<String, BTreeMap<String, String>>,
+    /// Unix seconds after which the plan must not be applied; set by
+    /// `--valid-for`. Covered by `plan_id` and the signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    valid_until: Option<u64>,
+    /// Digest of the manifests the plan was made against; see
+    /// `manifest::digest`. Covered by `plan_id` and the signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifests_digest: Option<String>,
+    /// `plan_id`s of plans that must be applied before this one; set by
+    /// `--depends-on`. Covered by `plan_id` and the signature.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<String>,
     sign: Option<Sign>,
+    /// Co-signatures from additional approvers, over the same bytes as `sign`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    signatures: Vec<Sign>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Plan {
+    fn all_signatures(&self) -> impl Iterator<Item = &Sign> {
+        self.sign.iter().chain(&self.signatures)
+    }
+
+    /// Index `routes_add` by batch for repeated `batch_of` lookups.
+    fn route_batches<'a>(&'a self, identity: &'a identity::IdentityKeys) -> batch::RouteBatches<'a> {
+        batch::RouteBatches::new(&self.routes_add, &self.order, identity)
+    }
+
+    /// Check that every route in `routes_add` belongs to exactly one batch
+    /// in `order`. Untagged routes belong to the first batch in the
+    /// historical shape where no route is tagged; once batching has tagged
+    /// routes, an untagged one is a batching bug or a bad hand edit.
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut listed = BTreeSet::new();
+        for batch in &self.order {
+            if !listed.insert(batch) {
+                problems.push(format!("batch {} is listed twice in order", batch));
+            }
+        }
+        let tagged = self.routes_add.iter().any(|r| r.batch.is_some());
+        for route in &self.routes_add {
+            match &route.batch {
+                Some(batch) if !listed.contains(batch) => problems.push(format!(
+                    "route {} -> {} is in batch {}, which is not in order",
+                    route.from, route.to, batch
+                )),
+                None if tagged || self.order.is_empty() => {
+                    problems.push(format!("route {} -> {} is not assigned to any batch", route.from, route.to))
+                }
+                _ => {}
+            }
+        }
+        // A deleted route must outlive every route that lists it in `after`:
+        // no added route may need it, and deleted dependents go first. A
+        // route deleted and added again is replaced, and still there for
+        // dependents from the batch it comes back in.
+        let batch_of = |r: &Route| r.batch.clone().or_else(|| self.order.first().cloned());
+        let position = |r: &Route| batch_of(r).and_then(|b| self.order.iter().position(|o| *o == b));
+        let replaced = |key: &str, from, until| {
+            self.routes_add.iter().any(|r| batch::route_key(r) == key && (from..=until).contains(&position(r)))
+        };
+        for prereq in &self.routes_del {
+            let key = batch::route_key(prereq);
+            let deleted = position(prereq);
+            for route in self.routes_add.iter().filter(|r| r.after.contains(&key)) {
+                if !replaced(&key, deleted, deleted.max(position(route))) {
+                    problems.push(format!(
+                        "route {} is deleted but {} -> {}, which is added, runs after it",
+                        key, route.from, route.to
+                    ));
+                }
+            }
+            for route in self.routes_del.iter().filter(|r| r.after.contains(&key)) {
+                if position(route) > deleted && !replaced(&key, deleted, deleted) {
+                    problems.push(format!(
+                        "route {} is deleted before its dependent {} -> {}; delete it in or after {}",
+                        key,
+                        route.from,
+                        route.to,
+                        batch_of(route).unwrap_or_default()
+                    ));
+                }
+            }
+        }
+        if !problems.is_empty() {
+            bail!("Invalid plan: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
 struct Sign {
     alg: String,
     key_id: String,
     sig: String,
+    /// Unix seconds when the signature was made. Covered by the signature
+    /// itself; see `signed_bytes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signed_at: Option<u64>,
 }
 
 fn hash_bytes(b: &[u8]) -> String {
@@ -39,7 +215,132 @@ fn hash_bytes(b: &[u8]) -> String {
     format!("sha256-{:x}", h.finalize())
 }
 
+/// Canonical plan bytes: compact JSON with sorted keys and the `plan_id`,
+/// `name`, `batch_notes` and signature fields removed. `plan_id` is the hash of these bytes.
+fn canonical_bytes(plan: &Plan) -> Result<Vec<u8>> {
+    let mut value = serde_json::to_value(plan)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("plan_id");
+        obj.remove("name");
+        obj.remove("batch_notes");
+        obj.remove("sign");
+        obj.remove("signatures");
+    }
+    Ok(serde_json::to_vec(&value)?)
+}
+
+/// The bytes a signature covers: the canonical bytes, followed by the signing
+/// time when the signature records one. Signatures without `signed_at` cover
+/// the canonical bytes alone, as they always have.
+fn signed_bytes(plan: &Plan, signed_at: Option<u64>) -> Result<Vec<u8>> {
+    let mut bytes = canonical_bytes(plan)?;
+    if let Some(t) = signed_at {
+        bytes.extend_from_slice(format!("\nsigned_at={}", t).as_bytes());
+    }
+    Ok(bytes)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// The time a new signature records. `--deterministic-signing` records
+/// none: ed25519 is deterministic, so the signature then depends only on
+/// the plan and the key.
+fn signing_time(opts: &Options) -> Option<u64> {
+    (!opts.deterministic_signing).then(unix_now)
+}
+
+fn compute_plan_id(plan: &Plan) -> Result<String> {
+    Ok(hash_bytes(&canonical_bytes(plan)?))
+}
+
+/// Short stable name for a plan: `plan-` plus the first 40 bits of the
+/// SHA-256 of `plan_id` in lowercase RFC 4648 base32, e.g. `plan-k3v7q2xa`.
+fn plan_name(plan_id: &str) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let digest = Sha256::digest(plan_id.as_bytes());
+    let bits = digest[..5].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    let name: String = (0..8)
+        .rev()
+        .map(|i| ALPHABET[((bits >> (i * 5)) & 31) as usize] as char)
+        .collect();
+    format!("plan-{}", name)
+}
+
+/// Recompute a plan's canonical `plan_id`. Returns the previously stored id
+/// when it differed; the plan then carries the new id and no signatures,
+/// since any existing signature was made over the stale content.
+fn rehash_plan(plan: &mut Plan) -> Result<Option<String>> {
+    let pid = compute_plan_id(plan)?;
+    if pid == plan.plan_id {
+        return Ok(None);
+    }
+    plan.sign = None;
+    plan.signatures.clear();
+    plan.name = plan_name(&pid);
+    Ok(Some(std::mem::replace(&mut plan.plan_id, pid)))
+}
+
+fn rehash_file(path: &Path, write: bool) -> Result<bool> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read plan file: {:?}", path))?;
+    let mut plan: Plan = serde_json::from_str(&content)
+        .with_context(|| "Failed to parse plan JSON")?;
+
+    match rehash_plan(&mut plan)? {
+        None => {
+            println!("{}", plan.plan_id);
+            eprintln!("[OK] plan_id is canonical");
+            Ok(false)
+        }
+        Some(stale) => {
+            println!("{}", plan.plan_id);
+            eprintln!("[WARN] plan_id changed: {} -> {}", stale, plan.plan_id);
+            if write {
+                fs::write(path, serde_json::to_vec_pretty(&plan)?)
+                    .with_context(|| format!("Failed to write plan file: {:?}", path))?;
+                eprintln!("[OK] Plan rewritten without signature; re-sign it");
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn cmd_rehash(args: &[String]) -> Result<()> {
+    let write = args.iter().any(|a| a == "--write");
+    let Some(plan_arg) = args.iter().find(|a| !a.starts_with("--")) else {
+        eprintln!("usage: rtt-planner rehash <plan.json> [--write]");
+        bail!("Invalid arguments");
+    };
+    let plan_path = validate_path(plan_arg, "plan file")?;
+    rehash_file(&plan_path, write)?;
+    Ok(())
+}
+
+/// Absolute directories that paths may name, from the colon-separated
+/// `RTT_ALLOWED_PATH_PREFIXES`. Empty when unset.
+fn allowed_path_prefixes() -> Result<Vec<PathBuf>> {
+    let Some(list) = std::env::var_os("RTT_ALLOWED_PATH_PREFIXES") else {
+        return Ok(Vec::new());
+    };
+    std::env::split_paths(&list)
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| match p.is_absolute() && !p.to_string_lossy().contains("..") {
+            true => Ok(p),
+            false => Err(anyhow!("RTT_ALLOWED_PATH_PREFIXES entries must be absolute, got: {:?}", p)),
+        })
+        .collect()
+}
+
 fn validate_path(path: &str, purpose: &str) -> Result<PathBuf> {
+    validate_path_in(path, purpose, &allowed_path_prefixes()?)
+}
+
+/// `validate_path` with absolute paths accepted under one of `allowed`.
+/// Prefixes match whole components, so `/srv/plans` does not admit
+/// `/srv/plans-old`.
+fn validate_path_in(path: &str, purpose: &str, allowed: &[PathBuf]) -> Result<PathBuf> {
     let p = PathBuf::from(path);
 
     // Check for path traversal
@@ -48,14 +349,60 @@ fn validate_path(path: &str, purpose: &str) -> Result<PathBuf> {
     }
 
     // Check for absolute paths trying to escape
-    if p.is_absolute() {
+    if p.is_absolute() && !allowed.iter().any(|prefix| p.starts_with(prefix)) {
         bail!("Absolute paths not allowed in {}: {}", purpose, path);
     }
 
     Ok(p)
 }
 
-fn safe_execute_signer(key_path: &str, plan_path: &PathBuf) -> Result<String> {
+/// The bytes handed to the external signer, in a file that is removed when
+/// this is dropped, so every exit path cleans it up.
+struct SigningPayload(PathBuf);
+
+impl SigningPayload {
+    /// Write `payload` next to the output file, or, with stdout output, to a
+    /// fresh temp file only the current user can read.
+    fn write(out_path: Option<&Path>, payload: &[u8]) -> Result<Self> {
+        let (guard, mut file) = match out_path {
+            Some(path) => {
+                let path = path.with_extension("payload");
+                let file = fs::File::create(&path)
+                    .with_context(|| format!("Failed to write signing payload: {:?}", path))?;
+                (Self(path), file)
+            }
+            None => Self::create_temp()?,
+        };
+        file.write_all(payload)
+            .with_context(|| format!("Failed to write signing payload: {:?}", guard.0))?;
+        Ok(guard)
+    }
+
+    fn create_temp() -> Result<(Self, fs::File)> {
+        let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        for attempt in 0..16 {
+            let path = std::env::temp_dir().join(format!("rtt-planner-{}-{}-{}.payload", std::process::id(), nanos, attempt));
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => return Ok((Self(path), file)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to create signing payload: {:?}", path)),
+            }
+        }
+        bail!("Failed to create a signing payload file in {:?}", std::env::temp_dir())
+    }
+}
+
+impl Drop for SigningPayload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn safe_execute_signer(key_path: &str, plan_path: &Path) -> Result<String> {
     // Validate inputs
     if key_path.contains(";") || key_path.contains("|") || key_path.contains("&") {
         bail!("Invalid characters in key path");
@@ -67,17 +414,42 @@ fn safe_execute_signer(key_path: &str, plan_path: &PathBuf) -> Result<String> {
     }
 
     // Use fixed signer path (no user input)
-    let signer_paths = [
-        "./tools/rtt_sign_rs/target/release/rtt-sign",
-        "../tools/rtt_sign_rs/target/release/rtt-sign",
-        "rtt-sign", // In PATH
-    ];
+    run_signer(&SIGNER_PATHS, key_path, &plan_str).map_err(Into::into)
+}
 
-    let mut last_error = None;
+const SIGNER_PATHS: [&str; 3] = [
+    "./tools/rtt_sign_rs/target/release/rtt-sign",
+    "../tools/rtt_sign_rs/target/release/rtt-sign",
+    "rtt-sign", // In PATH
+];
+
+/// Why signing through the external signer failed.
+#[derive(Debug)]
+enum SignerError {
+    /// No candidate path could be started: a setup issue. Holds each
+    /// candidate and why it could not run.
+    NotFound { tried: Vec<String> },
+    /// A signer ran but refused: usually the key or its permissions.
+    Exited { signer: String, stderr: String },
+}
 
-    for signer_path in &signer_paths {
+impl std::fmt::Display for SignerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound { tried } => write!(f, "No signer binary found in any candidate path (tried {})", tried.join(", ")),
+            Self::Exited { signer, stderr } => write!(f, "Signer {} exited with error: {}", signer, stderr.trim()),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+/// Sign with the first candidate that starts; its failure is final.
+fn run_signer(candidates: &[&str], key_path: &str, plan_path: &str) -> Result<String, SignerError> {
+    let mut tried = Vec::new();
+    for signer_path in candidates {
         let output = std::process::Command::new(signer_path)
-            .args(&["sign", key_path, &plan_str])
+            .args(["sign", key_path, plan_path])
             .output();
 
         match output {
@@ -86,94 +458,1107 @@ fn safe_execute_signer(key_path: &str, plan_path: &PathBuf) -> Result<String> {
                 return Ok(sig);
             }
             Ok(o) => {
-                last_error = Some(anyhow!(
-                    "Signer exited with error: {}",
-                    String::from_utf8_lossy(&o.stderr)
-                ));
+                let stderr = String::from_utf8_lossy(&o.stderr).into_owned();
+                return Err(SignerError::Exited { signer: signer_path.to_string(), stderr });
             }
-            Err(e) => {
-                last_error = Some(anyhow!("Failed to execute signer: {}", e));
+            Err(e) => tried.push(format!("{}: {}", signer_path, e)),
+        }
+    }
+    Err(SignerError::NotFound { tried })
+}
+
+/// Flags accepted by plan generation, separated from the positional arguments.
+#[derive(Default)]
+struct Options {
+    dropped_out: Option<String>,
+    annotations: BTreeMap<String, String>,
+    batching: batch::BatchConfig,
+    identity_keys: identity::IdentityKeys,
+    max_plan_bytes: Option<usize>,
+    preserve_order: bool,
+    /// Sort planned routes by `route_id` so input order does not affect the plan.
+    stable_id: bool,
+    /// Write the historical shape: every route untagged in `BATCH-1`.
+    legacy_plan: bool,
+    otlp_endpoint: Option<String>,
+    features: BTreeSet<String>,
+    weights: Option<String>,
+    /// Rescaling of weights in the capacity solver's objective.
+    weight_normalization: weights::WeightNormalization,
+    deprecations: Option<String>,
+    /// Required and optional route labels per environment, and the active one.
+    metadata_schema: Option<String>,
+    environment: Option<String>,
+    collapse_transitive: bool,
+    /// Plan at most this many routes, the heaviest by weight.
+    keep_top: Option<usize>,
+    /// Longest endpoint name allowed, in `endpoint_len_unit`s.
+    max_endpoint_len: Option<usize>,
+    endpoint_len_unit: limits::LengthUnit,
+    stream: bool,
+    input_format: input::InputFormat,
+    lockfile: bool,
+    lock_wait: Option<usize>,
+    normalize: bool,
+    /// Plans that must be applied first (`--depends-on`, repeatable).
+    depends_on: Vec<String>,
+    /// Seconds from planning until the plan expires (`--valid-for`).
+    valid_for: Option<u64>,
+    /// Directory for one routes file per batch plus an index.
+    emit_per_batch: Option<String>,
+    /// File for a ledger receipt of the written plan.
+    receipt: Option<String>,
+    /// SHM segment to also publish the written plan to.
+    publish_shm: Option<String>,
+    /// Label whose values collapse routes into summary routes.
+    aggregate_by: Option<String>,
+    /// JSON plan or signed JWT (`--format jwt`).
+    plan_format: jwt::PlanFormat,
+    /// File for the unsigned inverse of the written plan.
+    with_rollback: Option<String>,
+    fail_on_empty: bool,
+    record_manifests_digest: bool,
+    resolver: resolve::ResolverKind,
+    on_unresolved: resolve::OnUnresolved,
+    /// Comment of the SSH agent key to sign with (`--signer ssh-agent`).
+    ssh_agent_key: Option<String>,
+    /// Leave the signing time out so re-runs sign identical bytes.
+    deterministic_signing: bool,
+}
+
+fn parse_count(flag: &str, value: &str) -> Result<usize> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => bail!("{} expects a positive integer, got: {}", flag, value),
+    }
+}
+
+fn parse_options(args: &[String]) -> Result<(Vec<String>, Options)> {
+    let mut positional = Vec::new();
+    let mut opts = Options::default();
+    let mut ssh_agent = false;
+    let mut key_comment = None;
+    let mut it = args.iter();
+
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().cloned().with_context(|| format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--dropped-out" => opts.dropped_out = Some(value()?),
+            "--annotate" => {
+                let pair = value()?;
+                let (k, v) = pair
+                    .split_once('=')
+                    .filter(|(k, _)| !k.is_empty())
+                    .with_context(|| format!("--annotate expects key=value, got: {}", pair))?;
+                opts.annotations.insert(k.into(), v.into());
+            }
+            "--batch-strategy" => opts.batching.strategy = value()?.parse()?,
+            "--batch-size" => opts.batching.batch_size = Some(parse_count(arg, &value()?)?),
+            "--batches" => opts.batching.batches = Some(parse_count(arg, &value()?)?),
+            "--max-batches" => opts.batching.max_batches = Some(parse_count(arg, &value()?)?),
+            "--optimize" => opts.batching.optimize = true,
+            "--require-optimize" => {
+                opts.batching.optimize = true;
+                opts.batching.require_optimize = true;
+            }
+            "--identity-keys" => opts.identity_keys = value()?.parse()?,
+            "--max-plan-bytes" => opts.max_plan_bytes = Some(parse_count(arg, &value()?)?),
+            "--max-endpoint-len" => opts.max_endpoint_len = Some(parse_count(arg, &value()?)?),
+            "--endpoint-len-unit" => opts.endpoint_len_unit = value()?.parse()?,
+            "--keep-top" => opts.keep_top = Some(parse_count(arg, &value()?)?),
+            "--preserve-order" => opts.preserve_order = true,
+            "--otlp-endpoint" => opts.otlp_endpoint = Some(value()?),
+            "--weights" => opts.weights = Some(value()?),
+            "--normalize-weights" => opts.weight_normalization = value()?.parse()?,
+            "--deprecations" => opts.deprecations = Some(value()?),
+            "--metadata-schema" => opts.metadata_schema = Some(value()?),
+            "--env" => opts.environment = Some(value()?),
+            "--collapse-transitive" => opts.collapse_transitive = true,
+            "--stream" => opts.stream = true,
+            "--from-stdin-format" => opts.input_format = value()?.parse()?,
+            "--lockfile" => opts.lockfile = true,
+            "--normalize" => opts.normalize = true,
+            "--emit-per-batch" => opts.emit_per_batch = Some(value()?),
+            "--receipt" => opts.receipt = Some(value()?),
+            "--publish-shm" => opts.publish_shm = Some(value()?),
+            "--format" => opts.plan_format = value()?.parse()?,
+            "--aggregate-by" => opts.aggregate_by = Some(aggregate::parse_label(&value()?)?),
+            "--with-rollback" => opts.with_rollback = Some(value()?),
+            "--fail-on-empty" => opts.fail_on_empty = true,
+            "--stable-id" => opts.stable_id = true,
+            "--legacy-plan" => opts.legacy_plan = true,
+            "--record-manifests-digest" => opts.record_manifests_digest = true,
+            "--resolver" => opts.resolver = value()?.parse()?,
+            "--on-unresolved" => opts.on_unresolved = value()?.parse()?,
+            "--valid-for" => opts.valid_for = Some(verify::parse_age(&value()?)?),
+            "--depends-on" => {
+                let id = value()?;
+                if !id.starts_with("sha256-") {
+                    bail!("--depends-on expects a plan_id, got: {}", id);
+                }
+                opts.depends_on.push(id);
+            }
+            "--signer" => match value()?.as_str() {
+                "ssh-agent" => ssh_agent = true,
+                other => bail!("Unknown signer: {} (expected ssh-agent)", other),
+            },
+            "--key-comment" => key_comment = Some(value()?),
+            "--deterministic-signing" => opts.deterministic_signing = true,
+            "--lock-wait" => {
+                opts.lockfile = true;
+                opts.lock_wait = Some(parse_count(arg, &value()?)?);
             }
+            "--enable-feature" => {
+                opts.features.insert(value()?);
+            }
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg.clone()),
         }
     }
+    opts.ssh_agent_key = match (ssh_agent, key_comment) {
+        (true, Some(comment)) => Some(comment),
+        (false, None) => None,
+        _ => bail!("--signer ssh-agent and --key-comment go together"),
+    };
+    Ok((positional, opts))
+}
+
+fn check_plan_size(plan_len: usize, limit: Option<usize>) -> Result<()> {
+    match limit {
+        Some(limit) if plan_len > limit => bail!(
+            "Plan is {} bytes, over the --max-plan-bytes limit of {}; compress it or shard the routes into smaller plans",
+            plan_len,
+            limit
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Files named by options, loaded once and shared by every plan built.
+struct Inputs {
+    weights: Option<weights::Weights>,
+    deprecations: Vec<deprecate::Deprecation>,
+    /// Label rules of the active environment, from `--metadata-schema`.
+    label_rules: Option<schema::LabelRules>,
+    /// Endpoint capacities from the manifests, read only for `--optimize`.
+    capacities: BTreeMap<String, u32>,
+    /// For `--record-manifests-digest`.
+    manifests_digest: Option<String>,
+}
 
-    Err(last_error.unwrap_or_else(|| anyhow!("No signer found")))
+impl Inputs {
+    fn load(opts: &Options, manifests_dir: Option<&Path>) -> Result<Self> {
+        let weights = opts
+            .weights
+            .as_deref()
+            .map(|p| validate_path(p, "weights file").and_then(|p| weights::load_weights(&p)))
+            .transpose()?;
+        let deprecations = opts
+            .deprecations
+            .as_deref()
+            .map(|p| validate_path(p, "deprecations file").and_then(|p| deprecate::load_deprecations(&p)))
+            .transpose()?
+            .unwrap_or_default();
+        let label_rules = match (&opts.metadata_schema, &opts.environment) {
+            (Some(path), Some(env)) => Some(schema::load_schema(&validate_path(path, "metadata schema")?, env)?),
+            (None, None) => None,
+            _ => bail!("--metadata-schema and --env go together"),
+        };
+        let capacities = match manifests_dir {
+            Some(dir) if opts.batching.optimize => manifest::load_capacities(dir)?,
+            _ => BTreeMap::new(),
+        };
+        let manifests_digest = match manifests_dir {
+            Some(dir) if opts.record_manifests_digest => Some(manifest::digest(dir)?),
+            None if opts.record_manifests_digest => bail!("--record-manifests-digest needs a manifests directory"),
+            _ => None,
+        };
+        Ok(Self { weights, deprecations, label_rules, capacities, manifests_digest })
+    }
+}
+
+fn report_normalizations(report: &[normalize::Normalization]) {
+    let semantic = report.iter().filter(|n| n.kind == normalize::NormalizationKind::Semantic).count();
+    for n in report {
+        let line = format!("{:?} -> {:?} normalized to {} -> {}", n.original_from, n.original_to, n.from, n.to);
+        match n.kind {
+            normalize::NormalizationKind::Semantic => eprintln!("[WARN] {} (semantic: now a duplicate)", line),
+            normalize::NormalizationKind::Cosmetic => eprintln!("[INFO] {} (cosmetic)", line),
+        }
+    }
+    if !report.is_empty() {
+        eprintln!("[INFO] Normalized {} route(s): {} semantic, {} cosmetic", report.len(), semantic, report.len() - semantic);
+    }
+}
+
+/// Turn input routes into an unsigned plan: expand, prune, batch and hash.
+/// Returns the plan and the routes pruned from it.
+///
+/// When no routes are left to plan the result is the empty plan: no routes
+/// and no batches, so with no annotations or expiry its canonical bytes are
+/// `{"order":[],"routes_add":[],"routes_del":[]}` and every empty plan has
+/// the same `plan_id`. `--fail-on-empty` makes that case an error.
+fn build_plan(
+    routes: Vec<Route>,
+    opts: &Options,
+    inputs: &Inputs,
+    trace: &mut telemetry::Trace,
+) -> Result<(Plan, Vec<prune::DroppedRoute>)> {
+    // Expand compact range endpoints (e.g. `node-[1..100]`) and
+    // bidirectional routes
+    let t = SystemTime::now();
+    let mut routes = routes;
+    if opts.normalize {
+        report_normalizations(&normalize::normalize_routes(&mut routes, &opts.identity_keys));
+    }
+    let mut dropped = Vec::new();
+    let routes_add = expand::expand_routes(routes, expand::MAX_EXPANDED_ROUTES)?;
+    let mut routes_add = expand::expand_bidirectional(routes_add, expand::MAX_EXPANDED_ROUTES, &mut dropped)?;
+    if let Some(max) = opts.max_endpoint_len {
+        limits::check_endpoint_lengths(&routes_add, max, opts.endpoint_len_unit)?;
+    }
+
+    // Weights are metadata: merge them before pruning compares routes
+    if let Some(weights) = &inputs.weights {
+        let matched = weights::apply_weights(&mut routes_add, weights);
+        eprintln!("[INFO] Applied {} external weight(s)", matched);
+    }
+    trace.phase("expand", t);
+
+    // Drop routes behind disabled features, self-loops and duplicates,
+    // remembering why
+    let t = SystemTime::now();
+    let routes_add = prune::filter_features(routes_add, &opts.features, &mut dropped);
+    let routes_add = prune::dedup_routes(routes_add, &opts.identity_keys, &mut dropped);
+    let routes_add = if opts.collapse_transitive {
+        let before = dropped.len();
+        let kept = prune::collapse_transitive(routes_add, &mut dropped);
+        eprintln!("[INFO] Collapsed {} transitively redundant route(s)", dropped.len() - before);
+        kept
+    } else {
+        routes_add
+    };
+    let routes_add = match opts.keep_top {
+        Some(n) => {
+            let before = dropped.len();
+            let kept = prune::keep_top(routes_add, n, &opts.identity_keys, &mut dropped);
+            eprintln!("[INFO] Kept the {} heaviest route(s); {} dropped", kept.len(), dropped.len() - before);
+            kept
+        }
+        None => routes_add,
+    };
+    let routes_add = if inputs.capacities.is_empty() {
+        routes_add
+    } else {
+        let before = dropped.len();
+        let (kept, repair) =
+            prune::admit_capacities(routes_add, &inputs.capacities, &opts.identity_keys, opts.weight_normalization, &mut dropped)?;
+        if let Some(repair) = repair {
+            eprintln!(
+                "[WARN] {} route(s) exceed manifest capacities and were not planned: {}",
+                dropped.len() - before,
+                repair.drop.join(", ")
+            );
+            let relax: Vec<String> = repair.relax.iter().map(|(endpoint, n)| format!("{}={}", endpoint, n)).collect();
+            eprintln!("[WARN] Capacities that would admit every route: {}", relax.join(", "));
+        }
+        kept
+    };
+    if !dropped.is_empty() {
+        eprintln!("[INFO] Dropped {} route(s)", dropped.len());
+    }
+    trace.phase("dedup", t);
+    trace.attr("rtt.routes.dropped", dropped.len());
+
+    if opts.resolver != resolve::ResolverKind::None {
+        let t = SystemTime::now();
+        resolve::check_endpoints(&routes_add, opts.resolver.resolver().as_ref(), opts.on_unresolved)?;
+        trace.phase("resolve", t);
+    }
+
+    if !inputs.deprecations.is_empty() {
+        let today = (unix_now() / 86_400) as i64;
+        deprecate::check_deprecations(&routes_add, &inputs.deprecations, today)?;
+    }
+    if let (Some(rules), Some(env)) = (&inputs.label_rules, &opts.environment) {
+        schema::check_labels(&routes_add, rules, env)?;
+    }
+
+    // Split into rollout batches, heaviest routes first. `--preserve-order`
+    // promises consumers the exact source order in one flat batch, so it
+    // skips weight ordering and excludes batching flags. `--legacy-plan`
+    // overrides both instead, for consumers that only know one batch, and
+    // keeps input order as the old single-batch plans did.
+    let t = SystemTime::now();
+    if opts.legacy_plan && opts.valid_for.is_some() {
+        bail!("--valid-for records the planning time, so it cannot be combined with --legacy-plan");
+    }
+    let mut routes_add = routes_add;
+    if opts.stable_id {
+        if opts.preserve_order {
+            bail!("--stable-id reorders routes, so it cannot be combined with --preserve-order");
+        }
+        // Input order then no longer reaches batching, the plan or its plan_id
+        routes_add.sort_by_cached_key(|r| opts.identity_keys.route_id(r));
+    }
+    if routes_add.is_empty() {
+        if opts.fail_on_empty {
+            bail!("No routes left to plan (--fail-on-empty)");
+        }
+        eprintln!("[WARN] No routes to plan; writing the empty plan");
+    }
+    // Legacy consumers expect BATCH-1 even in an empty plan
+    let order = if opts.legacy_plan {
+        if opts.preserve_order {
+            eprintln!("[WARN] --legacy-plan overrides --preserve-order; routes keep their input order in {}", batch::batch_name(0));
+        }
+        if opts.batching.is_set() {
+            eprintln!("[WARN] --legacy-plan ignores batching options; every route goes in {}", batch::batch_name(0));
+        }
+        if routes_add.iter().any(|r| !r.after.is_empty()) {
+            eprintln!("[WARN] --legacy-plan writes a single batch, so `after` prerequisites are not ordered");
+        }
+        for route in &mut routes_add {
+            route.batch = None;
+        }
+        vec![batch::batch_name(0)]
+    } else if routes_add.is_empty() {
+        Vec::new()
+    } else if opts.preserve_order {
+        if opts.batching.is_set() {
+            bail!("--preserve-order cannot be combined with batching options");
+        }
+        if routes_add.iter().any(|r| !r.after.is_empty()) {
+            bail!("--preserve-order cannot honour `after` prerequisites in a single batch");
+        }
+        vec![batch::batch_name(0)]
+    } else {
+        weights::order_by_weight(&mut routes_add);
+        batch::assign_batches(&mut routes_add, &opts.batching)?
+    };
+    trace.phase("batch", t);
+    trace.attr("rtt.routes.planned", routes_add.len());
+    trace.attr("rtt.batches", order.len());
+
+    // Create plan
+    let mut plan = Plan {
+        plan_id: "sha256-PLACEHOLDER".to_string(),
+        routes_add,
+        order,
+        annotations: opts.annotations.clone(),
+        valid_until: opts.valid_for.map(|secs| unix_now().saturating_add(secs)),
+        manifests_digest: inputs.manifests_digest.clone(),
+        depends_on: opts.depends_on.clone(),
+        ..Default::default()
+    };
+    plan.validate().context("Batching produced an inconsistent plan")?;
+
+    // Compute plan hash and the name derived from it. `--stream` hashes
+    // while writing instead.
+    if !opts.stream {
+        let t = SystemTime::now();
+        let pid = compute_plan_id(&plan)?;
+        plan.name = plan_name(&pid);
+        plan.plan_id = pid;
+        trace.phase("hash", t);
+        trace.attr("rtt.plan_id", plan.plan_id.as_str());
+    }
+    Ok((plan, dropped))
 }
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
-    if args.len() < 4 {
+    match args.get(1).map(String::as_str) {
+        Some("rehash") => return cmd_rehash(&args[2..]),
+        Some("verify") => return verify::cmd_verify(&args[2..]),
+        Some("verify-id") => return verify::cmd_verify_id(&args[2..]),
+        Some("verify-receipt") => return receipt::cmd_verify_receipt(&args[2..]),
+        Some("simulate") => return simulate::cmd_simulate(&args[2..]),
+        Some("convert") => return convert::cmd_convert(&args[2..]),
+        Some("overlay") => return overlay::cmd_overlay(&args[2..]),
+        Some("from-matrix") => return matrix::cmd_from_matrix(&args[2..]),
+        Some("annotate-batch") => return annotate::cmd_annotate_batch(&args[2..]),
+        Some("trace-route") => return trace_route::cmd_trace_route(&args[2..]),
+        Some("endpoints") => return endpoints::cmd_endpoints(&args[2..]),
+        Some("invert") => return invert::cmd_invert(&args[2..]),
+        Some("expand-aggregates") => return aggregate::cmd_expand_aggregates(&args[2..]),
+        Some("visualize") => return visualize::cmd_visualize(&args[2..]),
+        Some("diff-state") => return reconcile::cmd_diff_state(&args[2..]),
+        Some("merge-plans") => return merge::cmd_merge_plans(&args[2..]),
+        Some("e2e-selftest") => return selftest::cmd_e2e_selftest(&args[2..]),
+        #[cfg(feature = "serve")]
+        Some("serve") => return serve::cmd_serve(&args[2..]),
+        #[cfg(not(feature = "serve"))]
+        Some("serve") => bail!("serve requires rtt-planner built with the serve feature"),
+        #[cfg(feature = "shm")]
+        Some("fetch-shm") => return shm::cmd_fetch_shm(&args[2..]),
+        #[cfg(not(feature = "shm"))]
+        Some("fetch-shm") => bail!("fetch-shm requires rtt-planner built with the shm feature"),
+        _ => {}
+    }
+
+    let (args, opts) = parse_options(&args[1..])?;
+
+    if args.len() < 3 {
         eprintln!("RTT Planner v1.0.0 - SECURITY HARDENED");
         eprintln!();
-        eprintln!("usage: rtt-planner <routes.json> <manifests_dir> <out_plan.json> [sign_key_b64]");
+        eprintln!("usage: rtt-planner [options] <routes.json> <manifests_dir> <out_plan.json> [sign_key_b64]");
+        eprintln!("       rtt-planner rehash <plan.json> [--write]");
+        eprintln!("       rtt-planner verify <plan.json> <pub_key_b64>... [--require-all-current] [--max-sig-age <age>] [--clock-skew <age>]");
+        eprintln!("       rtt-planner verify <plan.json> --keyring <keys.json> [--require-all-current] [--max-sig-age <age>] [--clock-skew <age>]");
+        eprintln!("       rtt-planner verify --all <plan.json>... --keyring <keys.json> [same options]");
+        eprintln!("                          [--manifests-dir <dir>] [--warn-before <age>] (any form)");
+        eprintln!("       rtt-planner verify-id <plan.json>");
+        eprintln!("       rtt-planner verify-receipt <receipt.json> <plan.json>");
+        eprintln!("       rtt-planner simulate <plan.json> --state <current.json>");
+        eprintln!("       rtt-planner convert --to|--from executor <file.json> [out.json]");
+        eprintln!("       rtt-planner overlay <base.json> <env.json> -o <plan.json> [--manifests-dir <dir>] [options]");
+        eprintln!("       rtt-planner from-matrix <matrix.csv> -o <plan.json> [--manifests-dir <dir>] [options]");
+        eprintln!("       rtt-planner annotate-batch <plan.json> <batch> key=value... [--unsigned]");
+        eprintln!("       rtt-planner trace-route <routes.json> <from> <to> [--manifests-dir <dir>] [options]");
+        eprintln!("       rtt-planner endpoints <routes.json> [--output-format text|json]");
+        eprintln!("       rtt-planner invert <plan.json> <out.json>");
+        eprintln!("       rtt-planner expand-aggregates <plan.json> <sidecar.json> <out.json>");
+        eprintln!("       rtt-planner visualize <plan.json> -o <plan.html> [--keyring <keys.json>]");
+        eprintln!("       rtt-planner diff-state <current.json> <desired.json> -o <plan.json>");
+        eprintln!("       rtt-planner merge-plans <a.json> <b.json> -o <out.json> [--on-batch-conflict error|earliest|latest] [--identity-keys <k>]");
+        eprintln!("       rtt-planner e2e-selftest");
+        eprintln!("       rtt-planner serve --listen <addr:port> [--manifests-dir <dir>] [options] (serve feature)");
+        eprintln!("       rtt-planner fetch-shm <segment> <out.json> (shm feature)");
         eprintln!();
         eprintln!("Arguments:");
-        eprintln!("  routes.json      - Input routes file");
+        eprintln!("  routes.json      - Input routes file, or - for stdin");
         eprintln!("  manifests_dir    - Directory containing manifests");
-        eprintln!("  out_plan.json    - Output plan file");
+        eprintln!("  out_plan.json    - Output plan file, or - for stdout");
         eprintln!("  sign_key_b64     - Optional signing key (base64)");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --dropped-out <file>  - Write dropped routes and the reason for each");
+        eprintln!("  --annotate key=value  - Attach signed metadata to the plan (repeatable)");
+        eprintln!("  --batch-strategy <s>  - greedy (fill --batch-size) or balanced (even sizes)");
+        eprintln!("  --batch-size <n>      - Maximum routes per batch");
+        eprintln!("  --batches <n>         - Target batch count for balanced batching");
+        eprintln!("  --max-batches <n>     - Fail if `after` layering needs more than n batches");
+        eprintln!("  --optimize            - Enforce manifest capacities; with --max-batches, pack batches");
+        eprintln!("  --require-optimize    - --optimize, failing instead of falling back if the solver fails");
+        eprintln!("  --identity-keys <k>   - Fields that identify a route (default from,to)");
+        eprintln!("  --max-plan-bytes <n>  - Fail if the serialized plan exceeds n bytes");
+        eprintln!("  --max-endpoint-len <n> - Reject routes with an endpoint name longer than n");
+        eprintln!("  --endpoint-len-unit u - Measure --max-endpoint-len in bytes (default) or graphemes");
+        eprintln!("  --preserve-order      - Keep routes in input order in a single batch");
+        eprintln!("  --legacy-plan         - Write every route untagged in BATCH-1, overriding batching options");
+        eprintln!("  --stable-id           - Sort routes by route_id first, so reordering the input keeps plan_id");
+        eprintln!("  --otlp-endpoint <url> - Export run spans to an OTLP/HTTP collector (otlp feature)");
+        eprintln!("  --enable-feature <f>  - Include routes that require feature f (repeatable)");
+        eprintln!("  --weights <file>      - Merge from,to,weight rows onto routes; heavier batch first");
+        eprintln!("  --normalize-weights m - minmax, zscore or none (default): rescale weights for --optimize");
+        eprintln!("  --deprecations <file> - Warn on deprecated endpoints, fail past their removal date");
+        eprintln!("  --metadata-schema <f> - Require the route labels f lists for the --env <name> environment");
+        eprintln!("  --collapse-transitive - Drop routes implied by a path of routes with equal metadata");
+        eprintln!("  --keep-top <n>        - Plan only the n heaviest routes, ties by route_id; drop the rest");
+        eprintln!("  --stream              - Write the plan compactly as it is hashed (unsigned only)");
+        eprintln!("  --from-stdin-format f - Routes input as json (default) or ndjson, one route per line");
+        eprintln!("  --normalize           - Trim endpoint whitespace and report semantic vs cosmetic changes");
+        eprintln!("  --lockfile            - Fail if another run holds <out_plan.json>.lock");
+        eprintln!("  --signer ssh-agent    - Sign through $SSH_AUTH_SOCK instead of sign_key_b64");
+        eprintln!("  --key-comment <name>  - Comment of the agent's ed25519 key to sign with");
+        eprintln!("  --deterministic-signing - Omit signed_at so the same inputs and key give a byte-identical plan");
+        eprintln!("  --lock-wait <secs>    - Like --lockfile, but wait up to secs for the lock");
+        eprintln!("  --emit-per-batch <d>  - Also write one routes file per batch and an index to d");
+        eprintln!("  --receipt <file>      - Write a ledger receipt with a rollup hash of plan_id and signatures");
+        eprintln!("  --publish-shm <name>  - Also publish the plan to SHM segment name (shm feature); see fetch-shm");
+        eprintln!("  --aggregate-by labels.<l> - Collapse each batch's routes sharing label l into one; detail in <out>.aggregates.json");
+        eprintln!("  --format json|jwt     - Write the plan as JSON (default) or as a signed EdDSA JWT");
+        eprintln!("  --with-rollback <f>   - Also write the unsigned inverse plan, to sign and keep as the undo");
+        eprintln!("  --fail-on-empty       - Fail instead of writing a plan with no routes");
+        eprintln!("  --record-manifests-digest - Sign a digest of manifests_dir into the plan");
+        eprintln!("  --resolver none|dns   - Check that every endpoint resolves (default none)");
+        eprintln!("  --on-unresolved m     - fail (default) or warn on endpoints that do not resolve");
+        eprintln!("  --valid-for <age>     - Record valid_until, e.g. 7d after planning; verify rejects it later");
+        eprintln!("  --depends-on <id>     - Record a plan_id to apply first (repeatable); see verify --chain");
+        eprintln!("Environment:");
+        eprintln!("  RTT_ALLOWED_PATH_PREFIXES - Colon-separated absolute directories paths may point into");
         bail!("Invalid arguments");
     }
 
-    // Validate all input paths
-    let routes_path = validate_path(&args[1], "routes file")?;
-    let _manifests_dir = validate_path(&args[2], "manifests directory")?;
-    let out_path = validate_path(&args[3], "output file")?;
+    // Validate all input paths; `-` reads routes from stdin / writes the plan to stdout
+    let routes_path = (args[0] != "-")
+        .then(|| validate_path(&args[0], "routes file"))
+        .transpose()?;
+    let manifests_dir = validate_path(&args[1], "manifests directory")?;
+    let sign_key = args.get(3).map(String::as_str);
+    // Hold the output lock from before reading routes until the plan is written
+    let output = PlanOutput::prepare("Plan", &args[2], &opts, sign_key)?;
+    let inputs = Inputs::load(&opts, Some(&manifests_dir))?;
 
     // Load routes
-    let routes_content = fs::read_to_string(&routes_path)
-        .with_context(|| format!("Failed to read routes file: {:?}", routes_path))?;
+    let mut trace = telemetry::Trace::new();
+    let t = SystemTime::now();
+    let routes_content = match &routes_path {
+        Some(path) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read routes file: {:?}", path))?,
+        None => std::io::read_to_string(std::io::stdin())
+            .with_context(|| "Failed to read routes from stdin")?,
+    };
 
-    let routes: Routes = serde_json::from_str(&routes_content)
-        .with_context(|| "Failed to parse routes JSON")?;
+    let routes = input::parse_routes(&routes_content, opts.input_format)?;
+    trace.phase("load", t);
+    trace.attr("rtt.routes.input", routes.routes.len());
 
-    // Create plan
-    let mut plan = Plan {
-        plan_id: "sha256-PLACEHOLDER".to_string(),
-        routes_add: routes.routes.clone(),
-        routes_del: vec![],
-        order: vec!["BATCH-1".into()],
-        sign: None,
-    };
+    output.write(routes.routes, &opts, &inputs, sign_key, trace)
+}
 
-    // Compute plan hash
-    let plan_json = serde_json::to_vec(&plan)?;
-    let pid = hash_bytes(&plan_json);
-    plan.plan_id = pid.clone();
+/// Where a generated plan goes: the validated output paths of the plan
+/// options and, with `--lockfile`, the output lock, held until the plan is
+/// written. Every command that generates a plan writes it through here, so
+/// the output options mean the same in each.
+struct PlanOutput {
+    /// What the `[OK]` line calls the plan.
+    kind: &'static str,
+    out_path: Option<PathBuf>,
+    dropped_path: Option<PathBuf>,
+    batch_dir: Option<PathBuf>,
+    receipt_path: Option<PathBuf>,
+    rollback_path: Option<PathBuf>,
+    _lock: Option<lock::OutputLock>,
+}
 
-    // Write initial plan
-    fs::write(&out_path, serde_json::to_vec_pretty(&plan)?)
-        .with_context(|| format!("Failed to write output file: {:?}", out_path))?;
+impl PlanOutput {
+    /// Check the output options for a plan written to `out` (`-` for stdout)
+    /// and signed with `sign_key`, if given, then take the output lock.
+    fn prepare(kind: &'static str, out: &str, opts: &Options, sign_key: Option<&str>) -> Result<Self> {
+        #[cfg(not(feature = "otlp"))]
+        if opts.otlp_endpoint.is_some() {
+            bail!("--otlp-endpoint requires rtt-planner built with the otlp feature");
+        }
+        #[cfg(not(feature = "shm"))]
+        if opts.publish_shm.is_some() {
+            bail!("--publish-shm requires rtt-planner built with the shm feature");
+        }
+        if opts.publish_shm.is_some() && (opts.stream || opts.plan_format == jwt::PlanFormat::Jwt) {
+            bail!("--publish-shm publishes JSON plans; it cannot be combined with --stream or --format jwt");
+        }
+        if opts.ssh_agent_key.is_some() && sign_key.is_some() {
+            bail!("Pass either sign_key_b64 or --signer ssh-agent, not both");
+        }
+        if opts.stream && (sign_key.is_some() || opts.ssh_agent_key.is_some()) {
+            bail!("--stream writes unsigned plans; sign the written plan separately");
+        }
+        if opts.deterministic_signing && opts.valid_for.is_some() {
+            bail!("--valid-for records the planning time, so re-runs cannot be byte-identical with --deterministic-signing");
+        }
+        if opts.plan_format == jwt::PlanFormat::Jwt {
+            if sign_key.is_none() && opts.ssh_agent_key.is_none() {
+                bail!("--format jwt needs sign_key_b64 or --signer ssh-agent");
+            }
+            if opts.stream || opts.receipt.is_some() {
+                bail!("--format jwt cannot be combined with --stream or --receipt");
+            }
+        }
+        if opts.aggregate_by.is_some() && opts.receipt.is_some() {
+            bail!("--receipt records the written plan, so it cannot be combined with --aggregate-by; expand the aggregate first");
+        }
 
-    // Sign if key provided
-    if args.len() > 4 {
-        eprintln!("[INFO] Signing plan with provided key");
+        let out_path = (out != "-")
+            .then(|| validate_path(out, "output file"))
+            .transpose()?;
+        let dropped_path = opts
+            .dropped_out
+            .as_deref()
+            .map(|p| validate_path(p, "dropped routes file"))
+            .transpose()?;
+        let batch_dir = opts
+            .emit_per_batch
+            .as_deref()
+            .map(|p| validate_path(p, "batch directory"))
+            .transpose()?;
+        let receipt_path = opts
+            .receipt
+            .as_deref()
+            .map(|p| validate_path(p, "receipt file"))
+            .transpose()?;
+        let rollback_path = opts
+            .with_rollback
+            .as_deref()
+            .map(|p| validate_path(p, "rollback file"))
+            .transpose()?;
 
-        match safe_execute_signer(&args[4], &out_path) {
-            Ok(sig) => {
-                let mut signed_plan = plan;
-                signed_plan.sign = Some(Sign {
-                    alg: "ed25519".into(),
-                    key_id: "dev".into(),
-                    sig,
-                });
+        let lock = match (&out_path, opts.lockfile) {
+            (Some(path), true) => {
+                let wait = opts.lock_wait.map(|s| std::time::Duration::from_secs(s as u64));
+                Some(lock::acquire(path, wait)?)
+            }
+            (None, true) => bail!("--lockfile needs an output file, not stdout"),
+            _ => None,
+        };
+        Ok(Self { kind, out_path, dropped_path, batch_dir, receipt_path, rollback_path, _lock: lock })
+    }
 
-                fs::write(&out_path, serde_json::to_vec_pretty(&signed_plan)?)
-                    .with_context(|| "Failed to write signed plan")?;
+    /// Plan `routes`, then sign and write the plan and everything else the
+    /// options ask for.
+    fn write(self, routes: Vec<Route>, opts: &Options, inputs: &Inputs, sign_key: Option<&str>, mut trace: telemetry::Trace) -> Result<()> {
+        let out_path = &self.out_path;
+        let (mut plan, dropped) = build_plan(routes, opts, inputs, &mut trace)?;
+        if let Some(path) = &self.dropped_path {
+            fs::write(path, serde_json::to_vec_pretty(&prune::dropped_report(&dropped, &opts.identity_keys))?)
+                .with_context(|| format!("Failed to write dropped routes file: {:?}", path))?;
+        }
+
+        // Summarize before signing, so the written plan is the one signed; the
+        // summary names the detailed plan, which the rollback undoes
+        let mut sidecar = None;
+        let mut detailed = None;
+        if let Some(label) = &opts.aggregate_by {
+            let Some(out) = out_path else {
+                bail!("--aggregate-by writes a sidecar next to the plan, so it needs an output file");
+            };
+            let (aggregated, detail) = aggregate::aggregate_plan(&plan, label)?;
+            eprintln!(
+                "[INFO] Aggregated {} route(s) into {} by label {}",
+                plan.routes_add.len(),
+                aggregated.routes_add.len(),
+                label
+            );
+            detailed = Some(std::mem::replace(&mut plan, aggregated));
+            sidecar = Some((aggregate::sidecar_path(out), detail));
+        }
+
+        // Sign if a key or an agent key was given
+        let mut token = None;
+        if sign_key.is_some() || opts.ssh_agent_key.is_some() {
+            let t = SystemTime::now();
+            let signed_at = signing_time(opts);
+            let key_id = opts.ssh_agent_key.clone().unwrap_or_else(|| "dev".to_string());
+            // A JWT signs its JWS signing input instead of the canonical bytes
+            let jwt_input = (opts.plan_format == jwt::PlanFormat::Jwt)
+                .then(|| jwt::signing_input(&plan, &key_id, signed_at))
+                .transpose()?;
+            let payload = match &jwt_input {
+                Some(input) => input.clone().into_bytes(),
+                None => signed_bytes(&plan, signed_at)?,
+            };
+            let signed = if let Some(comment) = &opts.ssh_agent_key {
+                eprintln!("[INFO] Signing plan with SSH agent key {:?}", comment);
+                sshagent::sign_with_env_agent(comment, &payload)
+                    .map(|(sig, public_key)| {
+                        eprintln!("[INFO] Verify with: {}", public_key);
+                        (sig, comment.clone())
+                    })
+            } else {
+                eprintln!("[INFO] Signing plan with provided key");
+                // The signature covers the canonical bytes, not the pretty file. With
+                // stdout output the payload goes to a private temp file instead.
+                let payload_file = SigningPayload::write(out_path.as_deref(), &payload)?;
+                safe_execute_signer(sign_key.unwrap_or_default(), &payload_file.0).map(|sig| (sig, key_id))
+            };
+
+            match (signed, &jwt_input) {
+                (Ok((sig, _)), Some(input)) => {
+                    token = Some(jwt::encode(input, &sig)?);
+                    eprintln!("[OK] Plan signed as a JWT");
+                }
+                (Err(e), Some(_)) => bail!("Signing failed: {}; --format jwt cannot write an unsigned token", e),
+                (Ok((sig, key_id)), None) => {
+                    plan.sign = Some(Sign {
+                        alg: "ed25519".into(),
+                        key_id,
+                        sig,
+                        signed_at,
+                    });
+                    eprintln!("[OK] Plan signed successfully");
+                }
+                (Err(e), None) => {
+                    eprintln!("[WARN] Signing failed: {}", e);
+                    eprintln!("[WARN] Plan written without signature");
+                }
+            }
+            trace.phase("sign", t);
+        }
+        trace.attr("rtt.signed", plan.sign.is_some() || token.is_some());
 
-                eprintln!("[OK] Plan signed successfully");
+        // Write plan, then its ID and name; on stdout they move to stderr so
+        // they don't corrupt the plan stream
+        let t = SystemTime::now();
+        if opts.stream {
+            let mut out: Box<dyn Write> = match out_path {
+                Some(path) => Box::new(std::io::BufWriter::new(
+                    fs::File::create(path).with_context(|| format!("Failed to create output file: {:?}", path))?,
+                )),
+                None => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+            };
+            let written = stream::write_plan(&mut plan, &mut out)?;
+            if out_path.is_none() {
+                out.write_all(b"\n")?;
             }
-            Err(e) => {
-                eprintln!("[WARN] Signing failed: {}", e);
-                eprintln!("[WARN] Plan written without signature");
+            out.flush()?;
+            drop(out);
+            trace.attr("rtt.plan_id", plan.plan_id.as_str());
+            if let Err(e) = check_plan_size(written, opts.max_plan_bytes) {
+                if let Some(path) = &out_path {
+                    let _ = fs::remove_file(path);
+                }
+                return Err(e);
+            }
+        } else {
+            let plan_json = match &token {
+                Some(token) => token.clone().into_bytes(),
+                None => serde_json::to_vec_pretty(&plan)?,
+            };
+            check_plan_size(plan_json.len(), opts.max_plan_bytes)?;
+            #[cfg(feature = "shm")]
+            if let Some(name) = &opts.publish_shm {
+                let seq = shm::publish(name, &plan.plan_id, &plan_json)?;
+                eprintln!("[OK] Plan published to segment {} (frame {})", name, seq);
+            }
+            match out_path {
+                Some(path) => fs::write(path, plan_json)
+                    .with_context(|| format!("Failed to write output file: {:?}", path))?,
+                None => {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(&plan_json)?;
+                    stdout.write_all(b"\n")?;
+                    stdout.flush()?;
+                }
+            }
+        }
+        match out_path {
+            Some(path) => {
+                println!("{}", plan.plan_id);
+                println!("{}", plan.name);
+                eprintln!("[OK] {} generated: {:?}", self.kind, path);
+            }
+            None => {
+                eprintln!("{}", plan.plan_id);
+                eprintln!("{}", plan.name);
+                eprintln!("[OK] {} generated: <stdout>", self.kind);
+            }
+        }
+        if let Some(dir) = &self.batch_dir {
+            let index = emit::write_per_batch(&plan, dir)?;
+            eprintln!("[OK] Wrote {} batch file(s) to {:?}", index.batches.len(), dir);
+        }
+        if let Some(path) = &self.receipt_path {
+            let receipt = receipt::write(&plan, path)?;
+            eprintln!("[OK] Receipt written: {:?} (rollup {})", path, receipt.rollup);
+        }
+        if let Some((path, sidecar)) = &sidecar {
+            aggregate::write_sidecar(sidecar, path)?;
+            eprintln!("[OK] Aggregate detail written: {:?}", path);
+        }
+        if let Some(path) = &self.rollback_path {
+            let rollback = invert::invert_plan(detailed.as_ref().unwrap_or(&plan))?;
+            fs::write(path, serde_json::to_vec_pretty(&rollback)?)
+                .with_context(|| format!("Failed to write rollback file: {:?}", path))?;
+            eprintln!("[OK] Rollback written unsigned: {:?} ({})", path, rollback.plan_id);
+        }
+        trace.phase("write", t);
+
+        #[cfg(feature = "otlp")]
+        if let Some(endpoint) = &opts.otlp_endpoint {
+            if let Err(e) = telemetry::export(endpoint, &trace) {
+                eprintln!("[WARN] Telemetry export failed: {}", e);
             }
         }
+
+        Ok(())
     }
+}
 
-    // Print plan ID
-    println!("{}", pid);
-    eprintln!("[OK] Plan generated: {:?}", out_path);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    fn sample_plan() -> Plan {
+        Plan {
+            plan_id: "sha256-PLACEHOLDER".into(),
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), ..Default::default() }],
+            order: vec!["BATCH-1".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_signer_missing_vs_failing() {
+        let missing = ["./no-such-dir/rtt-sign", "rtt-sign-does-not-exist"];
+        let err = run_signer(&missing, "key", "plan.payload").unwrap_err();
+        assert!(matches!(&err, SignerError::NotFound { tried } if tried.len() == 2), "{:?}", err);
+        assert!(err.to_string().starts_with("No signer binary found in any candidate path (tried ./no-such-dir/rtt-sign: "));
+
+        // A stub that runs and rejects the key is reported as such, even
+        // with missing candidates after it
+        let stub = std::env::temp_dir().join(format!("rtt-sign-stub-{}", std::process::id()));
+        {
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o755);
+            let mut file = options.open(&stub).unwrap();
+            file.write_all(b"#!/bin/sh\necho \"key is not readable\" >&2\nexit 3\n").unwrap();
+        }
+        // A child forked by another test thread while the stub was open for
+        // writing holds it busy (ETXTBSY) until that child execs
+        let stub_path = stub.to_string_lossy().into_owned();
+        let err = (0..100)
+            .find_map(|_| match run_signer(&[missing[0], &stub_path, missing[1]], "key", "plan.payload").unwrap_err() {
+                SignerError::NotFound { tried } if tried.iter().any(|t| t.contains("Text file busy")) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    None
+                }
+                err => Some(err),
+            })
+            .expect("signer stub stayed busy");
+        fs::remove_file(&stub).unwrap();
+        assert!(matches!(&err, SignerError::Exited { signer, .. } if *signer == stub_path), "{:?}", err);
+        assert_eq!(err.to_string(), format!("Signer {} exited with error: key is not readable", stub_path));
+    }
+
+    #[test]
+    fn test_signing_payload_is_private_and_removed() {
+        let first = SigningPayload::write(None, b"canonical").unwrap();
+        let second = SigningPayload::write(None, b"canonical").unwrap();
+        assert_ne!(first.0, second.0);
+        assert_eq!(fs::read(&first.0).unwrap(), b"canonical");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&first.0).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let path = first.0.clone();
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_validate_path_allowed_prefixes() {
+        // Without an allow-list, absolute paths are rejected as before
+        assert!(validate_path_in("plans/out.json", "output file", &[]).is_ok());
+        let err = validate_path_in("/var/lib/noa/plans/out.json", "output file", &[]).unwrap_err();
+        assert!(err.to_string().contains("Absolute paths not allowed"));
+
+        let allowed = [PathBuf::from("/var/lib/noa/plans")];
+        let ok = validate_path_in("/var/lib/noa/plans/2024/out.json", "output file", &allowed).unwrap();
+        assert_eq!(ok, PathBuf::from("/var/lib/noa/plans/2024/out.json"));
+        assert!(validate_path_in("/var/lib/noa/plans", "batch directory", &allowed).is_ok());
+
+        // Siblings and traversal out of the prefix stay rejected
+        for escape in ["/var/lib/noa/plans-old/out.json", "/var/lib/noa/out.json", "/var/lib/noa/plans/../keys/dev.key"] {
+            assert!(validate_path_in(escape, "output file", &allowed).is_err(), "{}", escape);
+        }
+        assert!(validate_path_in("../out.json", "output file", &allowed).is_err());
+    }
+
+    #[test]
+    fn test_rehash_stale_plan() {
+        let mut plan = sample_plan();
+        plan.sign = Some(Sign { alg: "ed25519".into(), key_id: "dev".into(), sig: "stale".into(), ..Default::default() });
+
+        let path = std::env::temp_dir().join(format!("rtt-rehash-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_vec_pretty(&plan).unwrap()).unwrap();
+
+        assert!(rehash_file(&path, true).unwrap());
+
+        let fixed: Plan = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_ne!(fixed.plan_id, "sha256-PLACEHOLDER");
+        assert_eq!(fixed.plan_id, compute_plan_id(&fixed).unwrap());
+        assert!(fixed.sign.is_none());
+        assert!(!rehash_file(&path, false).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_plan_id_ignores_id_and_sign() {
+        let mut plan = sample_plan();
+        let pid = compute_plan_id(&plan).unwrap();
+        plan.plan_id = pid.clone();
+        plan.sign = Some(Sign { alg: "ed25519".into(), key_id: "dev".into(), sig: "x".into(), ..Default::default() });
+        assert_eq!(compute_plan_id(&plan).unwrap(), pid);
+        assert_eq!(rehash_plan(&mut plan).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_plan() {
+        let mut trace = telemetry::Trace::new();
+        let inputs = Inputs::load(&Options::default(), None).unwrap();
+        let dedup_to_nothing = vec![Route { from: "a".into(), to: "a".into(), ..Default::default() }];
+        for routes in [vec![], dedup_to_nothing.clone()] {
+            let (plan, _) = build_plan(routes, &Options::default(), &inputs, &mut trace).unwrap();
+            assert!(plan.routes_add.is_empty() && plan.order.is_empty());
+            assert_eq!(canonical_bytes(&plan).unwrap(), br#"{"order":[],"routes_add":[],"routes_del":[]}"#);
+            assert_eq!(plan.plan_id, "sha256-a8dc1c67c2e67beac8a28aa091793fd5e174a7f2bad78f6c8ef61303af1e5c98");
+        }
+
+        let opts = Options { fail_on_empty: true, ..Default::default() };
+        let err = build_plan(dedup_to_nothing, &opts, &inputs, &mut trace).err().unwrap();
+        assert!(err.to_string().contains("--fail-on-empty"), "{}", err);
+    }
+
+    #[test]
+    fn test_validate_unassigned_route() {
+        let mut plan = sample_plan();
+        plan.validate().unwrap();
+        let tagged = Route { from: "b".into(), to: "c".into(), batch: Some("BATCH-1".into()), ..Default::default() };
+        plan.routes_add.push(tagged);
+        let err = plan.validate().unwrap_err().to_string();
+        assert_eq!(err, "Invalid plan: route a -> b is not assigned to any batch");
+
+        plan.routes_add[0].batch = Some("BATCH-1".into());
+        plan.validate().unwrap();
+        plan.order.clear();
+        assert!(plan.validate().unwrap_err().to_string().contains("is in batch BATCH-1, which is not in order"));
+    }
+
+    #[test]
+    fn test_validate_batch_missing_from_order() {
+        let route = |from: &str, batch: &str| Route { from: from.into(), to: "z".into(), batch: Some(batch.into()), ..Default::default() };
+        let mut plan = Plan {
+            routes_add: vec![route("a", "BATCH-1"), route("b", "BATCH-3"), route("c", "BATCH-2")],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        let err = plan.validate().unwrap_err().to_string();
+        assert_eq!(err, "Invalid plan: route b -> z is in batch BATCH-3, which is not in order");
+
+        plan.order.push("BATCH-2".into());
+        plan.routes_add.remove(1);
+        assert_eq!(plan.validate().unwrap_err().to_string(), "Invalid plan: batch BATCH-2 is listed twice in order");
+    }
+
+    #[test]
+    fn test_validate_deleted_prerequisite() {
+        let route = |from: &str, to: &str, batch: &str, after: &[&str]| Route {
+            from: from.into(),
+            to: to.into(),
+            batch: Some(batch.into()),
+            after: after.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        };
+        let order = vec!["BATCH-1".into(), "BATCH-2".into()];
+
+        // An added route cannot depend on a deleted one
+        let plan = Plan {
+            routes_add: vec![route("b", "c", "BATCH-2", &["a->b"])],
+            routes_del: vec![route("a", "b", "BATCH-1", &[])],
+            order: order.clone(),
+            ..Default::default()
+        };
+        let err = plan.validate().unwrap_err().to_string();
+        assert!(err.contains("route a->b is deleted but b -> c, which is added, runs after it"), "{}", err);
+
+        // Unless it is replaced no later than the dependent needs it
+        let mut replaced = Plan {
+            routes_add: vec![route("a", "b", "BATCH-1", &[]), route("b", "c", "BATCH-2", &["a->b"])],
+            routes_del: vec![route("a", "b", "BATCH-1", &[])],
+            order: order.clone(),
+            ..Default::default()
+        };
+        replaced.validate().unwrap();
+        replaced.routes_add[0].batch = Some("BATCH-2".into());
+        replaced.validate().unwrap();
+        replaced.routes_add[1].batch = Some("BATCH-1".into());
+        assert!(replaced.validate().is_err());
+
+        // A deleted prerequisite must go with or after its dependent
+        let mut plan = Plan {
+            routes_del: vec![route("a", "b", "BATCH-1", &[]), route("b", "c", "BATCH-2", &["a->b"])],
+            order,
+            ..Default::default()
+        };
+        let err = plan.validate().unwrap_err().to_string();
+        assert!(err.contains("delete it in or after BATCH-2"), "{}", err);
+        plan.routes_del[0].batch = Some("BATCH-2".into());
+        plan.validate().unwrap();
+
+        // Which is the order inversion produces for an added chain
+        let chain = Plan {
+            routes_add: vec![route("a", "b", "BATCH-1", &[]), route("b", "c", "BATCH-2", &["a->b"])],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        invert::invert_plan(&chain).unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn test_stable_id_ignores_input_order() {
+        let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+        let routes = vec![route("a", "b"), route("c", "d"), route("b", "c"), route("d", "e")];
+        let mut reordered = routes.clone();
+        reordered.reverse();
+        reordered.swap(0, 1);
+        let plan_id = |routes: &[Route], stable_id: bool| {
+            let opts = Options {
+                stable_id,
+                batching: batch::BatchConfig { batch_size: Some(3), ..Default::default() },
+                ..Default::default()
+            };
+            let inputs = Inputs::load(&opts, None).unwrap();
+            build_plan(routes.to_vec(), &opts, &inputs, &mut telemetry::Trace::new()).unwrap().0.plan_id
+        };
+
+        assert_ne!(plan_id(&routes, false), plan_id(&reordered, false));
+        assert_eq!(plan_id(&routes, true), plan_id(&reordered, true));
+        // A genuine change still changes the id
+        let mut changed = reordered.clone();
+        changed[0].to = "z".into();
+        assert_ne!(plan_id(&changed, true), plan_id(&routes, true));
+
+        let opts = Options { stable_id: true, preserve_order: true, ..Default::default() };
+        let inputs = Inputs::load(&opts, None).unwrap();
+        assert!(build_plan(routes, &opts, &inputs, &mut telemetry::Trace::new()).is_err());
+    }
+
+    #[test]
+    fn test_legacy_plan_is_one_batch() {
+        let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+        let routes = vec![
+            Route { weight: Some(5.0), batch: Some("BATCH-7".into()), ..route("a", "b") },
+            Route { after: vec!["a->b".into()], ..route("b", "c") },
+            Route { weight: Some(9.0), ..route("c", "d") },
+        ];
+        let build = |batching: batch::BatchConfig| {
+            let opts = Options { legacy_plan: true, batching, ..Default::default() };
+            let inputs = Inputs::load(&opts, None).unwrap();
+            build_plan(routes.clone(), &opts, &inputs, &mut telemetry::Trace::new()).unwrap().0
+        };
+
+        let plan = build(batch::BatchConfig { batch_size: Some(1), ..Default::default() });
+        assert_eq!(plan.order, vec!["BATCH-1"]);
+        assert!(plan.routes_add.iter().all(|r| r.batch.is_none()));
+        // Weights do not reorder the routes
+        assert_eq!(plan.routes_add.iter().map(|r| r.from.as_str()).collect::<String>(), "abc");
+        // The same routes give the same plan_id, whatever the batching flags
+        assert_eq!(plan.plan_id, build(batch::BatchConfig::default()).plan_id);
+        assert_eq!(plan.plan_id, build(batch::BatchConfig { batches: Some(3), ..Default::default() }).plan_id);
+        assert_eq!(plan.plan_id, compute_plan_id(&plan).unwrap());
+
+        let opts = Options { legacy_plan: true, valid_for: Some(60), ..Default::default() };
+        assert!(build_plan(routes.clone(), &opts, &Inputs::load(&opts, None).unwrap(), &mut telemetry::Trace::new()).is_err());
+
+        // An empty legacy plan still has its one batch
+        let opts = Options { legacy_plan: true, ..Default::default() };
+        let (empty, _) = build_plan(Vec::new(), &opts, &Inputs::load(&opts, None).unwrap(), &mut telemetry::Trace::new()).unwrap();
+        assert_eq!(empty.order, vec!["BATCH-1"]);
+        empty.validate().unwrap();
+
+        // --legacy-plan overrides --preserve-order, and input order is kept
+        let opts = Options { legacy_plan: true, preserve_order: true, ..Default::default() };
+        let (kept, _) = build_plan(routes.clone(), &opts, &Inputs::load(&opts, None).unwrap(), &mut telemetry::Trace::new()).unwrap();
+        assert_eq!(kept.order, vec!["BATCH-1"]);
+        assert!(kept.routes_add.iter().all(|r| r.batch.is_none()));
+        assert_eq!(kept.routes_add.iter().map(|r| r.from.as_str()).collect::<String>(), "abc");
+    }
+
+    #[test]
+    fn test_plan_name_is_stable() {
+        let pid = compute_plan_id(&sample_plan()).unwrap();
+        let name = plan_name(&pid);
+        assert_eq!(name, plan_name(&compute_plan_id(&sample_plan()).unwrap()));
+        assert_eq!(name.len(), "plan-".len() + 8);
+        assert_ne!(name, plan_name("sha256-other"));
+
+        // The name is not part of the hashed content
+        let mut plan = sample_plan();
+        plan.name = name;
+        assert_eq!(compute_plan_id(&plan).unwrap(), pid);
+    }
 }
@@ -0,0 +1,117 @@
+//! Symbol manifests
+//!
+//! The manifests directory holds one `*.json` manifest per symbol, shaped
+//! like `.rtt/manifests`: a `symbol` object whose `saddr` names the endpoint
+//! with its version, e.g. `rtt://obs/extension/logger@1.3.0#ndjson`. Only the
+//! fields the planner uses are read. A symbol may declare `capacity`, the
+//! most planned routes that may touch it; `--optimize` enforces these.
+//!
+//! `--record-manifests-digest` stores a digest of the manifests in the plan
+//! as `manifests_digest`, so `verify --manifests-dir` can later detect a
+//! plan applied against manifests that have since changed.
+
+use crate::hash_bytes;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Manifest {
+    symbol: Symbol,
+}
+
+#[derive(Deserialize)]
+struct Symbol {
+    saddr: String,
+    #[serde(default)]
+    capacity: Option<u32>,
+}
+
+/// The route endpoint for a symbol address: the address without its
+/// `@version`, keeping any `#fragment`.
+pub fn endpoint(saddr: &str) -> String {
+    match saddr.split_once('@') {
+        Some((base, rest)) => match rest.split_once('#') {
+            Some((_, fragment)) => format!("{}#{}", base, fragment),
+            None => base.to_string(),
+        },
+        None => saddr.to_string(),
+    }
+}
+
+/// The `*.json` manifests in `dir`, sorted by path.
+fn manifest_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read manifests directory: {:?}", dir))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+    Ok(paths)
+}
+
+/// Digest of the manifests in `dir`: the hash of one `<file name> <file
+/// hash>` line per manifest, in name order. Other files are ignored.
+pub fn digest(dir: &Path) -> Result<String> {
+    let mut lines = String::new();
+    for path in manifest_paths(dir)? {
+        let content = fs::read(&path).with_context(|| format!("Failed to read manifest: {:?}", path))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        lines.push_str(&format!("{} {}\n", name, hash_bytes(&content)));
+    }
+    Ok(hash_bytes(lines.as_bytes()))
+}
+
+/// Capacities declared by the manifests in `dir`, by endpoint.
+pub fn load_capacities(dir: &Path) -> Result<BTreeMap<String, u32>> {
+    let mut capacities = BTreeMap::new();
+    for path in manifest_paths(dir)? {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read manifest: {:?}", path))?;
+        let manifest: Manifest = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse manifest: {:?}", path))?;
+        if let Some(cap) = manifest.symbol.capacity {
+            capacities.insert(endpoint(&manifest.symbol.saddr), cap);
+        }
+    }
+    Ok(capacities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prune::{admit_capacities, DropReason};
+    use crate::Route;
+
+    #[test]
+    fn test_manifest_capacity_limits_routes() {
+        let dir = std::env::temp_dir().join(format!("rtt-manifests-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = |saddr: &str, cap: Option<u32>| {
+            let mut symbol = serde_json::json!({ "saddr": saddr, "type": "api", "direction": "provider" });
+            if let Some(cap) = cap {
+                symbol["capacity"] = cap.into();
+            }
+            serde_json::json!({ "symbol": symbol }).to_string()
+        };
+        fs::write(dir.join("metrics.json"), manifest("rtt://core/api/metrics@1.0.0", Some(2))).unwrap();
+        fs::write(dir.join("logger.json"), manifest("rtt://obs/extension/logger@1.3.0#ndjson", None)).unwrap();
+        fs::write(dir.join("README.md"), "not a manifest").unwrap();
+        let capacities = load_capacities(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(capacities, BTreeMap::from([("rtt://core/api/metrics".to_string(), 2)]));
+        assert_eq!(endpoint("rtt://obs/extension/logger@1.3.0#ndjson"), "rtt://obs/extension/logger#ndjson");
+
+        let routes: Vec<Route> = ["ui", "cli", "batch"]
+            .iter()
+            .map(|from| Route { from: format!("rtt://{}", from), to: "rtt://core/api/metrics".into(), ..Default::default() })
+            .collect();
+        let mut dropped = Vec::new();
+        let (kept, _) = admit_capacities(routes, &capacities, &Default::default(), Default::default(), &mut dropped).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].reason, DropReason::OverCapacity);
+    }
+}
@@ -0,0 +1,124 @@
+//! Routes from adjacency matrices
+//!
+//! `rtt-planner from-matrix <matrix.csv> -o <plan.json|-> [--manifests-dir <dir>] [options]`
+//! reads a labeled CSV adjacency matrix and plans its routes with the usual
+//! plan options, writing the plan as the main command would (signed only
+//! through `--signer ssh-agent`); `--optimize` enforces the capacities of
+//! the manifests in `--manifests-dir`. The header row names the destination endpoints after one
+//! leading cell, which is ignored; each following row names its source
+//! endpoint in the first cell:
+//!
+//! ```text
+//! ,a,b,c
+//! a,0,1,
+//! b,,0,2.5
+//! c,1,,0
+//! ```
+//!
+//! A cell of `1` is a route from the row to the column; any other number is
+//! a route with that weight, which like any weight must not be negative.
+//! Empty and `0` cells are no route. Routes come out row by row, columns
+//! left to right. Blank lines and `#` comments are ignored.
+
+use crate::{parse_options, validate_path, Inputs, PlanOutput, Route};
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs;
+
+pub fn parse_matrix(content: &str) -> Result<Vec<Route>> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        bail!("Matrix is empty");
+    };
+    let columns: Vec<&str> = header.split(',').map(str::trim).skip(1).collect();
+    let mut seen = HashSet::new();
+    if let Some(name) = columns.iter().find(|c| c.is_empty() || !seen.insert(**c)) {
+        bail!("Matrix header has an empty or repeated endpoint {:?}", name);
+    }
+
+    let mut rows = HashSet::new();
+    let mut routes = Vec::new();
+    for (n, line) in lines {
+        let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (from, cells) = cells.split_first().expect("split yields at least one field");
+        if from.is_empty() || !rows.insert(*from) {
+            bail!("Matrix line {}: missing or repeated row endpoint {:?}", n, from);
+        }
+        if cells.len() > columns.len() {
+            bail!("Matrix line {}: {} cells for {} columns", n, cells.len(), columns.len());
+        }
+        for (to, cell) in columns.iter().zip(cells) {
+            let value: f64 = match *cell {
+                "" => continue,
+                cell => cell
+                    .parse()
+                    .ok()
+                    .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+                    .with_context(|| format!("Matrix line {}: invalid cell for {}: {}", n, to, cell))?,
+            };
+            if value == 0.0 {
+                continue;
+            }
+            routes.push(Route {
+                from: from.to_string(),
+                to: to.to_string(),
+                weight: (value != 1.0).then_some(value),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(routes)
+}
+
+pub fn cmd_from_matrix(args: &[String]) -> Result<()> {
+    let (mut out, mut manifests_dir) = (None, None);
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => out = Some(it.next().context("-o requires a value")?.clone()),
+            "--manifests-dir" => manifests_dir = Some(it.next().context("--manifests-dir requires a value")?.clone()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let (positional, opts) = parse_options(&rest)?;
+    let (Some(out), [matrix]) = (out, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner from-matrix <matrix.csv> -o <plan.json|-> [--manifests-dir <dir>] [options]");
+        bail!("Invalid arguments");
+    };
+    let output = PlanOutput::prepare("Matrix plan", &out, &opts, None)?;
+    let path = validate_path(matrix, "matrix file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read matrix file: {:?}", path))?;
+    let routes = parse_matrix(&content)?;
+    eprintln!("[INFO] Read {} route(s) from the matrix", routes.len());
+    let manifests_dir = manifests_dir.map(|d| validate_path(&d, "manifests directory")).transpose()?;
+    let inputs = Inputs::load(&opts, manifests_dir.as_deref())?;
+
+    output.write(routes, &opts, &inputs, None, crate::telemetry::Trace::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_three_by_three_matrix() {
+        let matrix = "# lab topology\n,a,b,c\na,0,1,\nb,,0,2.5\n\nc,1,3,0\n";
+        let routes = parse_matrix(matrix).unwrap();
+        let got: Vec<_> = routes.iter().map(|r| (r.from.as_str(), r.to.as_str(), r.weight)).collect();
+        assert_eq!(got, vec![("a", "b", None), ("b", "c", Some(2.5)), ("c", "a", None), ("c", "b", Some(3.0))]);
+
+        // Short rows leave the remaining columns empty
+        assert_eq!(parse_matrix(",a,b\na,,1\nb\n").unwrap().len(), 1);
+        assert!(parse_matrix(",a,b\na,x\n").unwrap_err().to_string().contains("invalid cell for a: x"));
+        assert!(parse_matrix(",a,b\na,-2\n").unwrap_err().to_string().contains("Matrix line 2: invalid cell for a: -2"));
+        assert!(parse_matrix(",a,b\na,0,1,1\n").is_err());
+        assert!(parse_matrix(",a,b\na,1\na,1\n").unwrap_err().to_string().contains("repeated"));
+        assert!(parse_matrix(",a,,b\n").is_err());
+        assert!(parse_matrix(",a,a\n").is_err());
+    }
+}
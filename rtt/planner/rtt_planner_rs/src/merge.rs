@@ -0,0 +1,282 @@
+//! Plan merging
+//!
+//! `rtt-planner merge-plans <a.json> <b.json> -o <out.json>
+//! [--on-batch-conflict error|earliest|latest] [--identity-keys <k>]`
+//! combines two plans into one. Batch orders are interleaved: batches of `b`
+//! missing from `a` go right after the batch that precedes them in `b`, and
+//! common batches must come in the same relative order in both. Routes are
+//! the same route when their identity (`--identity-keys`, by default
+//! `from,to`) matches; a route that only one plan has is kept as it is, and
+//! a route both plans have identically is kept once.
+//!
+//! A route both plans have in different batches, or with different content,
+//! is a conflict. `error` (the default) rejects the merge and lists every
+//! conflict; `earliest` and `latest` keep the copy in the batch that comes
+//! first or last in the merged order (`a`'s or `b`'s copy respectively when
+//! the batches are the same), and warn. Either way a route must still land
+//! in a later batch than every route it lists in `after`, and the merged
+//! plan must validate. `depends_on` lists are combined; annotations, notes
+//! and expiry are not carried over. The merged plan comes out unsigned, to
+//! be signed like any other.
+
+use crate::batch::{route_key, untagged_key};
+use crate::identity::IdentityKeys;
+use crate::verify::load_plan;
+use crate::{compute_plan_id, plan_name, validate_path, Plan, Route};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BatchConflict {
+    #[default]
+    Error,
+    Earliest,
+    Latest,
+}
+
+impl FromStr for BatchConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(Self::Error),
+            "earliest" => Ok(Self::Earliest),
+            "latest" => Ok(Self::Latest),
+            _ => bail!("Unknown batch conflict strategy: {} (expected error, earliest or latest)", s),
+        }
+    }
+}
+
+/// A route the two plans put in different batches or give different content.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub route: String,
+    pub first: String,
+    pub second: String,
+    /// Whether the copies differ in more than their batch.
+    pub content: bool,
+    /// The batch the route was merged into; `None` under `error`.
+    pub kept: Option<String>,
+}
+
+fn merge_orders(a: &[String], b: &[String]) -> Result<Vec<String>> {
+    let mut order = a.to_vec();
+    let mut cursor = 0;
+    for batch in b {
+        match order.iter().position(|o| o == batch) {
+            Some(i) if i < cursor => bail!("Plans order batches {} and {} differently", order[cursor - 1], batch),
+            Some(i) => cursor = i + 1,
+            None => {
+                order.insert(cursor, batch.clone());
+                cursor += 1;
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// Merge one route list of each plan into `order`, tagging every route
+/// with its batch.
+fn merge_routes(
+    a: (&[Route], &[String]),
+    b: (&[Route], &[String]),
+    order: &[String],
+    strategy: BatchConflict,
+    identity: &IdentityKeys,
+    conflicts: &mut Vec<Conflict>,
+) -> Vec<Route> {
+    let position = |batch: &str| order.iter().position(|o| o == batch);
+    let mut merged: Vec<Route> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (routes, plan_order) in [a, b] {
+        for route in routes {
+            let batch = route.batch.clone().or_else(|| plan_order.first().cloned());
+            let route = Route { batch, ..route.clone() };
+            let id = identity.route_id(&route);
+            let Some(&i) = seen.get(&id) else {
+                seen.insert(id, merged.len());
+                merged.push(route);
+                continue;
+            };
+            let content = untagged_key(&merged[i]) != untagged_key(&route);
+            if !content && merged[i].batch == route.batch {
+                continue;
+            }
+            let (first, second) = (merged[i].batch.clone().unwrap_or_default(), route.batch.clone().unwrap_or_default());
+            let take_second = match strategy {
+                BatchConflict::Error => false,
+                BatchConflict::Earliest => position(&second) < position(&first),
+                BatchConflict::Latest => position(&second) >= position(&first),
+            };
+            let kept = (strategy != BatchConflict::Error).then(|| (if take_second { &second } else { &first }).clone());
+            let key = route_key(&route);
+            if take_second {
+                merged[i] = route;
+            }
+            conflicts.push(Conflict { route: key, first, second, content, kept });
+        }
+    }
+    merged
+}
+
+fn describe(c: &Conflict) -> String {
+    let content = if c.content { ", content differs" } else { "" };
+    format!("{} ({} vs {}{})", c.route, c.first, c.second, content)
+}
+
+/// Every route must come in a later batch than its prerequisites.
+fn check_after(routes: &[Route], order: &[String]) -> Result<()> {
+    let position = |r: &Route| r.batch.as_ref().and_then(|b| order.iter().position(|o| o == b));
+    let mut by_key: HashMap<String, Vec<&Route>> = HashMap::new();
+    for route in routes {
+        by_key.entry(route_key(route)).or_default().push(route);
+    }
+    for route in routes {
+        for key in &route.after {
+            if let Some(prereq) = by_key.get(key).into_iter().flatten().find(|p| position(p) >= position(route)) {
+                bail!(
+                    "Merged route {} is in {}, but its prerequisite {} is in {}",
+                    route_key(route),
+                    route.batch.as_deref().unwrap_or_default(),
+                    key,
+                    prereq.batch.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merge `b` into `a`, returning the unsigned plan and the batch conflicts
+/// met along the way.
+pub fn merge_plans(a: &Plan, b: &Plan, strategy: BatchConflict, identity: &IdentityKeys) -> Result<(Plan, Vec<Conflict>)> {
+    let order = merge_orders(&a.order, &b.order)?;
+    let mut conflicts = Vec::new();
+    let routes_add = merge_routes((&a.routes_add, &a.order), (&b.routes_add, &b.order), &order, strategy, identity, &mut conflicts);
+    let routes_del = merge_routes((&a.routes_del, &a.order), (&b.routes_del, &b.order), &order, strategy, identity, &mut conflicts);
+    if strategy == BatchConflict::Error && !conflicts.is_empty() {
+        let list: Vec<String> = conflicts.iter().map(describe).collect();
+        bail!("{} route(s) conflict between the two plans: {}", conflicts.len(), list.join(", "));
+    }
+    check_after(&routes_add, &order)?;
+
+    let mut depends_on = a.depends_on.clone();
+    depends_on.extend(b.depends_on.iter().filter(|id| !a.depends_on.contains(id)).cloned());
+    let mut plan = Plan { routes_add, routes_del, order, depends_on, ..Default::default() };
+    plan.validate()?;
+    plan.plan_id = compute_plan_id(&plan)?;
+    plan.name = plan_name(&plan.plan_id);
+    Ok((plan, conflicts))
+}
+
+pub fn cmd_merge_plans(args: &[String]) -> Result<()> {
+    let (mut out, mut strategy, mut identity) = (None, BatchConflict::default(), IdentityKeys::default());
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => out = Some(it.next().context("-o requires a value")?),
+            "--on-batch-conflict" => strategy = it.next().context("--on-batch-conflict requires a value")?.parse()?,
+            "--identity-keys" => identity = it.next().context("--identity-keys requires a value")?.parse()?,
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg),
+        }
+    }
+    let (Some(out), [a, b]) = (out, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner merge-plans <a.json> <b.json> -o <out.json> [--on-batch-conflict error|earliest|latest] [--identity-keys <k>]");
+        bail!("Invalid arguments");
+    };
+    let a = load_plan(&validate_path(a, "plan file")?)?;
+    let b = load_plan(&validate_path(b, "plan file")?)?;
+    let out = validate_path(out, "output file")?;
+
+    let (plan, conflicts) = merge_plans(&a, &b, strategy, &identity)?;
+    for c in &conflicts {
+        eprintln!("[WARN] Conflicting copies of {}; kept the one in {}", describe(c), c.kept.as_deref().unwrap_or_default());
+    }
+    fs::write(&out, serde_json::to_vec_pretty(&plan)?).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    println!("{}", plan.plan_id);
+    eprintln!("[OK] Merged plan written unsigned: {:?} ({} route(s))", out, plan.routes_add.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_conflict_strategies() {
+        let route = |from: &str, batch: &str| Route { from: from.into(), to: "z".into(), batch: Some(batch.into()), ..Default::default() };
+        let batches = |n: usize| (1..=n).map(|i| format!("BATCH-{}", i)).collect::<Vec<_>>();
+        let a = Plan { routes_add: vec![route("a", "BATCH-1"), route("b", "BATCH-2")], order: batches(2), ..Default::default() };
+        let b = Plan { routes_add: vec![route("a", "BATCH-3"), route("c", "BATCH-1")], order: batches(3), ..Default::default() };
+
+        let error = |merged: Result<(Plan, Vec<Conflict>)>| match merged {
+            Err(e) => e.to_string(),
+            Ok(_) => panic!("merge unexpectedly succeeded"),
+        };
+        let identity = IdentityKeys::default();
+        let err = error(merge_plans(&a, &b, BatchConflict::Error, &identity));
+        assert!(err.contains("a->z (BATCH-1 vs BATCH-3)"), "{}", err);
+
+        let batch_of = |plan: &Plan, from: &str| plan.routes_add.iter().find(|r| r.from == from).and_then(|r| r.batch.clone());
+        let (earliest, conflicts) = merge_plans(&a, &b, BatchConflict::Earliest, &identity).unwrap();
+        assert_eq!(earliest.order, batches(3));
+        assert_eq!(earliest.routes_add.len(), 3);
+        assert_eq!(batch_of(&earliest, "a").as_deref(), Some("BATCH-1"));
+        let conflict = Conflict { route: "a->z".into(), first: "BATCH-1".into(), second: "BATCH-3".into(), content: false, kept: Some("BATCH-1".into()) };
+        assert_eq!(conflicts, vec![conflict]);
+
+        let (latest, _) = merge_plans(&a, &b, BatchConflict::Latest, &identity).unwrap();
+        assert_eq!(batch_of(&latest, "a").as_deref(), Some("BATCH-3"));
+        assert_eq!(batch_of(&latest, "c").as_deref(), Some("BATCH-1"));
+        assert_ne!(earliest.plan_id, latest.plan_id);
+
+        // Resolving may not move a route to or before its prerequisite
+        let after = Route { after: vec!["a->z".into()], ..route("d", "BATCH-2") };
+        let a = Plan { routes_add: vec![route("a", "BATCH-1"), after], order: batches(2), ..Default::default() };
+        assert!(merge_plans(&a, &b, BatchConflict::Earliest, &identity).is_ok());
+        let err = error(merge_plans(&a, &b, BatchConflict::Latest, &identity));
+        assert!(err.contains("d->z is in BATCH-2, but its prerequisite a->z is in BATCH-3"), "{}", err);
+
+        let reversed = Plan { order: vec!["BATCH-2".into(), "BATCH-1".into()], ..Default::default() };
+        assert!(error(merge_plans(&a, &reversed, BatchConflict::Error, &identity)).contains("differently"));
+    }
+
+    #[test]
+    fn test_merge_matches_route_identity() {
+        let route = |from: &str, env: &str| Route {
+            from: from.into(),
+            to: "z".into(),
+            labels: [("env".to_string(), env.to_string())].into(),
+            batch: Some("BATCH-1".into()),
+            ..Default::default()
+        };
+        let order = vec!["BATCH-1".to_string()];
+        let a = Plan { routes_add: vec![route("a", "prod")], order: order.clone(), ..Default::default() };
+        let b = Plan { routes_add: vec![route("a", "dev")], order: order.clone(), ..Default::default() };
+
+        // The same route with different content is a conflict, not two routes
+        let identity = IdentityKeys::default();
+        let Err(err) = merge_plans(&a, &b, BatchConflict::Error, &identity) else { panic!("content conflict merged") };
+        assert!(err.to_string().contains("a->z (BATCH-1 vs BATCH-1, content differs)"), "{}", err);
+        let env = |plan: &Plan| plan.routes_add.iter().map(|r| r.labels["env"].clone()).collect::<Vec<_>>();
+        let (latest, conflicts) = merge_plans(&a, &b, BatchConflict::Latest, &identity).unwrap();
+        assert_eq!((env(&latest), conflicts.len()), (vec!["dev".to_string()], 1));
+        assert_eq!(env(&merge_plans(&a, &b, BatchConflict::Earliest, &identity).unwrap().0), vec!["prod"]);
+
+        // With the label in the identity they are two routes
+        let by_env: IdentityKeys = "from,to,labels.env".parse().unwrap();
+        let (merged, conflicts) = merge_plans(&a, &b, BatchConflict::Error, &by_env).unwrap();
+        assert_eq!((merged.routes_add.len(), conflicts.len()), (2, 0));
+
+        // A route one plan deletes cannot be a prerequisite the other adds after
+        let dependent = Route { after: vec!["p->z".into()], ..route("d", "prod") };
+        let a = Plan { routes_add: vec![dependent], order: order.clone(), ..Default::default() };
+        let b = Plan { routes_del: vec![route("p", "prod")], order, ..Default::default() };
+        let Err(err) = merge_plans(&a, &b, BatchConflict::Error, &identity) else { panic!("deleted prerequisite merged") };
+        assert!(err.to_string().contains("route p->z is deleted but d -> z"), "{}", err);
+    }
+}
@@ -0,0 +1,89 @@
+//! Endpoint normalization
+//!
+//! `--normalize` trims surrounding whitespace from route endpoints before
+//! anything else looks at them. Each changed route is reported as
+//! cosmetic when it stays distinct from every other route, or semantic
+//! when trimming makes it share an identity with another route, so dedup
+//! will collapse them and the plan differs from an unnormalized run.
+
+use crate::identity::IdentityKeys;
+use crate::Route;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalizationKind {
+    Cosmetic,
+    Semantic,
+}
+
+#[derive(Debug)]
+pub struct Normalization {
+    pub original_from: String,
+    pub original_to: String,
+    pub from: String,
+    pub to: String,
+    pub kind: NormalizationKind,
+}
+
+/// Normalize endpoints in place and report every route that changed.
+pub fn normalize_routes(routes: &mut [Route], identity: &IdentityKeys) -> Vec<Normalization> {
+    let count = |routes: &[Route]| {
+        let mut counts: HashMap<Vec<Option<String>>, usize> = HashMap::new();
+        for route in routes {
+            *counts.entry(identity.key(route)).or_default() += 1;
+        }
+        counts
+    };
+    let before = count(routes);
+
+    let mut changed = Vec::new();
+    for (i, route) in routes.iter_mut().enumerate() {
+        let (from, to) = (route.from.trim().to_string(), route.to.trim().to_string());
+        if from != route.from || to != route.to {
+            let key_before = identity.key(route);
+            let original_from = std::mem::replace(&mut route.from, from);
+            let original_to = std::mem::replace(&mut route.to, to);
+            changed.push((i, key_before, original_from, original_to));
+        }
+    }
+
+    let after = count(routes);
+    changed
+        .into_iter()
+        .map(|(i, key_before, original_from, original_to)| {
+            let route = &routes[i];
+            // More routes share the new identity than shared the old one
+            let kind = if after[&identity.key(route)] > before[&key_before] {
+                NormalizationKind::Semantic
+            } else {
+                NormalizationKind::Cosmetic
+            };
+            Normalization { original_from, original_to, from: route.from.clone(), to: route.to.clone(), kind }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_semantic_vs_cosmetic() {
+        let mut routes = vec![route("a", "b"), route(" a", "b "), route("c ", "d"), route("e", "f")];
+        let report = normalize_routes(&mut routes, &IdentityKeys::default());
+        assert_eq!(report.len(), 2);
+
+        // ` a -> b ` now duplicates `a -> b`
+        assert_eq!((report[0].original_from.as_str(), report[0].original_to.as_str()), (" a", "b "));
+        assert_eq!((report[0].from.as_str(), report[0].to.as_str()), ("a", "b"));
+        assert_eq!(report[0].kind, NormalizationKind::Semantic);
+        // `c ` -> `c` collides with nothing
+        assert_eq!(report[1].from, "c");
+        assert_eq!(report[1].kind, NormalizationKind::Cosmetic);
+        assert_eq!(routes[2].from, "c");
+    }
+}
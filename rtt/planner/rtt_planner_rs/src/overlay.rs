@@ -0,0 +1,196 @@
+//! Route overlays
+//!
+//! `rtt-planner overlay <base.json> <env.json> -o <plan.json> [--manifests-dir <dir>] [options]`
+//! merges an environment overlay onto a base routes file and plans the
+//! result with the usual plan options, writing it as the main command
+//! would (signed only through `--signer ssh-agent`); `--optimize` enforces
+//! the capacities of the manifests in `--manifests-dir`. Both files have the routes shape;
+//! overlay routes are matched to base routes by `(from, to)`:
+//!
+//! - An overlay route with `"remove": true` removes the matching base
+//!   routes. Removing a route the base does not have is an error.
+//! - An overlay route matching base routes overrides their metadata. Labels
+//!   merge key by key with the overlay winning; `requires`, `after`,
+//!   `bidirectional`, `weight` and `priority` are replaced when the overlay
+//!   sets them.
+//! - Any other overlay route is added after the base routes, as written.
+//!
+//! Base order is kept. An overlay listing the same `(from, to)` twice is
+//! rejected, since which entry should win is ambiguous.
+
+use crate::{input, parse_options, validate_path, Inputs, PlanOutput, Route};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::time::SystemTime;
+
+#[derive(Deserialize, Debug)]
+pub struct OverlayRoute {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub remove: bool,
+    #[serde(default)]
+    pub labels: Option<BTreeMap<String, String>>,
+    #[serde(default)]
+    pub requires: Option<Vec<String>>,
+    #[serde(default)]
+    pub after: Option<Vec<String>>,
+    #[serde(default)]
+    pub bidirectional: Option<bool>,
+    #[serde(default)]
+    pub weight: Option<f64>,
+    #[serde(default)]
+    pub priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct Overlay {
+    routes: Vec<OverlayRoute>,
+}
+
+impl OverlayRoute {
+    fn apply_to(&self, route: &mut Route) {
+        if let Some(labels) = &self.labels {
+            route.labels.extend(labels.clone());
+        }
+        if let Some(requires) = &self.requires {
+            route.requires = requires.clone();
+        }
+        if let Some(after) = &self.after {
+            route.after = after.clone();
+        }
+        if let Some(bidirectional) = self.bidirectional {
+            route.bidirectional = bidirectional;
+        }
+        if self.weight.is_some() {
+            route.weight = self.weight;
+        }
+        if self.priority.is_some() {
+            route.priority = self.priority;
+        }
+    }
+
+    fn into_route(self) -> Route {
+        let mut route = Route { from: self.from.clone(), to: self.to.clone(), ..Default::default() };
+        self.apply_to(&mut route);
+        route
+    }
+}
+
+/// Merge `overlay` onto `base` by the rules in the module docs.
+pub fn merge(mut base: Vec<Route>, overlay: Vec<OverlayRoute>) -> Result<Vec<Route>> {
+    let mut seen = HashSet::new();
+    let mut added = Vec::new();
+    for entry in overlay {
+        if !seen.insert((entry.from.clone(), entry.to.clone())) {
+            bail!("Overlay lists {} -> {} more than once", entry.from, entry.to);
+        }
+        let matches = |r: &Route| r.from == entry.from && r.to == entry.to;
+        if entry.remove {
+            let before = base.len();
+            base.retain(|r| !matches(r));
+            if base.len() == before {
+                bail!("Overlay removes {} -> {}, which the base does not have", entry.from, entry.to);
+            }
+        } else if base.iter().any(matches) {
+            base.iter_mut().filter(|r| matches(r)).for_each(|r| entry.apply_to(r));
+        } else {
+            added.push(entry.into_route());
+        }
+    }
+    base.extend(added);
+    Ok(base)
+}
+
+pub fn cmd_overlay(args: &[String]) -> Result<()> {
+    let (mut out, mut manifests_dir) = (None, None);
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => out = Some(it.next().context("-o requires a value")?.clone()),
+            "--manifests-dir" => manifests_dir = Some(it.next().context("--manifests-dir requires a value")?.clone()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let (positional, opts) = parse_options(&rest)?;
+    let (Some(out), [base, overlay]) = (out, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner overlay <base.json> <env.json> -o <plan.json|-> [--manifests-dir <dir>] [options]");
+        bail!("Invalid arguments");
+    };
+    let output = PlanOutput::prepare("Overlay plan", &out, &opts, None)?;
+    let read = |path: &str, what: &str| {
+        let path = validate_path(path, what)?;
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}: {:?}", what, path))
+    };
+    let base = input::parse_routes(&read(base, "base routes file")?, opts.input_format)?;
+    let overlay: Overlay = serde_json::from_str(&read(overlay, "overlay file")?)
+        .with_context(|| "Failed to parse overlay JSON")?;
+    let manifests_dir = manifests_dir.map(|d| validate_path(&d, "manifests directory")).transpose()?;
+    let inputs = Inputs::load(&opts, manifests_dir.as_deref())?;
+
+    let mut trace = crate::telemetry::Trace::new();
+    let t = SystemTime::now();
+    let routes = merge(base.routes, overlay.routes)?;
+    trace.phase("overlay", t);
+    output.write(routes, &opts, &inputs, None, trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Vec<Route> {
+        let route = |from: &str, to: &str, env: &str| Route {
+            from: from.into(),
+            to: to.into(),
+            labels: BTreeMap::from([("env".into(), env.into()), ("owner".into(), "core".into())]),
+            ..Default::default()
+        };
+        vec![route("a", "b", "base"), route("b", "c", "base"), route("c", "d", "base")]
+    }
+
+    fn overlay(json: &str) -> Vec<OverlayRoute> {
+        serde_json::from_str::<Overlay>(json).unwrap().routes
+    }
+
+    fn keys(routes: &[Route]) -> Vec<String> {
+        routes.iter().map(|r| format!("{}->{}", r.from, r.to)).collect()
+    }
+
+    #[test]
+    fn test_overlay_adds() {
+        let merged = merge(base(), overlay(r#"{"routes": [{"from": "d", "to": "e", "weight": 2.0}]}"#)).unwrap();
+        assert_eq!(keys(&merged), vec!["a->b", "b->c", "c->d", "d->e"]);
+        assert_eq!(merged[3].weight, Some(2.0));
+        assert!(merged[3].labels.is_empty());
+    }
+
+    #[test]
+    fn test_overlay_overrides() {
+        let merged = merge(
+            base(),
+            overlay(r#"{"routes": [{"from": "b", "to": "c", "labels": {"env": "prod"}, "requires": ["canary"]}]}"#),
+        )
+        .unwrap();
+        assert_eq!(keys(&merged), vec!["a->b", "b->c", "c->d"]);
+        assert_eq!(merged[1].labels["env"], "prod");
+        // Unmentioned labels survive the merge
+        assert_eq!(merged[1].labels["owner"], "core");
+        assert_eq!(merged[1].requires, vec!["canary"]);
+        assert_eq!(merged[0].labels["env"], "base");
+    }
+
+    #[test]
+    fn test_overlay_removes() {
+        let merged = merge(base(), overlay(r#"{"routes": [{"from": "a", "to": "b", "remove": true}]}"#)).unwrap();
+        assert_eq!(keys(&merged), vec!["b->c", "c->d"]);
+
+        let err = merge(base(), overlay(r#"{"routes": [{"from": "x", "to": "y", "remove": true}]}"#)).unwrap_err();
+        assert!(err.to_string().contains("does not have"), "{}", err);
+        let twice = r#"{"routes": [{"from": "a", "to": "b"}, {"from": "a", "to": "b", "remove": true}]}"#;
+        assert!(merge(base(), overlay(twice)).is_err());
+    }
+}
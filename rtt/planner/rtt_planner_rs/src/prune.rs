@@ -0,0 +1,419 @@
+//! Route pruning passes and the dropped-routes report
+//!
+//! Every pass that removes routes from the plan records what it removed and
+//! why, so `--dropped-out` can explain why a plan is smaller than its input.
+
+use crate::batch::{route_key, SOLVER_NODE_BUDGET};
+use crate::identity::IdentityKeys;
+use crate::weights::{objective_weights, WeightNormalization, DEFAULT_WEIGHT};
+use crate::Route;
+use anyhow::{bail, Result};
+use rtt_solver::{oversubscribed_endpoints, Control, GraphRoute, RouteGraphConfig, RouteRepair, SolveOptions, Solver, Status};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DropReason {
+    /// Same identity (by default `(from, to)`) as an earlier route.
+    Duplicate,
+    /// `from` and `to` are the same endpoint.
+    SelfLoop,
+    /// The route `requires` a feature that was not enabled.
+    FeatureDisabled,
+    /// Implied by a longer path of routes with the same metadata
+    /// (`--collapse-transitive`).
+    Transitive,
+    /// Generated reverse of a bidirectional route that disagrees with an
+    /// explicit route in the opposite direction.
+    Conflict,
+    /// Not admitted within the endpoint capacities declared in manifests
+    /// (`--optimize`).
+    OverCapacity,
+    /// Not among the `--keep-top` heaviest routes.
+    OutsideTop,
+    /// Lists in `after` a route that `--keep-top` dropped.
+    PrerequisiteDropped,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DroppedRoute {
+    #[serde(flatten)]
+    pub route: Route,
+    pub reason: DropReason,
+}
+
+/// A dropped route as written to `--dropped-out`, led by its `route_id`.
+#[derive(Serialize)]
+pub struct DroppedEntry<'a> {
+    pub route_id: String,
+    #[serde(flatten)]
+    pub dropped: &'a DroppedRoute,
+}
+
+pub fn dropped_report<'a>(dropped: &'a [DroppedRoute], identity: &IdentityKeys) -> Vec<DroppedEntry<'a>> {
+    dropped.iter().map(|d| DroppedEntry { route_id: identity.route_id(&d.route), dropped: d }).collect()
+}
+
+/// Drop routes that require a feature missing from `enabled`.
+pub fn filter_features(routes: Vec<Route>, enabled: &BTreeSet<String>, dropped: &mut Vec<DroppedRoute>) -> Vec<Route> {
+    let (kept, disabled): (Vec<_>, Vec<_>) = routes
+        .into_iter()
+        .partition(|r| r.requires.iter().all(|f| enabled.contains(f)));
+    dropped.extend(disabled.into_iter().map(|route| DroppedRoute { route, reason: DropReason::FeatureDisabled }));
+    kept
+}
+
+/// Drop self-loops and routes whose identity repeats an earlier route,
+/// keeping the first occurrence and the input order of the survivors.
+pub fn dedup_routes(routes: Vec<Route>, identity: &IdentityKeys, dropped: &mut Vec<DroppedRoute>) -> Vec<Route> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::with_capacity(routes.len());
+
+    for route in routes {
+        let reason = if route.from == route.to {
+            DropReason::SelfLoop
+        } else if !seen.insert(identity.key(&route)) {
+            DropReason::Duplicate
+        } else {
+            kept.push(route);
+            continue;
+        };
+        dropped.push(DroppedRoute { route, reason });
+    }
+    kept
+}
+
+/// Keep the `n` heaviest routes, ranked by weight (unweighted routes count
+/// as `DEFAULT_WEIGHT`) with ties going to the smaller `route_id`, then drop
+/// every kept route that lists a dropped route in `after`, transitively.
+/// Survivors keep their input order. Routes must already be deduplicated
+/// under `identity`.
+pub fn keep_top(routes: Vec<Route>, n: usize, identity: &IdentityKeys, dropped: &mut Vec<DroppedRoute>) -> Vec<Route> {
+    if routes.len() <= n {
+        return routes;
+    }
+    let mut ranked: Vec<(usize, f64, String)> =
+        routes.iter().enumerate().map(|(i, r)| (i, r.weight.unwrap_or(DEFAULT_WEIGHT), identity.route_id(r))).collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.2.cmp(&b.2)));
+    let mut top: HashSet<usize> = ranked.into_iter().take(n).map(|(i, _, _)| i).collect();
+
+    // A kept route cannot run without its prerequisites
+    let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, route) in routes.iter().enumerate() {
+        by_key.entry(route_key(route)).or_default().push(i);
+    }
+    let mut orphaned = HashSet::new();
+    loop {
+        let missing: Vec<usize> = top
+            .iter()
+            .copied()
+            .filter(|&i| routes[i].after.iter().flat_map(|key| by_key.get(key)).flatten().any(|p| !top.contains(p)))
+            .collect();
+        if missing.is_empty() {
+            break;
+        }
+        for i in missing {
+            top.remove(&i);
+            orphaned.insert(i);
+        }
+    }
+
+    let mut kept = Vec::with_capacity(top.len());
+    for (i, route) in routes.into_iter().enumerate() {
+        if top.contains(&i) {
+            kept.push(route);
+        } else {
+            let reason = if orphaned.contains(&i) { DropReason::PrerequisiteDropped } else { DropReason::OutsideTop };
+            dropped.push(DroppedRoute { route, reason });
+        }
+    }
+    kept
+}
+
+/// Everything about a route other than its endpoints and batch.
+pub fn metadata(route: &Route) -> (&BTreeMap<String, String>, &[String], Option<u64>) {
+    (&route.labels, &route.requires, route.weight.map(f64::to_bits))
+}
+
+/// Whether `routes[skip]` is implied by a path of other live routes that all
+/// carry the same metadata as it.
+fn has_bypass(routes: &[Route], live: &[bool], adjacency: &HashMap<&str, Vec<usize>>, skip: usize) -> bool {
+    let edge = &routes[skip];
+    let meta = metadata(edge);
+    let mut seen = HashSet::from([edge.from.as_str()]);
+    let mut queue = VecDeque::from([edge.from.as_str()]);
+    while let Some(node) = queue.pop_front() {
+        for &j in adjacency.get(node).into_iter().flatten() {
+            if j == skip || !live[j] || metadata(&routes[j]) != meta {
+                continue;
+            }
+            let next = routes[j].to.as_str();
+            if next == edge.to {
+                return true;
+            }
+            if seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+/// Drop routes implied by transit: `a->c` goes when `a->b->...->c` exists
+/// over routes with identical labels, feature requirements and weight.
+/// Routes are considered in input order against the already reduced graph,
+/// so reachability is preserved even when routes form cycles.
+pub fn collapse_transitive(routes: Vec<Route>, dropped: &mut Vec<DroppedRoute>) -> Vec<Route> {
+    let mut adjacency: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, route) in routes.iter().enumerate() {
+        adjacency.entry(&route.from).or_default().push(i);
+    }
+    let mut live = vec![true; routes.len()];
+    for i in 0..routes.len() {
+        if has_bypass(&routes, &live, &adjacency, i) {
+            live[i] = false;
+        }
+    }
+
+    let mut kept = Vec::with_capacity(routes.len());
+    for (route, live) in routes.into_iter().zip(live) {
+        if live {
+            kept.push(route);
+        } else {
+            dropped.push(DroppedRoute { route, reason: DropReason::Transitive });
+        }
+    }
+    kept
+}
+
+/// Admit the most routes that fit the endpoint `capacities`, counting
+/// routes in either direction and keeping `after` prerequisites of admitted
+/// routes. Among those, heavier routes win, by their weights after
+/// `normalization`. Ties keep higher-`priority` routes, then earlier ones. Solver
+/// variables are named by `route_id`, so routes must already be deduplicated
+/// under `identity`. The search gets `SOLVER_NODE_BUDGET` nodes, and
+/// running out of them is an error. When routes had to go, also returns
+/// the repair: the ids of the dropped routes, which are as few as possible
+/// since weights never outweigh admission, and the capacities that would
+/// admit every route. The model is only solved when some endpoint is over
+/// capacity, and then once.
+pub fn admit_capacities(
+    routes: Vec<Route>,
+    capacities: &BTreeMap<String, u32>,
+    identity: &IdentityKeys,
+    normalization: WeightNormalization,
+    dropped: &mut Vec<DroppedRoute>,
+) -> Result<(Vec<Route>, Option<RouteRepair>)> {
+    let route_ids: Vec<String> = routes.iter().map(|r| identity.route_id(r)).collect();
+    let config = RouteGraphConfig { capacities: capacities.clone(), ..Default::default() };
+    let weights = objective_weights(&routes, normalization, config.admit_priority);
+    let mut ids: HashMap<String, Vec<String>> = HashMap::new();
+    for (route, id) in routes.iter().zip(&route_ids) {
+        ids.entry(format!("{}->{}", route.from, route.to)).or_default().push(id.clone());
+    }
+    // Unknown prerequisites are left for batching to report
+    let graph: Vec<GraphRoute> = routes
+        .iter()
+        .zip(route_ids)
+        .zip(weights)
+        .map(|((route, id), weight)| GraphRoute {
+            id,
+            from: route.from.clone(),
+            to: route.to.clone(),
+            rtt: 0.0,
+            weight,
+            after: route.after.iter().flat_map(|key| ids.get(key)).flatten().cloned().collect(),
+            priority: route.priority.unwrap_or(0) as f64,
+        })
+        .collect();
+    let relax = oversubscribed_endpoints(&graph, &config);
+    if relax.is_empty() {
+        return Ok((routes, None));
+    }
+
+    let mut solver = Solver::new();
+    let vars = solver.ingest_route_graph(&graph, &config)?;
+    let opts = SolveOptions { max_nodes: Some(SOLVER_NODE_BUDGET), ..Default::default() };
+    let solution = solver.solve_with_options(&opts, |_| Control::Continue)?;
+    if solution.status == Status::BudgetExhausted {
+        bail!(
+            "Capacity admission of {} route(s) found no answer within {} search nodes; plan fewer routes or without --optimize",
+            routes.len(),
+            SOLVER_NODE_BUDGET
+        );
+    }
+    let mut kept = Vec::with_capacity(routes.len());
+    let mut drop = Vec::new();
+    for ((route, var), graph_route) in routes.into_iter().zip(vars).zip(graph) {
+        if solution.is_selected(var) {
+            kept.push(route);
+        } else {
+            drop.push(graph_route.id);
+            dropped.push(DroppedRoute { route, reason: DropReason::OverCapacity });
+        }
+    }
+    Ok((kept, Some(RouteRepair { drop, relax })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(from: &str, to: &str) -> Route {
+        Route { from: from.into(), to: to.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_dedup_records_reasons() {
+        let mut dropped = Vec::new();
+        let kept = dedup_routes(
+            vec![route("a", "b"), route("a", "a"), route("b", "c"), route("a", "b")],
+            &IdentityKeys::default(),
+            &mut dropped,
+        );
+
+        assert_eq!(kept.len(), 2);
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[0].reason, DropReason::SelfLoop);
+        assert_eq!(dropped[1].reason, DropReason::Duplicate);
+        assert_eq!(dropped[1].route.to, "b");
+
+        let json = serde_json::to_value(&dropped).unwrap();
+        assert_eq!(json[0]["reason"], "self-loop");
+        assert_eq!(json[1]["from"], "a");
+        assert_eq!(json[1]["reason"], "duplicate");
+    }
+
+    #[test]
+    fn test_label_in_identity_keeps_routes_distinct() {
+        let labeled = |env: &str| {
+            let mut r = route("a", "b");
+            r.labels.insert("env".into(), env.into());
+            r
+        };
+        let routes = vec![labeled("prod"), labeled("staging")];
+
+        let mut dropped = Vec::new();
+        let kept = dedup_routes(routes.clone(), &IdentityKeys::default(), &mut dropped);
+        assert_eq!((kept.len(), dropped.len()), (1, 1));
+
+        let mut dropped = Vec::new();
+        let keys = "from,to,labels.env".parse().unwrap();
+        let kept = dedup_routes(routes, &keys, &mut dropped);
+        assert_eq!((kept.len(), dropped.len()), (2, 0));
+    }
+
+    #[test]
+    fn test_keep_top_by_weight() {
+        let weighted = |from: &str, weight: Option<f64>| Route { weight, ..route(from, "z") };
+        let routes = vec![
+            weighted("a", Some(1.0)),
+            weighted("b", Some(5.0)),
+            weighted("c", None),
+            weighted("d", Some(3.0)),
+            weighted("e", Some(5.0)),
+            weighted("f", Some(3.0)),
+        ];
+        let identity = IdentityKeys::default();
+        let kept_froms = |n: usize, routes: Vec<Route>, dropped: &mut Vec<DroppedRoute>| -> Vec<String> {
+            keep_top(routes, n, &identity, dropped).into_iter().map(|r| r.from).collect()
+        };
+
+        // b and e tie at 5; d and f tie at 3 and the smaller route_id wins
+        let mut dropped = Vec::new();
+        assert!(identity.route_id(&routes[3]) < identity.route_id(&routes[5]));
+        let kept = kept_froms(3, routes.clone(), &mut dropped);
+        assert_eq!(kept, vec!["b", "d", "e"]);
+        assert_eq!(dropped.len(), 3);
+        assert!(dropped.iter().all(|d| d.reason == DropReason::OutsideTop));
+
+        // Unweighted c ranks with a at the default weight, above a lighter route
+        let light = vec![weighted("c", None), weighted("g", Some(0.5))];
+        assert_eq!(kept_froms(1, light, &mut Vec::new()), vec!["c"]);
+
+        // A kept route whose prerequisite is dropped goes too, transitively
+        let mut chained = routes.clone();
+        chained[1].after = vec!["a->z".into()];
+        chained[4].after = vec!["b->z".into()];
+        let mut dropped = Vec::new();
+        assert_eq!(kept_froms(3, chained, &mut dropped), vec!["d"]);
+        let orphaned: Vec<&str> =
+            dropped.iter().filter(|d| d.reason == DropReason::PrerequisiteDropped).map(|d| d.route.from.as_str()).collect();
+        assert_eq!(orphaned, vec!["b", "e"]);
+
+        // Input order does not change which routes survive
+        let mut reversed = routes.clone();
+        reversed.reverse();
+        let mut again = kept_froms(3, reversed, &mut Vec::new());
+        again.sort();
+        assert_eq!(again, kept);
+        assert_eq!(kept_froms(10, routes, &mut Vec::new()).len(), 6);
+    }
+
+    #[test]
+    fn test_disabled_feature_is_dropped() {
+        let mut gated = route("a", "c");
+        gated.requires = vec!["feature-x".into(), "feature-y".into()];
+        let routes = vec![route("a", "b"), gated];
+
+        let mut dropped = Vec::new();
+        let enabled = BTreeSet::from(["feature-x".to_string()]);
+        let kept = filter_features(routes.clone(), &enabled, &mut dropped);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(dropped[0].reason, DropReason::FeatureDisabled);
+        let report = serde_json::to_value(&dropped).unwrap();
+        assert_eq!(report[0]["to"], "c");
+        assert_eq!(report[0]["reason"], "feature-disabled");
+
+        let enabled = BTreeSet::from(["feature-x".to_string(), "feature-y".to_string()]);
+        assert_eq!(filter_features(routes, &enabled, &mut Vec::new()).len(), 2);
+    }
+
+    #[test]
+    fn test_collapse_transitive_respects_metadata() {
+        let graph = || vec![route("a", "b"), route("b", "c"), route("a", "c"), route("c", "d")];
+
+        let mut dropped = Vec::new();
+        let kept = collapse_transitive(graph(), &mut dropped);
+        assert_eq!(kept.len(), 3);
+        assert_eq!((dropped[0].route.from.as_str(), dropped[0].route.to.as_str()), ("a", "c"));
+        assert_eq!(dropped[0].reason, DropReason::Transitive);
+
+        // A labeled shortcut is not implied by the unlabeled path
+        let mut routes = graph();
+        routes[2].labels.insert("env".into(), "prod".into());
+        let mut dropped = Vec::new();
+        assert_eq!(collapse_transitive(routes, &mut dropped).len(), 4);
+        assert!(dropped.is_empty());
+
+        // In a cycle, only one of two edges that imply each other goes
+        let cycle = vec![route("a", "b"), route("b", "a"), route("a", "c"), route("b", "c")];
+        let mut dropped = Vec::new();
+        assert_eq!(collapse_transitive(cycle, &mut dropped).len(), 3);
+    }
+
+    #[test]
+    fn test_admit_capacities_prefers_priority() {
+        let caps = BTreeMap::from([("gw".to_string(), 1)]);
+        let mut urgent = route("b", "gw");
+        urgent.priority = Some(10);
+        let mut dropped = Vec::new();
+        let (kept, repair) = admit_capacities(vec![route("a", "gw"), urgent], &caps, &IdentityKeys::default(), WeightNormalization::None, &mut dropped).unwrap();
+        assert_eq!(kept[0].from, "b");
+        let repair = repair.unwrap();
+        assert_eq!(repair.relax, BTreeMap::from([("gw".to_string(), 2)]));
+        assert_eq!(repair.drop, vec![IdentityKeys::default().route_id(&route("a", "gw"))]);
+        assert_eq!(dropped[0].route.from, "a");
+
+        // Without priorities the earlier route is kept
+        let mut dropped = Vec::new();
+        let (kept, _) = admit_capacities(vec![route("a", "gw"), route("b", "gw")], &caps, &IdentityKeys::default(), WeightNormalization::None, &mut dropped).unwrap();
+        assert_eq!(kept[0].from, "a");
+
+        // The report names dropped routes by route_id
+        let report = serde_json::to_value(dropped_report(&dropped, &IdentityKeys::default())).unwrap();
+        assert_eq!(report[0]["route_id"], IdentityKeys::default().route_id(&route("b", "gw")).as_str());
+        assert_eq!(report[0]["reason"], "over-capacity");
+    }
+}
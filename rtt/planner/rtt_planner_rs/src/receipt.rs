@@ -0,0 +1,157 @@
+//! Plan receipts for external ledgers
+//!
+//! `--receipt <file>` writes, next to the signed plan, a compact record for
+//! anchoring in an append-only audit log:
+//!
+//! ```json
+//! {"plan_id": "sha256-...", "signatures": [{"key_id": "dev", "signed_at": 1700000000, "sha256": "sha256-..."}],
+//!  "issued_at": 1700000005, "rollup": "sha256-..."}
+//! ```
+//!
+//! Each signature digest is the SHA-256 of the signature's compact,
+//! key-sorted JSON, as stored in the plan. `rollup` is the SHA-256 of the
+//! receipt's own compact, key-sorted JSON without `rollup`, so one hash
+//! commits to the plan content, every signature and the issue time. Anyone
+//! holding the plan can recompute all of it; `verify-receipt` does.
+
+use crate::{compute_plan_id, hash_bytes, unix_now, validate_path, verify::load_plan, Plan, Sign};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SignatureDigest {
+    pub key_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_at: Option<u64>,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Receipt {
+    pub plan_id: String,
+    pub signatures: Vec<SignatureDigest>,
+    pub issued_at: u64,
+    pub rollup: String,
+}
+
+fn signature_digest(sign: &Sign) -> Result<SignatureDigest> {
+    let bytes = serde_json::to_vec(&serde_json::to_value(sign)?)?;
+    Ok(SignatureDigest { key_id: sign.key_id.clone(), signed_at: sign.signed_at, sha256: hash_bytes(&bytes) })
+}
+
+/// The hash `rollup` must hold for `receipt`.
+fn rollup(receipt: &Receipt) -> Result<String> {
+    let mut value = serde_json::to_value(receipt)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.remove("rollup");
+    }
+    Ok(hash_bytes(&serde_json::to_vec(&value)?))
+}
+
+/// Build the receipt for `plan`, issued at `issued_at`.
+pub fn build(plan: &Plan, issued_at: u64) -> Result<Receipt> {
+    let signatures = plan.all_signatures().map(signature_digest).collect::<Result<_>>()?;
+    let mut receipt = Receipt { plan_id: plan.plan_id.clone(), signatures, issued_at, rollup: String::new() };
+    receipt.rollup = rollup(&receipt)?;
+    Ok(receipt)
+}
+
+pub fn write(plan: &Plan, path: &Path) -> Result<Receipt> {
+    let receipt = build(plan, unix_now())?;
+    fs::write(path, serde_json::to_vec_pretty(&receipt)?).with_context(|| format!("Failed to write receipt: {:?}", path))?;
+    Ok(receipt)
+}
+
+/// Check that `receipt` was issued for exactly this plan and its signatures.
+pub fn check(receipt: &Receipt, plan: &Plan) -> Result<()> {
+    if rollup(receipt)? != receipt.rollup {
+        bail!("Receipt rollup does not match its contents");
+    }
+    let plan_id = compute_plan_id(plan)?;
+    if receipt.plan_id != plan_id {
+        bail!("Receipt is for plan {}, but the plan content hashes to {}", receipt.plan_id, plan_id);
+    }
+    let expected = build(plan, receipt.issued_at)?;
+    if expected.signatures != receipt.signatures {
+        bail!("Receipt signatures do not match the plan's signatures");
+    }
+    Ok(())
+}
+
+pub fn cmd_verify_receipt(args: &[String]) -> Result<()> {
+    let [receipt_arg, plan_arg] = args else {
+        eprintln!("usage: rtt-planner verify-receipt <receipt.json> <plan.json>");
+        bail!("Invalid arguments");
+    };
+    let path = validate_path(receipt_arg, "receipt file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read receipt: {:?}", path))?;
+    let receipt: Receipt = serde_json::from_str(&content).with_context(|| "Failed to parse receipt JSON")?;
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    check(&receipt, &plan)?;
+    println!("{}", receipt.rollup);
+    eprintln!("[OK] Receipt matches plan {} and {} signature(s)", receipt.plan_id, receipt.signatures.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+
+    fn signed_plan() -> Plan {
+        let mut plan = Plan {
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), ..Default::default() }],
+            order: vec!["BATCH-1".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        let sign = |key_id: &str, sig: &str| Sign { alg: "ed25519".into(), key_id: key_id.into(), sig: sig.into(), signed_at: Some(1_700_000_000) };
+        plan.sign = Some(sign("dev", "c2ln"));
+        plan.signatures.push(sign("ops", "b3Bz"));
+        plan
+    }
+
+    #[test]
+    fn test_rollup_recomputes_from_plan() {
+        let plan = signed_plan();
+        let receipt = build(&plan, 1_700_000_005).unwrap();
+        assert_eq!(receipt.plan_id, plan.plan_id);
+        assert_eq!(receipt.signatures.iter().map(|s| s.key_id.as_str()).collect::<Vec<_>>(), vec!["dev", "ops"]);
+
+        // Recompute by hand from the plan and its signatures
+        let sig_digest = |s: &Sign| hash_bytes(&serde_json::to_vec(&serde_json::to_value(s).unwrap()).unwrap());
+        let body = serde_json::json!({
+            "issued_at": 1_700_000_005u64,
+            "plan_id": compute_plan_id(&plan).unwrap(),
+            "signatures": plan.all_signatures().map(|s| serde_json::json!({
+                "key_id": s.key_id, "sha256": sig_digest(s), "signed_at": s.signed_at,
+            })).collect::<Vec<_>>(),
+        });
+        assert_eq!(receipt.rollup, hash_bytes(&serde_json::to_vec(&body).unwrap()));
+        check(&receipt, &plan).unwrap();
+
+        // A round trip through JSON still checks
+        let reloaded: Receipt = serde_json::from_slice(&serde_json::to_vec_pretty(&receipt).unwrap()).unwrap();
+        check(&reloaded, &plan).unwrap();
+    }
+
+    #[test]
+    fn test_receipt_rejects_changes() {
+        let plan = signed_plan();
+        let receipt = build(&plan, 1_700_000_005).unwrap();
+
+        let mut resigned = signed_plan();
+        resigned.signatures[0].sig = "b3RoZXI=".into();
+        assert!(check(&receipt, &resigned).unwrap_err().to_string().contains("signatures"));
+
+        let mut edited = signed_plan();
+        edited.routes_add[0].to = "c".into();
+        assert!(check(&receipt, &edited).unwrap_err().to_string().contains("hashes to"));
+
+        let mut backdated = receipt;
+        backdated.issued_at -= 3600;
+        assert!(check(&backdated, &plan).unwrap_err().to_string().contains("rollup"));
+    }
+}
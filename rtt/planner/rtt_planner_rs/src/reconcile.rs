@@ -0,0 +1,113 @@
+//! State reconciliation
+//!
+//! `rtt-planner diff-state <current.json> <desired.json> -o <plan.json>`
+//! writes the plan that turns one route table into another: routes only in
+//! the desired state are added, routes only in the current state deleted,
+//! and a route whose endpoints are in both but whose content differs is
+//! deleted and added again with the desired content. Both files are route
+//! tables as `simulate --state` reads them. Everything goes in the single
+//! batch `BATCH-1`, where deletions apply before additions; the plan comes
+//! out unsigned, to be signed like any other.
+
+use crate::batch::batch_name;
+use crate::state::RouteTable;
+use crate::{compute_plan_id, plan_name, validate_path, Plan, Route};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// The unsigned plan taking `current` to `desired`.
+pub fn diff_tables(current: &RouteTable, desired: &RouteTable) -> Result<Plan> {
+    let key = |r: &Route| (r.from.clone(), r.to.clone());
+    let by_key = |table: &RouteTable| -> Result<BTreeMap<(String, String), serde_json::Value>> {
+        table.routes().map(|r| Ok((key(r), serde_json::to_value(r)?))).collect()
+    };
+    let (have, want) = (by_key(current)?, by_key(desired)?);
+    // Missing on one side, or present on both with different content
+    let differs = |r: &Route| have.get(&key(r)) != want.get(&key(r));
+
+    let mut plan = Plan {
+        routes_del: current.routes().filter(|r| differs(r)).cloned().collect(),
+        routes_add: desired.routes().filter(|r| differs(r)).cloned().collect(),
+        ..Default::default()
+    };
+    if !plan.routes_add.is_empty() || !plan.routes_del.is_empty() {
+        plan.order = vec![batch_name(0)];
+    }
+    plan.validate()?;
+    plan.plan_id = compute_plan_id(&plan)?;
+    plan.name = plan_name(&plan.plan_id);
+    Ok(plan)
+}
+
+pub fn cmd_diff_state(args: &[String]) -> Result<()> {
+    let mut out = None;
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => out = Some(it.next().context("-o requires a value")?),
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg),
+        }
+    }
+    let (Some(out), [current, desired]) = (out, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner diff-state <current.json> <desired.json> -o <plan.json>");
+        bail!("Invalid arguments");
+    };
+    let current = RouteTable::load(&validate_path(current, "state file")?)?;
+    let desired = RouteTable::load(&validate_path(desired, "state file")?)?;
+    let out = validate_path(out, "output file")?;
+
+    let plan = diff_tables(&current, &desired)?;
+    fs::write(&out, serde_json::to_vec_pretty(&plan)?).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    println!("{}", plan.plan_id);
+    eprintln!(
+        "[OK] Reconciliation plan written unsigned: {:?} (+{} -{})",
+        out,
+        plan.routes_add.len(),
+        plan.routes_del.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::simulate;
+
+    #[test]
+    fn test_diff_state_reaches_desired_table() {
+        let route = |from: &str, to: &str, weight: Option<f64>| Route { from: from.into(), to: to.into(), weight, ..Default::default() };
+        let current = RouteTable::from_routes(vec![route("a", "b", None), route("b", "c", Some(1.0)), route("c", "d", None)]);
+        let desired = RouteTable::from_routes(vec![route("a", "b", None), route("b", "c", Some(2.0)), route("d", "e", None)]);
+
+        let plan = diff_tables(&current, &desired).unwrap();
+        let keys = |routes: &[Route]| routes.iter().map(|r| format!("{}->{}", r.from, r.to)).collect::<Vec<_>>();
+        assert_eq!(keys(&plan.routes_del), vec!["b->c", "c->d"]);
+        assert_eq!(keys(&plan.routes_add), vec!["b->c", "d->e"]);
+        assert_eq!(plan.routes_add[0].weight, Some(2.0));
+
+        // Applying the plan to the current table gives the desired one
+        let mut table = current.clone();
+        let reports = simulate(&plan, &mut table).unwrap();
+        assert!(reports.iter().all(|r| r.warnings.is_empty()));
+        let contents = |t: &RouteTable| serde_json::to_value(t.routes().collect::<Vec<_>>()).unwrap();
+        assert_eq!(contents(&table), contents(&desired));
+
+        // A changed prerequisite is replaced, so its dependents, changed or
+        // not, still find it
+        let after = |from: &str, to: &str, prereq: &str, weight| Route { after: vec![prereq.into()], ..route(from, to, weight) };
+        let current = RouteTable::from_routes(vec![route("a", "b", None), after("b", "c", "a->b", None), after("d", "e", "a->b", None)]);
+        let desired = RouteTable::from_routes(vec![route("a", "b", Some(5.0)), after("b", "c", "a->b", Some(2.0)), after("d", "e", "a->b", None)]);
+        let plan = diff_tables(&current, &desired).unwrap();
+        assert_eq!(keys(&plan.routes_del), vec!["a->b", "b->c"]);
+        let mut table = current.clone();
+        simulate(&plan, &mut table).unwrap();
+        assert_eq!(contents(&table), contents(&desired));
+
+        // Identical tables need no changes
+        let noop = diff_tables(&desired, &desired).unwrap();
+        assert!(noop.routes_add.is_empty() && noop.routes_del.is_empty() && noop.order.is_empty());
+    }
+}
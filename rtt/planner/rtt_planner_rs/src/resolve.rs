@@ -0,0 +1,174 @@
+//! Endpoint resolution
+//!
+//! Before batching, every distinct endpoint of the planned routes is passed
+//! to an `EndpointResolver`, which confirms it names something reachable
+//! (a DNS name, a service registry entry). The default resolver accepts
+//! everything; `--resolver dns` looks up the endpoint's host. Unresolvable
+//! endpoints fail the plan, or with `--on-unresolved warn` are only reported.
+
+use crate::Route;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::net::ToSocketAddrs;
+use std::str::FromStr;
+
+pub trait EndpointResolver {
+    /// `Ok` if `endpoint` resolves; otherwise why it does not.
+    fn resolve(&self, endpoint: &str) -> Result<()>;
+}
+
+/// Accepts every endpoint.
+pub struct NoopResolver;
+
+impl EndpointResolver for NoopResolver {
+    fn resolve(&self, _endpoint: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Resolves the host of `scheme://[user@]host[:port]/path#fragment`
+/// endpoints, or of bare `host[:port]` endpoints, through the system
+/// resolver. IPv6 hosts are written in brackets, as in `[::1]:80`.
+pub struct DnsResolver;
+
+fn host(endpoint: &str) -> &str {
+    let rest = endpoint.split_once("://").map_or(endpoint, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    if let Some(bracketed) = authority.strip_prefix('[') {
+        return bracketed.split_once(']').map_or(bracketed, |(host, _)| host);
+    }
+    authority.rsplit_once(':').map_or(authority, |(host, _)| host)
+}
+
+impl EndpointResolver for DnsResolver {
+    fn resolve(&self, endpoint: &str) -> Result<()> {
+        let host = host(endpoint);
+        if host.is_empty() {
+            bail!("no host in endpoint");
+        }
+        let addrs = (host, 0).to_socket_addrs().with_context(|| format!("cannot resolve host {}", host))?;
+        match addrs.count() {
+            0 => bail!("host {} has no addresses", host),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResolverKind {
+    #[default]
+    None,
+    Dns,
+}
+
+impl FromStr for ResolverKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "dns" => Ok(Self::Dns),
+            _ => bail!("Unknown resolver: {} (expected none or dns)", s),
+        }
+    }
+}
+
+impl ResolverKind {
+    pub fn resolver(self) -> Box<dyn EndpointResolver> {
+        match self {
+            Self::None => Box::new(NoopResolver),
+            Self::Dns => Box::new(DnsResolver),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnUnresolved {
+    #[default]
+    Fail,
+    Warn,
+}
+
+impl FromStr for OnUnresolved {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "warn" => Ok(Self::Warn),
+            _ => bail!("Unknown --on-unresolved mode: {} (expected fail or warn)", s),
+        }
+    }
+}
+
+/// Resolve each distinct endpoint of `routes` once. Returns the
+/// unresolvable endpoints with the reason, after warning about each; with
+/// `OnUnresolved::Fail` any of them is an error instead.
+pub fn check_endpoints(
+    routes: &[Route],
+    resolver: &dyn EndpointResolver,
+    on_unresolved: OnUnresolved,
+) -> Result<Vec<(String, String)>> {
+    let endpoints: BTreeSet<&str> = routes.iter().flat_map(|r| [r.from.as_str(), r.to.as_str()]).collect();
+    let unresolved: Vec<(String, String)> = endpoints
+        .into_iter()
+        .filter_map(|e| resolver.resolve(e).err().map(|err| (e.to_string(), format!("{:#}", err))))
+        .collect();
+    for (endpoint, reason) in &unresolved {
+        eprintln!("[WARN] Endpoint {} does not resolve: {}", endpoint, reason);
+    }
+    if on_unresolved == OnUnresolved::Fail && !unresolved.is_empty() {
+        let names: Vec<&str> = unresolved.iter().map(|(e, _)| e.as_str()).collect();
+        bail!("{} endpoint(s) do not resolve: {}", names.len(), names.join(", "));
+    }
+    Ok(unresolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Rejects `gw.typo`, and records every lookup.
+    struct MockResolver(RefCell<Vec<String>>);
+
+    impl EndpointResolver for MockResolver {
+        fn resolve(&self, endpoint: &str) -> Result<()> {
+            self.0.borrow_mut().push(endpoint.to_string());
+            match endpoint {
+                "gw.typo" => bail!("NXDOMAIN"),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mock_resolver_rejects_endpoint() {
+        let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+        let routes = vec![route("a", "gw.typo"), route("b", "gw.typo"), route("a", "db")];
+        let mock = MockResolver(RefCell::new(Vec::new()));
+
+        let err = check_endpoints(&routes, &mock, OnUnresolved::Fail).unwrap_err().to_string();
+        assert_eq!(err, "1 endpoint(s) do not resolve: gw.typo");
+        // Each endpoint is looked up once
+        assert_eq!(*mock.0.borrow(), vec!["a", "b", "db", "gw.typo"]);
+
+        let unresolved = check_endpoints(&routes, &mock, OnUnresolved::Warn).unwrap();
+        assert_eq!(unresolved, vec![("gw.typo".to_string(), "NXDOMAIN".to_string())]);
+        assert!(check_endpoints(&routes, &NoopResolver, OnUnresolved::Fail).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_host_of_endpoint() {
+        assert_eq!(host("rtt://svc.internal:8080/ext/logger#ndjson"), "svc.internal");
+        assert_eq!(host("db.local:5432"), "db.local");
+        assert_eq!(host("gw"), "gw");
+        assert_eq!(host("rtt://obs/extension/logger@1.3.0"), "obs");
+        assert_eq!(host("ssh://deploy@bastion.internal:22/srv"), "bastion.internal");
+        assert_eq!(host("ops@laptop"), "laptop");
+        assert_eq!(host("[::1]:80"), "::1");
+        assert_eq!(host("rtt://user@[fe80::1]/api"), "fe80::1");
+        assert!(DnsResolver.resolve("[::1]:80").is_ok());
+    }
+}
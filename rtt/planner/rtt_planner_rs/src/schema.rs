@@ -0,0 +1,121 @@
+//! Route metadata schemas
+//!
+//! `--metadata-schema <file>` names the labels routes must carry in each
+//! environment, and `--env <name>` picks the active one. Planned routes
+//! missing a required label fail planning:
+//!
+//! ```json
+//! {"environments": {"prod": {"required": ["owner", "change-ticket"], "optional": ["team"]}, "dev": {}}}
+//! ```
+//!
+//! Without `optional`, routes may carry any other labels. With it, the
+//! environment's labels are closed: a label neither required nor optional
+//! is rejected too. An active environment missing from the schema is an
+//! error, so a typo in `--env` cannot skip the checks.
+
+use crate::Route;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LabelRules {
+    #[serde(default)]
+    pub required: Vec<String>,
+    #[serde(default)]
+    pub optional: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct SchemaFile {
+    environments: BTreeMap<String, LabelRules>,
+}
+
+/// Rules for `env` from the schema file `content`.
+pub fn parse_schema(content: &str, env: &str) -> Result<LabelRules> {
+    let file: SchemaFile = serde_json::from_str(content).with_context(|| "Failed to parse metadata schema JSON")?;
+    match file.environments.get(env) {
+        Some(rules) => Ok(rules.clone()),
+        None => bail!(
+            "Metadata schema has no environment {} (has {})",
+            env,
+            file.environments.keys().cloned().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+pub fn load_schema(path: &Path, env: &str) -> Result<LabelRules> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read metadata schema: {:?}", path))?;
+    parse_schema(&content, env)
+}
+
+/// Fail if any route breaks the rules of environment `env`, listing every
+/// violation.
+pub fn check_labels(routes: &[Route], rules: &LabelRules, env: &str) -> Result<()> {
+    let mut violations = Vec::new();
+    for route in routes {
+        let missing: Vec<&str> = rules.required.iter().filter(|k| !route.labels.contains_key(*k)).map(String::as_str).collect();
+        if !missing.is_empty() {
+            violations.push(format!("{} -> {} lacks {}", route.from, route.to, missing.join(", ")));
+        }
+        if let Some(optional) = &rules.optional {
+            let allowed = |k: &String| rules.required.contains(k) || optional.contains(k);
+            let unknown: Vec<&str> = route.labels.keys().filter(|k| !allowed(k)).map(String::as_str).collect();
+            if !unknown.is_empty() {
+                violations.push(format!("{} -> {} has unknown {}", route.from, route.to, unknown.join(", ")));
+            }
+        }
+    }
+    if !violations.is_empty() {
+        bail!("{} route label violation(s) for environment {}: {}", violations.len(), env, violations.join("; "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"{"environments": {
+        "prod": {"required": ["owner", "change-ticket"], "optional": ["team"]},
+        "dev": {}
+    }}"#;
+
+    fn route(from: &str, labels: &[(&str, &str)]) -> Route {
+        Route {
+            from: from.into(),
+            to: "db".into(),
+            labels: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_prod_requires_owner_dev_does_not() {
+        let routes = vec![
+            route("api", &[("owner", "core"), ("change-ticket", "CHG-1")]),
+            route("batch", &[("change-ticket", "CHG-2")]),
+        ];
+        let prod = parse_schema(SCHEMA, "prod").unwrap();
+        let err = check_labels(&routes, &prod, "prod").unwrap_err().to_string();
+        assert_eq!(err, "1 route label violation(s) for environment prod: batch -> db lacks owner");
+
+        let dev = parse_schema(SCHEMA, "dev").unwrap();
+        check_labels(&routes, &dev, "dev").unwrap();
+    }
+
+    #[test]
+    fn test_optional_closes_labels() {
+        let prod = parse_schema(SCHEMA, "prod").unwrap();
+        let labels = [("owner", "core"), ("change-ticket", "CHG-1"), ("team", "db")];
+        check_labels(&[route("api", &labels)], &prod, "prod").unwrap();
+        let err = check_labels(&[route("api", &[labels[0], labels[1], ("color", "blue")])], &prod, "prod").unwrap_err();
+        assert!(err.to_string().contains("api -> db has unknown color"), "{}", err);
+
+        let err = parse_schema(SCHEMA, "staging").unwrap_err().to_string();
+        assert_eq!(err, "Metadata schema has no environment staging (has dev, prod)");
+    }
+}
@@ -0,0 +1,86 @@
+//! End-to-end self-test
+//!
+//! `rtt-planner e2e-selftest` exercises the core flows together, in
+//! process: it plans a small sample, signs and verifies the plan, inverts
+//! it, signs and verifies the inverse, and checks that inverting twice
+//! gives back the original content. Each step is reported as it passes;
+//! the first failure stops the test with a non-zero exit.
+//!
+//! Signing uses a fixed throwaway key, so the run needs no key material and
+//! its signatures prove nothing outside the test.
+
+use crate::batch::BatchConfig;
+use crate::invert::invert_plan;
+use crate::verify::{verify_plan, PublicKeys};
+use crate::{build_plan, canonical_bytes, signed_bytes, telemetry, unix_now, Inputs, Options, Plan, Route, Sign};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+
+const SELFTEST_SEED: [u8; 32] = [0x5e; 32];
+
+fn sign(plan: &mut Plan, key: &SigningKey) -> Result<()> {
+    let signed_at = unix_now();
+    let sig = key.sign(&signed_bytes(plan, Some(signed_at))?);
+    plan.sign = Some(Sign {
+        alg: "ed25519".into(),
+        key_id: "selftest".into(),
+        sig: STANDARD.encode(sig.to_bytes()),
+        signed_at: Some(signed_at),
+    });
+    Ok(())
+}
+
+fn sample_plan() -> Result<Plan> {
+    let route = |from: &str, to: &str| Route { from: from.into(), to: to.into(), ..Default::default() };
+    let mut db = route("api", "db");
+    db.after = vec!["gw->api".into()];
+    let routes = vec![route("gw", "api"), db, route("api", "cache")];
+    let opts = Options { batching: BatchConfig { batch_size: Some(2), ..Default::default() }, ..Default::default() };
+    let inputs = Inputs::load(&opts, None)?;
+    let (plan, _) = build_plan(routes, &opts, &inputs, &mut telemetry::Trace::new())?;
+    Ok(plan)
+}
+
+/// Run every step, stopping at the first failure.
+pub fn run() -> Result<()> {
+    let key = SigningKey::from_bytes(&SELFTEST_SEED);
+    let keys = PublicKeys::Any(vec![STANDARD.encode(key.verifying_key().to_bytes())]);
+    let step = |name: &str| eprintln!("[OK] {}", name);
+
+    let mut plan = sample_plan().context("build")?;
+    if plan.order.len() < 2 {
+        bail!("build: sample plan has {} batch(es), expected several", plan.order.len());
+    }
+    step("build");
+    sign(&mut plan, &key).context("sign")?;
+    step("sign");
+    verify_plan(&plan, &keys, true, None).context("verify")?;
+    step("verify");
+
+    let mut inverse = invert_plan(&plan).context("invert")?;
+    if inverse.routes_del.len() != plan.routes_add.len() || inverse.plan_id == plan.plan_id {
+        bail!("invert: inverse does not undo the plan");
+    }
+    step("invert");
+    sign(&mut inverse, &key).context("sign inverse")?;
+    verify_plan(&inverse, &keys, true, None).context("verify inverse")?;
+    step("sign and verify inverse");
+
+    let restored = invert_plan(&inverse).context("double inversion")?;
+    if canonical_bytes(&restored)? != canonical_bytes(&plan)? || restored.plan_id != plan.plan_id {
+        bail!("double inversion: content differs from the original plan");
+    }
+    step("double inversion");
+    Ok(())
+}
+
+pub fn cmd_e2e_selftest(args: &[String]) -> Result<()> {
+    if !args.is_empty() {
+        eprintln!("usage: rtt-planner e2e-selftest");
+        bail!("Invalid arguments");
+    }
+    run().context("Self-test failed")?;
+    println!("OK");
+    Ok(())
+}
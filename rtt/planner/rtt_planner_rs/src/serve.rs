@@ -0,0 +1,228 @@
+//! Server mode (`serve` feature)
+//!
+//! `rtt-planner serve --listen <addr> [--manifests-dir <dir>] [options]` plans
+//! over HTTP; `--optimize` enforces the capacities of the manifests in
+//! `--manifests-dir`:
+//!
+//! - `GET /healthz` answers 200 while the process is up.
+//! - `GET /readyz` answers 200 while the server accepts work, 503 once it
+//!   is draining.
+//! - `POST /plan` takes a routes document and returns the unsigned plan,
+//!   built with the plan options given at startup.
+//!
+//! On SIGTERM or SIGINT the server starts draining: `/readyz` answers 503,
+//! new `/plan` requests are refused with 503, and once every request
+//! accepted before then has finished (for at most `DRAIN_TIMEOUT`) it exits.
+//! At most `MAX_WORKERS` requests are handled at once; connections beyond
+//! that get 503 straight away. Each socket read or write gives up after
+//! `IO_TIMEOUT`.
+
+use crate::jwt::PlanFormat;
+use crate::{build_plan, check_plan_size, input, parse_options, telemetry, validate_path, Inputs, Options};
+use anyhow::{bail, Context, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_WORKERS: usize = 64;
+const MAX_BODY: usize = 16 << 20;
+
+struct State {
+    opts: Options,
+    inputs: Inputs,
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Counts a connection as in flight until dropped.
+struct InFlight(Arc<State>);
+
+impl InFlight {
+    fn new(state: &Arc<State>) -> Self {
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(state.clone())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self { status, content_type: "text/plain", body: body.into().into_bytes() }
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<(String, String, Vec<u8>)> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line");
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut len = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header == "\r\n" || header == "\n" {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().context("Invalid Content-Length")?;
+            }
+        }
+    }
+    if len > MAX_BODY {
+        bail!("Request body of {} bytes exceeds {}", len, MAX_BODY);
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok((method, path, body))
+}
+
+fn plan_response(state: &State, body: &[u8]) -> Result<Response> {
+    let body = std::str::from_utf8(body).context("Routes body is not UTF-8")?;
+    let routes = input::parse_routes(body, state.opts.input_format)?;
+    let mut trace = telemetry::Trace::new();
+    let (plan, _) = build_plan(routes.routes, &state.opts, &state.inputs, &mut trace)?;
+    let plan_json = serde_json::to_vec_pretty(&plan)?;
+    check_plan_size(plan_json.len(), state.opts.max_plan_bytes)?;
+    Ok(Response { status: "200 OK", content_type: "application/json", body: plan_json })
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn prepare(stream: &TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    Ok(())
+}
+
+/// Serve one connection. `refuse_work` is set for connections accepted
+/// while draining, which may still probe but not plan.
+fn handle(mut stream: TcpStream, state: &State, refuse_work: bool) -> Result<()> {
+    prepare(&stream)?;
+    let response = match read_request(&stream) {
+        Err(e) => Response::text("400 Bad Request", e.to_string()),
+        Ok((method, path, body)) => match (method.as_str(), path.as_str()) {
+            ("GET", "/healthz") => Response::text("200 OK", "ok"),
+            ("GET", "/readyz") if state.draining.load(Ordering::SeqCst) => {
+                Response::text("503 Service Unavailable", "draining")
+            }
+            ("GET", "/readyz") => Response::text("200 OK", "ready"),
+            ("POST", "/plan") if refuse_work => Response::text("503 Service Unavailable", "draining"),
+            ("POST", "/plan") => plan_response(state, &body)
+                .unwrap_or_else(|e| Response::text("400 Bad Request", format!("{:#}", e))),
+            _ => Response::text("404 Not Found", "not found"),
+        },
+    };
+    write_response(&mut stream, &response)
+}
+
+pub fn cmd_serve(args: &[String]) -> Result<()> {
+    let (mut listen, mut manifests_dir) = (None, None);
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--listen" => listen = Some(it.next().context("--listen requires a value")?.clone()),
+            "--manifests-dir" => manifests_dir = Some(it.next().context("--manifests-dir requires a value")?.clone()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let (positional, opts) = parse_options(&rest)?;
+    let (Some(listen), true) = (listen, positional.is_empty()) else {
+        eprintln!("usage: rtt-planner serve --listen <addr:port> [--manifests-dir <dir>] [options]");
+        bail!("Invalid arguments");
+    };
+    let file_only = opts.stream || opts.emit_per_batch.is_some() || opts.receipt.is_some() || opts.with_rollback.is_some();
+    if file_only || opts.plan_format != PlanFormat::Json || opts.aggregate_by.is_some() {
+        bail!("--stream, --emit-per-batch, --receipt, --with-rollback, --format and --aggregate-by only apply to plans written to a file or stdout");
+    }
+    let manifests_dir = manifests_dir.map(|d| validate_path(&d, "manifests directory")).transpose()?;
+    let inputs = Inputs::load(&opts, manifests_dir.as_deref())?;
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(signal, shutdown.clone())?;
+    }
+
+    let listener = TcpListener::bind(&listen).with_context(|| format!("Failed to listen on {}", listen))?;
+    listener.set_nonblocking(true)?;
+    eprintln!("[INFO] Listening on {}", listener.local_addr()?);
+
+    let state = Arc::new(State {
+        opts,
+        inputs,
+        draining: AtomicBool::new(false),
+        in_flight: AtomicUsize::new(0),
+    });
+    // Keep accepting while draining, so readiness probes see the 503
+    let mut deadline = None;
+    loop {
+        if deadline.is_none() && shutdown.load(Ordering::SeqCst) {
+            state.draining.store(true, Ordering::SeqCst);
+            eprintln!("[INFO] Shutting down; draining {} request(s)", state.in_flight.load(Ordering::SeqCst));
+            deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+        }
+        if let Some(deadline) = deadline {
+            let in_flight = state.in_flight.load(Ordering::SeqCst);
+            if in_flight == 0 {
+                break;
+            }
+            if Instant::now() > deadline {
+                bail!("{} request(s) still in flight after {:?}", in_flight, DRAIN_TIMEOUT);
+            }
+        }
+        match listener.accept() {
+            Ok((mut stream, _)) if state.in_flight.load(Ordering::SeqCst) >= MAX_WORKERS => {
+                let busy = Response::text("503 Service Unavailable", "busy");
+                if let Err(e) = prepare(&stream).and_then(|()| write_response(&mut stream, &busy)) {
+                    eprintln!("[WARN] Request failed: {}", e);
+                }
+            }
+            Ok((stream, _)) => {
+                let guard = InFlight::new(&state);
+                let refuse_work = deadline.is_some();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, &guard.0, refuse_work) {
+                        eprintln!("[WARN] Request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(20)),
+            Err(e) => eprintln!("[WARN] Accept failed: {}", e),
+        }
+    }
+    drop(listener);
+    eprintln!("[OK] Server stopped");
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Plan handoff through shared memory (`shm` feature)
+//!
+//! `--publish-shm <name>` also appends the written plan to the fabric
+//! segment `name`, creating it if needed, so an executor can map the
+//! segment and take new plans without a filesystem round-trip. Each plan is
+//! one `FRAME_PLAN` frame:
+//!
+//! ```text
+//! u16 id_len | plan_id[id_len] | plan JSON
+//! ```
+//!
+//! Readers take the newest plan frame and check that the header `plan_id`
+//! matches both the plan's own `plan_id` and the hash of its content. When
+//! the segment is full, the publisher consumes every earlier frame and
+//! compacts, so only the newest plans stay mapped.
+//!
+//! `rtt-planner fetch-shm <name> <out.json>` writes the newest plan in a
+//! segment to a file, for inspection.
+
+use crate::{compute_plan_id, validate_path, Plan};
+use anyhow::{bail, Context, Result};
+use rtt_fabric_shm::{Frame, ShmSegment};
+use std::fs;
+
+/// Frame type of a published plan, above the fabric's own types.
+pub const FRAME_PLAN: u16 = 0x10;
+
+/// Capacity of a segment the planner creates, unless one plan needs more.
+const DEFAULT_CAPACITY: usize = 4 << 20;
+
+fn encode(plan_id: &str, plan_json: &[u8]) -> Result<Vec<u8>> {
+    let id_len = u16::try_from(plan_id.len()).context("plan_id is too long for a plan frame")?;
+    let mut frame = Vec::with_capacity(2 + plan_id.len() + plan_json.len());
+    frame.extend_from_slice(&id_len.to_le_bytes());
+    frame.extend_from_slice(plan_id.as_bytes());
+    frame.extend_from_slice(plan_json);
+    Ok(frame)
+}
+
+/// Append a plan to segment `name` and return the frame's sequence number.
+pub fn publish(name: &str, plan_id: &str, plan_json: &[u8]) -> Result<u64> {
+    let frame = encode(plan_id, plan_json)?;
+    let mut seg = match ShmSegment::try_open(name)? {
+        Some(seg) => seg,
+        None => ShmSegment::create(name, DEFAULT_CAPACITY.max(2 * (frame.len() + 16)))?,
+    };
+    if let Ok(seq) = seg.write_typed_frame(FRAME_PLAN, &frame) {
+        return Ok(seq);
+    }
+    // Full: drop the older frames and retry once
+    if let Some(last) = seg.frames().last().map(|f| f.seq) {
+        seg.consume(last);
+        seg.compact();
+    }
+    seg.write_typed_frame(FRAME_PLAN, &frame)
+}
+
+/// Decode a plan frame and check it against its header `plan_id`.
+pub fn decode(frame: &Frame<'_>) -> Result<Plan> {
+    let payload = frame.payload;
+    let id_len = payload.get(..2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize).context("Plan frame is truncated")?;
+    let Some((id, plan_json)) = payload[2..].split_at_checked(id_len) else {
+        bail!("Plan frame {} is truncated", frame.seq);
+    };
+    let header_id = std::str::from_utf8(id).context("Plan frame plan_id is not UTF-8")?;
+    let plan: Plan = serde_json::from_slice(plan_json).with_context(|| format!("Plan frame {} holds invalid plan JSON", frame.seq))?;
+    if plan.plan_id != header_id {
+        bail!("Plan frame {} header names {}, but the plan is {}", frame.seq, header_id, plan.plan_id);
+    }
+    let computed = compute_plan_id(&plan)?;
+    if computed != header_id {
+        bail!("Plan frame {} content hashes to {}, not {}", frame.seq, computed, header_id);
+    }
+    Ok(plan)
+}
+
+/// The newest plan in segment `name`, read through a read-only mapping
+/// into a snapshot, so a publisher compacting meanwhile cannot tear it.
+pub fn latest(name: &str) -> Result<Option<Plan>> {
+    let snapshot = ShmSegment::open_readonly(name)?.snapshot();
+    let frame = snapshot.frames().of_type(FRAME_PLAN).last();
+    frame.as_ref().map(decode).transpose()
+}
+
+pub fn cmd_fetch_shm(args: &[String]) -> Result<()> {
+    let [name, out_arg] = args else {
+        eprintln!("usage: rtt-planner fetch-shm <segment> <out.json>");
+        bail!("Invalid arguments");
+    };
+    let out = validate_path(out_arg, "output file")?;
+    let Some(plan) = latest(name)? else {
+        bail!("Segment {} holds no plan", name);
+    };
+    fs::write(&out, serde_json::to_vec_pretty(&plan)?).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    println!("{}", plan.plan_id);
+    eprintln!("[OK] Fetched plan from segment {}: {:?}", name, out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+
+    #[test]
+    fn test_publish_and_read_back() {
+        let name = format!("rtt-planner-test-{}", std::process::id());
+        let plan = |to: &str| {
+            let mut plan = Plan {
+                routes_add: vec![Route { from: "a".into(), to: to.into(), ..Default::default() }],
+                order: vec!["BATCH-1".into()],
+                ..Default::default()
+            };
+            plan.plan_id = compute_plan_id(&plan).unwrap();
+            plan
+        };
+        let (first, second) = (plan("b"), plan("c"));
+        for p in [&first, &second] {
+            publish(&name, &p.plan_id, &serde_json::to_vec_pretty(p).unwrap()).unwrap();
+        }
+
+        // A second handle sees the newest plan, checked against its header
+        let read = latest(&name).unwrap().unwrap();
+        assert_eq!(read.plan_id, second.plan_id);
+        assert_eq!(read.routes_add[0].to, "c");
+
+        // A header that does not match the plan is rejected
+        let mut seg = ShmSegment::open(&name).unwrap();
+        seg.write_typed_frame(FRAME_PLAN, &encode(&first.plan_id, &serde_json::to_vec(&second).unwrap()).unwrap()).unwrap();
+        let Err(err) = latest(&name) else { panic!("mismatched header accepted") };
+        assert!(err.to_string().contains("header names"), "{}", err);
+        ShmSegment::unlink(&name).unwrap();
+    }
+}
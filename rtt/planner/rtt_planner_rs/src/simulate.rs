@@ -0,0 +1,174 @@
+//! Plan simulation
+//!
+//! `rtt-planner simulate <plan.json> --state <current.json>` applies a plan
+//! to an in-memory copy of the route table, batch by batch in plan order,
+//! and reports what each batch would do, then the resulting routes. Within a batch, deletions apply
+//! before additions. Untagged routes belong to the first batch. The state
+//! file is never written.
+//!
+//! A batch may not delete a route that a route left in the table lists in
+//! `after`: the simulation stops there and names the dependent.
+
+use crate::batch::route_key;
+use crate::state::RouteTable;
+use crate::verify::load_plan;
+use crate::{validate_path, Plan, Route};
+use anyhow::{bail, Context, Result};
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub batch: String,
+    pub added: usize,
+    pub removed: usize,
+    /// Routes in the table after this batch.
+    pub routes: usize,
+    pub warnings: Vec<String>,
+}
+
+fn in_batch<'a>(routes: &'a [Route], order: &'a [String], batch: &'a str) -> impl Iterator<Item = &'a Route> {
+    routes
+        .iter()
+        .filter(move |r| r.batch.as_deref().unwrap_or(&order[0]) == batch)
+}
+
+/// Apply `plan` to `table` and report each batch.
+pub fn simulate(plan: &Plan, table: &mut RouteTable) -> Result<Vec<BatchReport>> {
+    let all = plan.routes_add.iter().chain(&plan.routes_del);
+    if plan.order.is_empty() {
+        if all.count() > 0 {
+            bail!("Plan has routes but no batch order");
+        }
+        return Ok(vec![]);
+    }
+    for route in all {
+        if let Some(batch) = route.batch.as_ref().filter(|b| !plan.order.contains(b)) {
+            bail!("Route {} -> {} is in batch {}, which is not in the plan order", route.from, route.to, batch);
+        }
+    }
+
+    let mut reports = Vec::new();
+    for batch in &plan.order {
+        let mut report = BatchReport { batch: batch.clone(), ..Default::default() };
+        for route in in_batch(&plan.routes_del, &plan.order, batch) {
+            if table.remove(route) {
+                report.removed += 1;
+            } else {
+                report.warnings.push(format!("delete of absent route {} -> {}", route.from, route.to));
+            }
+        }
+        for route in in_batch(&plan.routes_add, &plan.order, batch) {
+            if table.insert(route.clone()) {
+                report.added += 1;
+            } else {
+                report.warnings.push(format!("add of existing route {} -> {}", route.from, route.to));
+            }
+        }
+        // A route deleted and added back in the same batch is replaced
+        for route in in_batch(&plan.routes_del, &plan.order, batch) {
+            let key = route_key(route);
+            if table.routes().any(|r| route_key(r) == key) {
+                continue;
+            }
+            if let Some(dependent) = table.routes().find(|r| r.after.contains(&key)) {
+                bail!(
+                    "{} deletes {}, but {} -> {} is still applied and runs after it; delete the dependent first",
+                    batch, key, dependent.from, dependent.to
+                );
+            }
+        }
+        report.routes = table.len();
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+pub fn cmd_simulate(args: &[String]) -> Result<()> {
+    let mut state = None;
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--state" => state = Some(it.next().context("--state requires a value")?),
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg),
+        }
+    }
+    let (Some(state), [plan_arg]) = (state, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner simulate <plan.json> --state <current.json>");
+        bail!("Invalid arguments");
+    };
+
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    let mut table = RouteTable::load(&validate_path(state, "state file")?)?;
+
+    println!("initial: {} route(s)", table.len());
+    for report in simulate(&plan, &mut table)? {
+        println!(
+            "{}: {} route(s) (+{} -{})",
+            report.batch, report.routes, report.added, report.removed
+        );
+        for warning in &report.warnings {
+            eprintln!("[WARN] {}: {}", report.batch, warning);
+        }
+    }
+    for route in table.routes() {
+        println!("  {} -> {}", route.from, route.to);
+    }
+    eprintln!("[OK] Simulated {}; state not modified", plan.plan_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(from: &str, to: &str, batch: Option<&str>) -> Route {
+        Route {
+            from: from.into(),
+            to: to.into(),
+            batch: batch.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_final_route_set() {
+        let mut table = RouteTable::from_routes(vec![route("a", "b", None), route("b", "c", None)]);
+        let plan = Plan {
+            routes_add: vec![route("c", "d", Some("BATCH-1")), route("a", "b", Some("BATCH-2")), route("d", "e", Some("BATCH-2"))],
+            routes_del: vec![route("a", "b", Some("BATCH-1")), route("x", "y", Some("BATCH-2"))],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+
+        let reports = simulate(&plan, &mut table).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!((reports[0].added, reports[0].removed, reports[0].routes), (1, 1, 2));
+        assert!(reports[0].warnings.is_empty());
+        assert_eq!((reports[1].added, reports[1].removed, reports[1].routes), (2, 0, 4));
+        assert_eq!(reports[1].warnings, vec!["delete of absent route x -> y"]);
+
+        let final_routes: Vec<_> = table.routes().map(|r| format!("{}->{}", r.from, r.to)).collect();
+        assert_eq!(final_routes, vec!["a->b", "b->c", "c->d", "d->e"]);
+
+        let bad = Plan { routes_add: vec![route("a", "b", Some("BATCH-9"))], ..plan };
+        assert!(simulate(&bad, &mut table).is_err());
+    }
+
+    #[test]
+    fn test_delete_of_prerequisite_still_in_use() {
+        let after = |from: &str, to: &str, batch: Option<&str>, prereq: &str| Route { after: vec![prereq.into()], ..route(from, to, batch) };
+        let state = || RouteTable::from_routes(vec![route("a", "b", None), after("b", "c", None, "a->b")]);
+        let plan = |routes_del: Vec<Route>| Plan { routes_del, order: vec!["BATCH-1".into(), "BATCH-2".into()], ..Default::default() };
+
+        // b -> c stays applied and needs a -> b
+        let err = simulate(&plan(vec![route("a", "b", Some("BATCH-1"))]), &mut state()).unwrap_err().to_string();
+        assert!(err.contains("BATCH-1 deletes a->b, but b -> c is still applied"), "{}", err);
+
+        // Deleting the dependent in the same or an earlier batch is safe
+        let mut table = state();
+        simulate(&plan(vec![route("a", "b", Some("BATCH-2")), after("b", "c", Some("BATCH-1"), "a->b")]), &mut table).unwrap();
+        assert_eq!(table.len(), 0);
+        simulate(&plan(vec![after("b", "c", Some("BATCH-1"), "a->b"), route("a", "b", Some("BATCH-1"))]), &mut state()).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+//! Signing through a running SSH agent
+//!
+//! `--signer ssh-agent --key-comment <name>` asks the agent at
+//! `$SSH_AUTH_SOCK` to sign the plan with the ed25519 identity whose comment
+//! is `<name>`, so the private key never leaves the agent. Agents sign
+//! ed25519 data as is, so the result is an ordinary `ed25519` signature that
+//! verifies against the key's OpenSSH public key line
+//! (`ssh-ed25519 AAAA... comment`). The comment becomes the `key_id`.
+//!
+//! Only the two requests needed are spoken, from the agent protocol in
+//! draft-miller-ssh-agent: REQUEST_IDENTITIES and SIGN_REQUEST.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+const AGENT_FAILURE: u8 = 5;
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+const SIGN_REQUEST: u8 = 13;
+const SIGN_RESPONSE: u8 = 14;
+const ED25519: &str = "ssh-ed25519";
+
+fn put_string(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Cursor over an agent message body.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn u32(&mut self) -> Result<u32> {
+        let (head, rest) = self.0.split_first_chunk::<4>().context("Truncated agent message")?;
+        self.0 = rest;
+        Ok(u32::from_be_bytes(*head))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if len > self.0.len() {
+            bail!("Truncated agent message");
+        }
+        let (s, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(s)
+    }
+}
+
+fn request(stream: &mut UnixStream, body: &[u8]) -> Result<Vec<u8>> {
+    stream.write_all(&(body.len() as u32).to_be_bytes())?;
+    stream.write_all(body)?;
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let mut reply = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply)?;
+    if reply.is_empty() {
+        bail!("Empty reply from SSH agent");
+    }
+    Ok(reply)
+}
+
+/// The 32-byte public key inside an `ssh-ed25519` key blob.
+fn ed25519_key(blob: &[u8]) -> Option<[u8; 32]> {
+    let mut r = Reader(blob);
+    (r.string().ok()? == ED25519.as_bytes())
+        .then(|| r.string().ok()?.try_into().ok())
+        .flatten()
+}
+
+/// The raw key from an OpenSSH `ssh-ed25519 <base64> [comment]` line.
+pub fn parse_public_key(line: &str) -> Result<[u8; 32]> {
+    let mut fields = line.split_whitespace();
+    let (Some(ED25519), Some(blob)) = (fields.next(), fields.next()) else {
+        bail!("Not an ssh-ed25519 public key: {}", line);
+    };
+    let blob = STANDARD.decode(blob).context("SSH public key is not valid base64")?;
+    ed25519_key(&blob).ok_or_else(|| anyhow!("Malformed ssh-ed25519 key blob"))
+}
+
+/// Sign `data` with the agent's ed25519 key commented `comment`. Returns the
+/// base64 signature and the key's public key line.
+pub fn sign(socket: &Path, comment: &str, data: &[u8]) -> Result<(String, String)> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to SSH agent at {:?}", socket))?;
+
+    let reply = request(&mut stream, &[REQUEST_IDENTITIES])?;
+    if reply[0] != IDENTITIES_ANSWER {
+        bail!("SSH agent refused to list identities");
+    }
+    let mut r = Reader(&reply[1..]);
+    let mut found = None;
+    for _ in 0..r.u32()? {
+        let (blob, key_comment) = (r.string()?, r.string()?);
+        if key_comment == comment.as_bytes() && ed25519_key(blob).is_some() {
+            found = Some(blob);
+            break;
+        }
+    }
+    let blob = found.with_context(|| format!("SSH agent has no ed25519 key with comment {:?}", comment))?;
+
+    let mut body = vec![SIGN_REQUEST];
+    put_string(&mut body, blob);
+    put_string(&mut body, data);
+    body.extend_from_slice(&0u32.to_be_bytes());
+    let reply = request(&mut stream, &body)?;
+    match reply[0] {
+        SIGN_RESPONSE => {}
+        AGENT_FAILURE => bail!("SSH agent declined to sign with {:?}", comment),
+        other => bail!("Unexpected SSH agent reply type {}", other),
+    }
+    let mut sig = Reader(Reader(&reply[1..]).string()?);
+    if sig.string()? != ED25519.as_bytes() {
+        bail!("SSH agent returned a non-ed25519 signature");
+    }
+    let sig = sig.string()?;
+    if sig.len() != 64 {
+        bail!("SSH agent returned a {}-byte ed25519 signature", sig.len());
+    }
+    Ok((STANDARD.encode(sig), format!("{} {} {}", ED25519, STANDARD.encode(blob), comment)))
+}
+
+pub fn sign_with_env_agent(comment: &str, data: &[u8]) -> Result<(String, String)> {
+    let socket = std::env::var_os("SSH_AUTH_SOCK").context("--signer ssh-agent needs SSH_AUTH_SOCK")?;
+    sign(Path::new(&socket), comment, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::verify_signature;
+    use crate::{compute_plan_id, signed_bytes, Plan, Route, Sign};
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::os::unix::net::UnixListener;
+
+    fn key_blob(sk: &SigningKey) -> Vec<u8> {
+        let mut blob = Vec::new();
+        put_string(&mut blob, ED25519.as_bytes());
+        put_string(&mut blob, sk.verifying_key().as_bytes());
+        blob
+    }
+
+    /// Serve one agent connection holding an RSA-looking decoy and `sk`.
+    fn mock_agent(listener: UnixListener, sk: SigningKey) {
+        let (mut conn, _) = listener.accept().unwrap();
+        let read = |conn: &mut UnixStream| {
+            let mut len = [0u8; 4];
+            conn.read_exact(&mut len).ok()?;
+            let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+            conn.read_exact(&mut msg).ok()?;
+            Some(msg)
+        };
+        while let Some(msg) = read(&mut conn) {
+            let mut reply = Vec::new();
+            match msg[0] {
+                REQUEST_IDENTITIES => {
+                    reply.push(IDENTITIES_ANSWER);
+                    reply.extend_from_slice(&2u32.to_be_bytes());
+                    let mut decoy = Vec::new();
+                    put_string(&mut decoy, b"ssh-rsa");
+                    put_string(&mut reply, &decoy);
+                    put_string(&mut reply, b"ops@laptop");
+                    put_string(&mut reply, &key_blob(&sk));
+                    put_string(&mut reply, b"ops@laptop");
+                }
+                SIGN_REQUEST => {
+                    let mut r = Reader(&msg[1..]);
+                    assert_eq!(r.string().unwrap(), key_blob(&sk));
+                    let sig = sk.sign(r.string().unwrap());
+                    let mut inner = Vec::new();
+                    put_string(&mut inner, ED25519.as_bytes());
+                    put_string(&mut inner, &sig.to_bytes());
+                    reply.push(SIGN_RESPONSE);
+                    put_string(&mut reply, &inner);
+                }
+                _ => reply.push(AGENT_FAILURE),
+            }
+            conn.write_all(&(reply.len() as u32).to_be_bytes()).unwrap();
+            conn.write_all(&reply).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sign_with_mock_agent() {
+        let sk = SigningKey::from_bytes(&[5u8; 32]);
+        let socket = std::env::temp_dir().join(format!("rtt-agent-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = std::thread::spawn(move || mock_agent(listener, sk));
+
+        let mut plan = Plan {
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), ..Default::default() }],
+            order: vec!["BATCH-1".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        let data = signed_bytes(&plan, Some(42)).unwrap();
+
+        let (sig, public_key) = sign(&socket, "ops@laptop", &data).unwrap();
+        agent.join().unwrap();
+        let _ = std::fs::remove_file(&socket);
+
+        assert!(public_key.starts_with("ssh-ed25519 ") && public_key.ends_with(" ops@laptop"));
+        assert_eq!(parse_public_key(&public_key).unwrap(), SigningKey::from_bytes(&[5u8; 32]).verifying_key().to_bytes());
+        let sign = Sign { alg: "ed25519".into(), key_id: "ops@laptop".into(), sig, signed_at: Some(42) };
+        verify_signature(&plan, &sign, &public_key).unwrap();
+
+        assert!(parse_public_key("ssh-rsa AAAA").is_err());
+    }
+}
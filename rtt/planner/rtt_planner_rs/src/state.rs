@@ -0,0 +1,54 @@
+//! Route table state
+//!
+//! The routes currently applied to the fabric, keyed by `(from, to)`. State
+//! files use the same `{"routes": [...]}` shape as planner input.
+
+use crate::{Route, Routes};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct RouteTable {
+    routes: BTreeMap<(String, String), Route>,
+}
+
+impl RouteTable {
+    pub fn from_routes(routes: Vec<Route>) -> Self {
+        let mut table = Self::default();
+        for route in routes {
+            table.insert(route);
+        }
+        table
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read state file: {:?}", path))?;
+        let state: Routes = serde_json::from_str(&content)
+            .with_context(|| "Failed to parse state JSON")?;
+        Ok(Self::from_routes(state.routes))
+    }
+
+    /// Add a route; returns false if one with the same endpoints was present.
+    /// Batch tags are plan bookkeeping and are not kept in the table.
+    pub fn insert(&mut self, route: Route) -> bool {
+        let route = Route { batch: None, ..route };
+        self.routes.insert((route.from.clone(), route.to.clone()), route).is_none()
+    }
+
+    /// Remove a route by endpoints; returns false if it was not present.
+    pub fn remove(&mut self, route: &Route) -> bool {
+        self.routes.remove(&(route.from.clone(), route.to.clone())).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Routes sorted by `(from, to)`.
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.values()
+    }
+}
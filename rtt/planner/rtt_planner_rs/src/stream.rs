@@ -0,0 +1,126 @@
+//! Streaming plan output
+//!
+//! `--stream` writes the plan without first building its serialized form in
+//! memory. The output is the canonical bytes, written one route at a time
+//! and hashed as they go, with `name` and `plan_id` appended as the last
+//! keys once the hash is known. The result parses to the same plan as the
+//! buffered path, but is compact rather than pretty-printed.
+
+use crate::{canonical_bytes, plan_name, Plan, Route};
+use anyhow::Result;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::Write;
+
+/// Forwards writes to `inner` while hashing them and counting bytes.
+struct HashingWriter<'a, W: Write> {
+    inner: &'a mut W,
+    hasher: Sha256,
+    written: usize,
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_routes(out: &mut impl Write, routes: &[Route]) -> Result<()> {
+    out.write_all(b"[")?;
+    for (i, route) in routes.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        // Through `Value` so keys come out sorted, as in `canonical_bytes`
+        serde_json::to_writer(&mut *out, &serde_json::to_value(route)?)?;
+    }
+    out.write_all(b"]")?;
+    Ok(())
+}
+
+/// Write `plan` to `out`, set its `plan_id` and `name` from the streamed
+/// bytes, and return the number of bytes written.
+pub fn write_plan(plan: &mut Plan, out: &mut impl Write) -> Result<usize> {
+    // Everything but the route lists is small; serialize it the usual way
+    let routes_add = std::mem::take(&mut plan.routes_add);
+    let routes_del = std::mem::take(&mut plan.routes_del);
+    let head: Value = serde_json::from_slice(&canonical_bytes(plan)?)?;
+    plan.routes_add = routes_add;
+    plan.routes_del = routes_del;
+
+    let mut hashed = HashingWriter { inner: out, hasher: Sha256::new(), written: 0 };
+    hashed.write_all(b"{")?;
+    for (i, (key, value)) in head.as_object().into_iter().flatten().enumerate() {
+        if i > 0 {
+            hashed.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut hashed, key)?;
+        hashed.write_all(b":")?;
+        match key.as_str() {
+            "routes_add" => write_routes(&mut hashed, &plan.routes_add)?,
+            "routes_del" => write_routes(&mut hashed, &plan.routes_del)?,
+            _ => serde_json::to_writer(&mut hashed, value)?,
+        }
+    }
+
+    // The canonical bytes end with `}`; hash it but hold it back from the
+    // output until `name` and `plan_id` follow
+    let HashingWriter { inner: out, mut hasher, written } = hashed;
+    hasher.update(b"}");
+    plan.plan_id = format!("sha256-{:x}", hasher.finalize());
+    plan.name = plan_name(&plan.plan_id);
+
+    let tail = format!(
+        ",\"name\":{},\"plan_id\":{}}}",
+        serde_json::to_string(&plan.name)?,
+        serde_json::to_string(&plan.plan_id)?
+    );
+    out.write_all(tail.as_bytes())?;
+    Ok(written + tail.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute_plan_id;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_streamed_matches_buffered() {
+        let routes: Vec<Route> = (0..50)
+            .map(|i| Route {
+                from: format!("node-{}", i),
+                to: "sink".into(),
+                labels: BTreeMap::from([("env".into(), "prod".into()), ("az".into(), format!("az-{}", i % 3))]),
+                weight: Some(i as f64),
+                ..Default::default()
+            })
+            .collect();
+        let mut plan = Plan {
+            routes_add: routes[..40].to_vec(),
+            routes_del: routes[40..].to_vec(),
+            order: vec!["BATCH-1".into()],
+            annotations: BTreeMap::from([("ticket".into(), "CHG-1".into())]),
+            ..Default::default()
+        };
+        let buffered_id = compute_plan_id(&plan).unwrap();
+
+        let mut out = Vec::new();
+        let written = write_plan(&mut plan, &mut out).unwrap();
+        assert_eq!(written, out.len());
+        assert_eq!(plan.plan_id, buffered_id);
+        assert_eq!(plan.name, plan_name(&buffered_id));
+
+        let streamed: Plan = serde_json::from_slice(&out).unwrap();
+        assert_eq!(streamed.plan_id, buffered_id);
+        assert_eq!(serde_json::to_value(&streamed).unwrap(), serde_json::to_value(&plan).unwrap());
+        assert_eq!(compute_plan_id(&streamed).unwrap(), buffered_id);
+    }
+}
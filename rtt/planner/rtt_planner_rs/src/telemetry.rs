@@ -0,0 +1,184 @@
+//! Planner run telemetry
+//!
+//! Every generation run records a `plan` span with one child span per phase
+//! and attributes such as route counts and signed status. Recording is cheap
+//! and always on; exporting it to an OpenTelemetry collector with
+//! `--otlp-endpoint http://host:4318` needs the `otlp` feature. Spans are
+//! sent as OTLP/HTTP JSON to `/v1/traces` unless the endpoint names a path.
+//! Export gives up on a collector that takes over five seconds to connect,
+//! accept or answer, and export failures are logged and never fail the run.
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug)]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttrValue {
+    fn from(v: &str) -> Self {
+        Self::Str(v.into())
+    }
+}
+
+impl From<usize> for AttrValue {
+    fn from(v: usize) -> Self {
+        Self::Int(v as i64)
+    }
+}
+
+impl From<bool> for AttrValue {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
+struct Span {
+    name: String,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+pub struct Trace {
+    trace_id: [u8; 16],
+    start: SystemTime,
+    phases: Vec<Span>,
+    attributes: Vec<(String, AttrValue)>,
+}
+
+fn unix_nanos(t: SystemTime) -> String {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trace {
+    pub fn new() -> Self {
+        let start = SystemTime::now();
+        let seed = format!("{}-{}", unix_nanos(start), std::process::id());
+        let mut trace_id = [0u8; 16];
+        trace_id.copy_from_slice(&Sha256::digest(seed.as_bytes())[..16]);
+        Self { trace_id, start, phases: Vec::new(), attributes: Vec::new() }
+    }
+
+    /// Close a phase span that began at `start`.
+    pub fn phase(&mut self, name: &str, start: SystemTime) {
+        self.phases.push(Span { name: name.into(), start, end: SystemTime::now() });
+    }
+
+    pub fn attr(&mut self, key: &str, value: impl Into<AttrValue>) {
+        self.attributes.push((key.into(), value.into()));
+    }
+
+    fn span_id(&self, index: usize) -> String {
+        let mut h = Sha256::new();
+        h.update(self.trace_id);
+        h.update(index.to_le_bytes());
+        hex(&h.finalize()[..8])
+    }
+
+    /// The trace as an OTLP `ExportTraceServiceRequest` in JSON encoding.
+    pub fn to_otlp_json(&self) -> Value {
+        let trace_id = hex(&self.trace_id);
+        let root_id = self.span_id(0);
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    AttrValue::Str(s) => json!({ "stringValue": s }),
+                    AttrValue::Int(i) => json!({ "intValue": i.to_string() }),
+                    AttrValue::Bool(b) => json!({ "boolValue": b }),
+                };
+                json!({ "key": key, "value": value })
+            })
+            .collect();
+
+        let mut spans = vec![json!({
+            "traceId": trace_id,
+            "spanId": root_id,
+            "name": "plan",
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.phases.last().map_or(self.start, |p| p.end)),
+            "attributes": attributes,
+        })];
+        for (i, phase) in self.phases.iter().enumerate() {
+            spans.push(json!({
+                "traceId": trace_id,
+                "spanId": self.span_id(i + 1),
+                "parentSpanId": root_id,
+                "name": phase.name,
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(phase.start),
+                "endTimeUnixNano": unix_nanos(phase.end),
+            }));
+        }
+
+        json!({ "resourceSpans": [{
+            "resource": { "attributes": [
+                { "key": "service.name", "value": { "stringValue": "rtt-planner" } },
+            ] },
+            "scopeSpans": [{ "scope": { "name": "rtt-planner" }, "spans": spans }],
+        }] })
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Send `trace` to an OTLP/HTTP collector.
+#[cfg(feature = "otlp")]
+pub fn export(endpoint: &str, trace: &Trace) -> anyhow::Result<()> {
+    use anyhow::{bail, Context};
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, ToSocketAddrs};
+    use std::time::Duration;
+
+    // Per connect, write and read
+    const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    let Some(rest) = endpoint.strip_prefix("http://") else {
+        bail!("OTLP endpoint must be an http:// URL: {}", endpoint);
+    };
+    let (host, path) = match rest.split_once('/') {
+        Some((host, path)) if !path.is_empty() => (host, format!("/{}", path)),
+        Some((host, _)) => (host, "/v1/traces".to_string()),
+        None => (rest, "/v1/traces".to_string()),
+    };
+    let body = trace.to_otlp_json().to_string();
+
+    let addr = host
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve OTLP endpoint {}", host))?
+        .next()
+        .with_context(|| format!("OTLP endpoint {} resolves to no address", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, EXPORT_TIMEOUT)
+        .with_context(|| format!("Failed to connect to OTLP endpoint {}", host))?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.split_whitespace().nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        bail!("OTLP collector returned {:?}", response.lines().next().unwrap_or(""));
+    }
+    Ok(())
+}
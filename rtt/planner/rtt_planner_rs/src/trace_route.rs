@@ -0,0 +1,140 @@
+//! Single-route tracing
+//!
+//! `rtt-planner trace-route <routes.json> <from> <to> [--manifests-dir <dir>] [options]`
+//! plans the routes with the given options, as the main command would, and
+//! reports what happened to every occurrence of `from -> to`: its
+//! `route_id`, whether a pruning pass dropped it and why, and otherwise the
+//! batch it landed in and what decided that batch. With capacities from
+//! `--manifests-dir` and `--optimize`, it also reports whether the
+//! admission solver selected the route.
+
+use crate::prune::DropReason;
+use crate::{build_plan, input, parse_options, telemetry, validate_path, Inputs, Options, Route};
+use anyhow::{bail, Context, Result};
+use std::fs;
+
+/// What happened to one occurrence of the traced route.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Dropped(DropReason),
+    /// Planned in `batch`, for the reason given.
+    Planned { batch: String, why: String },
+}
+
+#[derive(Debug)]
+pub struct RouteTrace {
+    pub route_id: String,
+    /// Matching routes after range and bidirectional expansion.
+    pub occurrences: Vec<Outcome>,
+    /// `Some` when the capacity solver ran: whether it admitted the route.
+    pub solver_selected: Option<bool>,
+}
+
+fn batch_reason(route: &Route, opts: &Options) -> String {
+    if !route.after.is_empty() {
+        format!("layered after its prerequisites {}", route.after.join(", "))
+    } else if opts.preserve_order {
+        "--preserve-order keeps every route in one batch".to_string()
+    } else if opts.batching.is_set() {
+        format!("{:?} batching, heaviest routes first", opts.batching.strategy)
+    } else {
+        "no batching options: every route is in the first batch".to_string()
+    }
+}
+
+pub fn trace_route(routes: Vec<Route>, from: &str, to: &str, opts: &Options, inputs: &Inputs) -> Result<RouteTrace> {
+    let matches = |r: &Route| r.from == from && r.to == to;
+    // The identity may include labels, so hash the loaded route, not just its endpoints
+    let loaded = routes.iter().find(|r| matches(r)).cloned();
+    let (plan, dropped) = build_plan(routes, opts, inputs, &mut telemetry::Trace::new())?;
+    let traced = loaded
+        .or_else(|| plan.routes_add.iter().find(|r| matches(r)).cloned())
+        .unwrap_or_else(|| Route { from: from.into(), to: to.into(), ..Default::default() });
+    let route_id = opts.identity_keys.route_id(&traced);
+
+    let mut occurrences: Vec<Outcome> = dropped.iter().filter(|d| matches(&d.route)).map(|d| Outcome::Dropped(d.reason)).collect();
+    let batches = plan.route_batches(&opts.identity_keys);
+    for route in plan.routes_add.iter().filter(|r| matches(r)) {
+        let batch = batches.batch_of(route).unwrap_or_default().to_string();
+        occurrences.push(Outcome::Planned { batch, why: batch_reason(route, opts) });
+    }
+    let solver_selected = (!inputs.capacities.is_empty() && !occurrences.is_empty())
+        .then(|| !occurrences.contains(&Outcome::Dropped(DropReason::OverCapacity)));
+    Ok(RouteTrace { route_id, occurrences, solver_selected })
+}
+
+pub fn cmd_trace_route(args: &[String]) -> Result<()> {
+    let mut manifests_dir = None;
+    let mut rest = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--manifests-dir" => manifests_dir = Some(it.next().context("--manifests-dir requires a value")?.clone()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    let (positional, opts) = parse_options(&rest)?;
+    let [routes_arg, from, to] = positional.as_slice() else {
+        eprintln!("usage: rtt-planner trace-route <routes.json> <from> <to> [--manifests-dir <dir>] [options]");
+        bail!("Invalid arguments");
+    };
+    let path = validate_path(routes_arg, "routes file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read routes file: {:?}", path))?;
+    let routes = input::parse_routes(&content, opts.input_format)?.routes;
+    let manifests_dir = manifests_dir.map(|d| validate_path(&d, "manifests directory")).transpose()?;
+    let inputs = Inputs::load(&opts, manifests_dir.as_deref())?;
+
+    let trace = trace_route(routes, from, to, &opts, &inputs)?;
+    println!("route {} -> {}", from, to);
+    println!("  route_id: {}", trace.route_id);
+    if trace.occurrences.is_empty() {
+        println!("  not in the input, even after expansion");
+    }
+    for (i, outcome) in trace.occurrences.iter().enumerate() {
+        match outcome {
+            Outcome::Dropped(reason) => println!("  occurrence {}: dropped ({:?})", i + 1, reason),
+            Outcome::Planned { batch, why } => println!("  occurrence {}: planned in {} ({})", i + 1, batch, why),
+        }
+    }
+    match trace.solver_selected {
+        Some(true) => println!("  solver: admitted within manifest capacities"),
+        Some(false) => println!("  solver: not admitted within manifest capacities"),
+        None => println!("  solver: not run"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_trace_through_dedup() {
+        let route = |from: &str, to: &str, env: &str| Route {
+            from: from.into(),
+            to: to.into(),
+            labels: BTreeMap::from([("env".into(), env.into())]),
+            ..Default::default()
+        };
+        let routes = vec![route("a", "b", "prod"), route("b", "c", "prod"), route("a", "b", "dev")];
+        let opts = Options::default();
+        let inputs = Inputs::load(&opts, None).unwrap();
+
+        let trace = trace_route(routes.clone(), "a", "b", &opts, &inputs).unwrap();
+        assert_eq!(trace.route_id, opts.identity_keys.route_id(&routes[0]));
+        assert_eq!(trace.solver_selected, None);
+        assert_eq!(trace.occurrences.len(), 2);
+        assert_eq!(trace.occurrences[0], Outcome::Dropped(DropReason::Duplicate));
+        assert!(matches!(&trace.occurrences[1], Outcome::Planned { batch, .. } if batch == "BATCH-1"));
+
+        let missing = trace_route(routes.clone(), "x", "y", &opts, &inputs).unwrap();
+        assert!(missing.occurrences.is_empty());
+
+        // With labels in the identity, the id is that of the loaded route
+        let opts = Options { identity_keys: "from,to,labels.env".parse().unwrap(), ..Default::default() };
+        let trace = trace_route(routes.clone(), "a", "b", &opts, &inputs).unwrap();
+        assert_eq!(trace.route_id, opts.identity_keys.route_id(&routes[0]));
+        assert_ne!(trace.route_id, opts.identity_keys.route_id(&Route { from: "a".into(), to: "b".into(), ..Default::default() }));
+    }
+}
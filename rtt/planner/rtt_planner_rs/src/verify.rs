@@ -0,0 +1,1039 @@
+//! Plan integrity and signature verification
+//!
+//! A plan verifies when its stored `plan_id` matches the hash of its
+//! canonical bytes and its signature is a valid ed25519 signature over those
+//! same bytes.
+//!
+//! Public keys come either bare on the command line, where each signature is
+//! tried against every key, or from a `--keyring` file, where each signature
+//! is checked only against the entry with its `key_id`:
+//!
+//! ```json
+//! {"keys": [{"key_id": "dev", "alg": "ed25519", "public_key": "<base64>"}]}
+//! ```
+//!
+//! A bare JSON array of entries is accepted as well. Either way a key may
+//! also be an OpenSSH `ssh-ed25519 AAAA...` line, for plans signed through
+//! an SSH agent.
+//!
+//! `--max-sig-age 30d` additionally rejects signatures whose `signed_at` is
+//! older than the window, or missing, to enforce re-signing after rotation.
+//! A plan whose `valid_until` has passed always fails.
+//!
+//! `verify --all <plan.json>... --keyring <keys.json>` checks many plans at
+//! once, verifying all their signatures in one ed25519 batch and falling
+//! back to one-by-one checks only to name the bad ones.
+//!
+//! `--manifests-dir <dir>` also recomputes the digest of the manifests and
+//! requires it to match the plan's signed `manifests_digest`, catching a
+//! plan applied against manifests that drifted after it was made.
+//!
+//! `verify --chain <plan.json>... --keyring <keys.json>` verifies the plans
+//! as `--all` does and also walks their `depends_on` links: every plan_id a
+//! plan depends on must be one of the given plans, and the links must not
+//! form a cycle. On success it prints the order to apply them in.
+//!
+//! A plan written with `--format jwt` is verified as a JWS: its signature
+//! over the token, then the `plan_id` claim against the payload.
+//!
+//! `--clock-skew 60s` widens both comparisons by the given tolerance, so a
+//! verifier whose clock runs slightly ahead of the signer's does not reject
+//! plans right at the boundary.
+//!
+//! A `VerifyCache` remembers signature results for a while, so callers that
+//! verify the same plan repeatedly skip the ed25519 work. Entries are keyed
+//! on the hash of the signed bytes with the key_id, signature and public
+//! key, so any change to the plan's canonical bytes misses the cache.
+
+use crate::{compute_plan_id, hash_bytes, jwt, manifest, signed_bytes, sshagent, unix_now, validate_path, Plan, Sign};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long `verify` trusts a cached signature result.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+fn decode_key(public_key_b64: &str) -> Result<VerifyingKey> {
+    // Raw base64 keys, or OpenSSH `ssh-ed25519 ...` lines for agent signatures
+    let key: [u8; 32] = if public_key_b64.starts_with("ssh-ed25519 ") {
+        sshagent::parse_public_key(public_key_b64)?
+    } else {
+        STANDARD
+            .decode(public_key_b64)
+            .context("Public key is not valid base64")?
+            .try_into()
+            .map_err(|_| anyhow!("Public key must be 32 bytes"))?
+    };
+    Ok(VerifyingKey::from_bytes(&key)?)
+}
+
+fn decode_signature(sign: &Sign) -> Result<Signature> {
+    if sign.alg != "ed25519" {
+        bail!("Unsupported signature algorithm: {}", sign.alg);
+    }
+    let sig: [u8; 64] = STANDARD
+        .decode(&sign.sig)
+        .context("Signature is not valid base64")?
+        .try_into()
+        .map_err(|_| anyhow!("Signature must be 64 bytes"))?;
+    Ok(Signature::from_bytes(&sig))
+}
+
+fn mismatch(sign: &Sign) -> String {
+    format!("Signature by {} does not match plan content", sign.key_id)
+}
+
+pub fn verify_signature(plan: &Plan, sign: &Sign, public_key_b64: &str) -> Result<()> {
+    let sig = decode_signature(sign)?;
+    let key = decode_key(public_key_b64)?;
+    key.verify(&signed_bytes(plan, sign.signed_at)?, &sig).map_err(|_| anyhow!(mismatch(sign)))
+}
+
+/// Verify many ed25519 signatures with one batch verification, which is
+/// much cheaper than checking them one by one. A failed batch does not say
+/// which signature is bad, so then each is checked on its own. Returns the
+/// indices of the invalid signatures, empty when all are valid.
+pub fn verify_batch(messages: &[&[u8]], signatures: &[Signature], keys: &[VerifyingKey]) -> Vec<usize> {
+    if ed25519_dalek::verify_batch(messages, signatures, keys).is_ok() {
+        return Vec::new();
+    }
+    (0..messages.len()).filter(|&i| keys[i].verify(messages[i], &signatures[i]).is_err()).collect()
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct KeyringEntry {
+    pub key_id: String,
+    pub alg: String,
+    pub public_key: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyringFile {
+    Keys { keys: Vec<KeyringEntry> },
+    List(Vec<KeyringEntry>),
+}
+
+pub fn load_keyring(path: &Path) -> Result<Vec<KeyringEntry>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read keyring file: {:?}", path))?;
+    let file: KeyringFile = serde_json::from_str(&content)
+        .with_context(|| "Failed to parse keyring JSON")?;
+    Ok(match file {
+        KeyringFile::Keys { keys } | KeyringFile::List(keys) => keys,
+    })
+}
+
+/// Public keys to verify against.
+pub enum PublicKeys {
+    /// Bare base64 keys; a signature is valid if any of them verifies it.
+    Any(Vec<String>),
+    /// Keys by `key_id`; a signature whose `key_id` is missing is unverifiable.
+    Keyring(Vec<KeyringEntry>),
+}
+
+/// Parse a duration such as `90s`, `45m`, `12h` or `30d` into seconds.
+pub fn parse_age(s: &str) -> Result<u64> {
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86_400,
+        _ => bail!("Duration must end in s, m, h or d, got: {}", s),
+    };
+    match s[..s.len() - 1].parse::<u64>() {
+        Ok(n) => n.checked_mul(unit).with_context(|| format!("Duration out of range: {}", s)),
+        Err(_) => bail!("Invalid duration: {}", s),
+    }
+}
+
+/// Signatures older than `max_secs` at time `now` (all in seconds) fail
+/// verification even when cryptographically valid. `skew_secs` is added to
+/// the window to absorb clock differences between signer and verifier, and
+/// bounds how far in the future `signed_at` may be.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxAge {
+    pub now: u64,
+    pub max_secs: u64,
+    pub skew_secs: u64,
+}
+
+impl MaxAge {
+    fn check(&self, sign: &Sign) -> Option<String> {
+        match sign.signed_at {
+            None => Some("no signed_at, so its age cannot be checked".to_string()),
+            Some(t) if t > self.now.saturating_add(self.skew_secs) => Some(format!(
+                "signed {}s in the future, beyond the clock skew of {}s",
+                t - self.now,
+                self.skew_secs
+            )),
+            Some(t) if self.now.saturating_sub(t) > self.max_secs.saturating_add(self.skew_secs) => Some(format!(
+                "signed {}s ago, older than the --max-sig-age window of {}s (clock skew {}s)",
+                self.now - t,
+                self.max_secs,
+                self.skew_secs
+            )),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Fail if the plan's `valid_until` is more than `skew_secs` before `now`.
+pub fn check_expiry(plan: &Plan, now: u64, skew_secs: u64) -> Result<()> {
+    match plan.valid_until {
+        Some(until) if now > until.saturating_add(skew_secs) => bail!(
+            "Plan expired {}s ago (valid_until {}, clock skew {}s)",
+            now - until,
+            until,
+            skew_secs
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// A warning when the plan's `valid_until` is less than `warn_secs` away,
+/// for plans that still pass `check_expiry` but need renewing soon.
+pub fn expiry_warning(plan: &Plan, now: u64, warn_secs: u64) -> Option<String> {
+    let until = plan.valid_until?;
+    if now > until {
+        Some(format!("Plan expired {}s ago (valid_until {}), accepted within clock skew", now - until, until))
+    } else if until - now < warn_secs {
+        Some(format!("Plan expires in {}s (valid_until {}); renew it", until - now, until))
+    } else {
+        None
+    }
+}
+
+/// Outcome of checking one signature carried by a plan.
+#[derive(Debug)]
+pub struct SignatureCheck {
+    pub key_id: String,
+    /// `None` when a supplied key verified the signature.
+    pub error: Option<String>,
+}
+
+impl SignatureCheck {
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Signature results by (signed bytes hash, key_id, sig, public key).
+type CacheKey = (String, String, String, String);
+
+/// Verification results remembered for `ttl`. Safe to share between
+/// threads; `hits` counts lookups answered from the cache.
+pub struct VerifyCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, (Instant, Option<String>)>>,
+    hits: AtomicU64,
+}
+
+impl VerifyCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entries: Mutex::new(HashMap::new()), hits: AtomicU64::new(0) }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// `verify_signature` as an error string, answered from the cache while
+    /// the entry is fresh.
+    fn verify(&self, plan: &Plan, sign: &Sign, public_key_b64: &str) -> Option<String> {
+        let bytes = match signed_bytes(plan, sign.signed_at) {
+            Ok(bytes) => bytes,
+            Err(e) => return Some(e.to_string()),
+        };
+        let key = (hash_bytes(&bytes), sign.key_id.clone(), sign.sig.clone(), public_key_b64.to_string());
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match entries.get(&key) {
+            Some((at, result)) if at.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return result.clone();
+            }
+            _ => {}
+        }
+        let result = verify_signature(plan, sign, public_key_b64).err().map(|e| e.to_string());
+        entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), result.clone()));
+        result
+    }
+}
+
+fn check_signature(plan: &Plan, sign: &Sign, keys: &PublicKeys, cache: Option<&VerifyCache>) -> Option<String> {
+    check_with_keys(sign, keys, |key| match cache {
+        Some(cache) => cache.verify(plan, sign, key),
+        None => verify_signature(plan, sign, key).err().map(|e| e.to_string()),
+    })
+}
+
+/// Run `verify` with every candidate key for `sign`: any key when bare, the
+/// `key_id` entry of a keyring. `None` once one verifies, else the error.
+fn check_with_keys(sign: &Sign, keys: &PublicKeys, verify: impl Fn(&str) -> Option<String>) -> Option<String> {
+    match keys {
+        PublicKeys::Any(keys) => {
+            let mut error = Some("no public key supplied".to_string());
+            for key in keys {
+                match verify(key) {
+                    None => return None,
+                    e => error = e,
+                }
+            }
+            error
+        }
+        PublicKeys::Keyring(entries) => match keyring_key(entries, sign) {
+            Ok(key) => verify(key),
+            Err(e) => Some(e),
+        },
+    }
+}
+
+/// Check a JWT plan's signature over its JWS signing input, and its `iat`
+/// against the age window.
+pub fn verify_token(token: &jwt::Token, keys: &PublicKeys, max_age: Option<MaxAge>) -> SignatureCheck {
+    let sign = token.sign();
+    let error = check_with_keys(&sign, keys, |key| {
+        let sig = decode_signature(&sign).map_err(|e| e.to_string());
+        match (decode_key(key), sig) {
+            (Ok(key), Ok(sig)) => key.verify(token.signing_input.as_bytes(), &sig).err().map(|_| mismatch(&sign)),
+            (Err(e), _) => Some(e.to_string()),
+            (_, Err(e)) => Some(e),
+        }
+    });
+    SignatureCheck { key_id: sign.key_id.clone(), error: error.or_else(|| max_age.and_then(|age| age.check(&sign))) }
+}
+
+/// The keyring's public key for `sign`, or why there is none.
+fn keyring_key<'a>(entries: &'a [KeyringEntry], sign: &Sign) -> Result<&'a str, String> {
+    let Some(entry) = entries.iter().find(|e| e.key_id == sign.key_id) else {
+        return Err(format!("unverifiable: key_id {} is not in the keyring", sign.key_id));
+    };
+    if entry.alg != sign.alg {
+        return Err(format!("keyring key {} is {}, signature is {}", entry.key_id, entry.alg, sign.alg));
+    }
+    Ok(&entry.public_key)
+}
+
+/// `check_signatures` for several plans against a keyring, verifying every
+/// signature that has a key in a single batch. Results are per plan, in
+/// order.
+pub fn check_signatures_batched(
+    plans: &[Plan],
+    keyring: &[KeyringEntry],
+    max_age: Option<MaxAge>,
+) -> Result<Vec<Vec<SignatureCheck>>> {
+    let mut checks: Vec<Vec<SignatureCheck>> = Vec::with_capacity(plans.len());
+    // Batched signatures: (plan index, signature index) and the inputs
+    let (mut slots, mut messages, mut signatures, mut keys) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (p, plan) in plans.iter().enumerate() {
+        let mut plan_checks = Vec::new();
+        for (i, sign) in plan.all_signatures().enumerate() {
+            let decoded = keyring_key(keyring, sign).and_then(|key| {
+                let key = decode_key(key).map_err(|e| e.to_string())?;
+                Ok((key, decode_signature(sign).map_err(|e| e.to_string())?))
+            });
+            let error = match decoded {
+                Ok((key, sig)) => {
+                    slots.push((p, i));
+                    messages.push(signed_bytes(plan, sign.signed_at)?);
+                    signatures.push(sig);
+                    keys.push(key);
+                    None
+                }
+                Err(e) => Some(e),
+            };
+            plan_checks.push(SignatureCheck { key_id: sign.key_id.clone(), error });
+        }
+        checks.push(plan_checks);
+    }
+
+    let messages: Vec<&[u8]> = messages.iter().map(Vec::as_slice).collect();
+    for bad in verify_batch(&messages, &signatures, &keys) {
+        let (p, i) = slots[bad];
+        checks[p][i].error = plans[p].all_signatures().nth(i).map(mismatch);
+    }
+    if let Some(age) = max_age {
+        for (plan, plan_checks) in plans.iter().zip(&mut checks) {
+            for (sign, check) in plan.all_signatures().zip(plan_checks) {
+                check.error = check.error.take().or_else(|| age.check(sign));
+            }
+        }
+    }
+    Ok(checks)
+}
+
+/// Check every signature on the plan against the supplied public keys and,
+/// if given, the age window.
+/// With a `cache`, signature results are reused and recorded there; the
+/// age window is always applied afresh.
+pub fn check_signatures(
+    plan: &Plan,
+    keys: &PublicKeys,
+    max_age: Option<MaxAge>,
+    cache: Option<&VerifyCache>,
+) -> Vec<SignatureCheck> {
+    plan.all_signatures()
+        .map(|sign| SignatureCheck {
+            key_id: sign.key_id.clone(),
+            error: check_signature(plan, sign, keys, cache).or_else(|| max_age.and_then(|age| age.check(sign))),
+        })
+        .collect()
+}
+
+/// Check that the manifests in `dir` still have the digest the plan
+/// recorded. A plan without a recorded digest fails.
+pub fn check_manifests(plan: &Plan, dir: &Path) -> Result<()> {
+    let Some(recorded) = &plan.manifests_digest else {
+        bail!("Plan records no manifests_digest; plan with --record-manifests-digest");
+    };
+    let current = manifest::digest(dir)?;
+    if &current != recorded {
+        bail!("Manifests have changed since planning: recorded {}, now {}", recorded, current);
+    }
+    Ok(())
+}
+
+/// Check the `depends_on` links among `plans` and return an apply order,
+/// prerequisites first: every dependency must be one of `plans` and there
+/// must be no cycle.
+pub fn check_chain(plans: &[Plan]) -> Result<Vec<usize>> {
+    let mut by_id = std::collections::BTreeMap::new();
+    for (i, plan) in plans.iter().enumerate() {
+        if by_id.insert(plan.plan_id.as_str(), i).is_some() {
+            bail!("Plan {} is given more than once", plan.plan_id);
+        }
+    }
+    let mut deps = Vec::with_capacity(plans.len());
+    for plan in plans {
+        let mut plan_deps = Vec::new();
+        for id in &plan.depends_on {
+            let Some(&dep) = by_id.get(id.as_str()) else {
+                bail!("Plan {} depends on {}, which is not among the given plans", plan.plan_id, id);
+            };
+            plan_deps.push(dep);
+        }
+        deps.push(plan_deps);
+    }
+
+    // Depth-first, so a plan is placed after everything it depends on
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        Open,
+        Done,
+    }
+    fn visit(i: usize, deps: &[Vec<usize>], marks: &mut [Mark], path: &mut Vec<usize>, order: &mut Vec<usize>) -> Option<Vec<usize>> {
+        match marks[i] {
+            Mark::Done => return None,
+            Mark::Open => {
+                let start = path.iter().position(|&p| p == i).expect("open plans are on the path");
+                return Some(path[start..].iter().copied().chain([i]).collect());
+            }
+            Mark::New => {}
+        }
+        marks[i] = Mark::Open;
+        path.push(i);
+        for &dep in &deps[i] {
+            if let Some(cycle) = visit(dep, deps, marks, path, order) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        marks[i] = Mark::Done;
+        order.push(i);
+        None
+    }
+    let (mut marks, mut order) = (vec![Mark::New; plans.len()], Vec::with_capacity(plans.len()));
+    for i in 0..plans.len() {
+        if let Some(cycle) = visit(i, &deps, &mut marks, &mut Vec::new(), &mut order) {
+            let ids: Vec<&str> = cycle.iter().map(|&p| plans[p].plan_id.as_str()).collect();
+            bail!("Plans depend on each other in a cycle: {}", ids.join(" -> "));
+        }
+    }
+    Ok(order)
+}
+
+/// Check that the stored `plan_id` is the hash of the plan's canonical
+/// bytes, independent of any signature.
+pub fn check_plan_id(plan: &Plan) -> Result<()> {
+    let pid = compute_plan_id(plan)?;
+    if pid != plan.plan_id {
+        bail!("plan_id mismatch: stored {}, computed {}", plan.plan_id, pid);
+    }
+    Ok(())
+}
+
+/// Verify `plan_id` and the plan's signatures. The plan passes if at least
+/// one signature is valid; with `require_all_current`, every signature
+/// present must be valid. An unsigned plan always fails, and it is reported
+/// differently from a plan whose only signatures are stale.
+pub fn verify_plan(
+    plan: &Plan,
+    keys: &PublicKeys,
+    require_all_current: bool,
+    max_age: Option<MaxAge>,
+) -> Result<Vec<SignatureCheck>> {
+    verify_plan_cached(plan, keys, require_all_current, max_age, None)
+}
+
+/// `verify_plan`, going through `cache` when one is given.
+pub fn verify_plan_cached(
+    plan: &Plan,
+    keys: &PublicKeys,
+    require_all_current: bool,
+    max_age: Option<MaxAge>,
+    cache: Option<&VerifyCache>,
+) -> Result<Vec<SignatureCheck>> {
+    check_plan_id(plan)?;
+    let checks = check_signatures(plan, keys, max_age, cache);
+    require_valid(&checks, require_all_current)?;
+    Ok(checks)
+}
+
+/// The pass/fail rule of `verify_plan`, applied to computed checks.
+fn require_valid(checks: &[SignatureCheck], require_all_current: bool) -> Result<()> {
+    let invalid = checks.iter().filter(|c| !c.is_valid()).count();
+    if checks.is_empty() {
+        bail!("Plan is not signed");
+    }
+    if invalid == checks.len() {
+        bail!("No valid signature: all {} signature(s) are invalid", invalid);
+    }
+    if require_all_current && invalid > 0 {
+        bail!("{} of {} signature(s) are invalid", invalid, checks.len());
+    }
+    Ok(())
+}
+
+fn print_checks(checks: &[SignatureCheck]) {
+    for (i, check) in checks.iter().enumerate() {
+        match &check.error {
+            None => eprintln!("[OK] signature {} (key_id {}): valid", i + 1, check.key_id),
+            Some(e) => eprintln!("[WARN] signature {} (key_id {}): invalid: {}", i + 1, check.key_id, e),
+        }
+    }
+}
+
+pub fn load_plan(path: &Path) -> Result<Plan> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read plan file: {:?}", path))?;
+    if jwt::is_token(&content) {
+        bail!("{:?} is a JWT plan; only `verify <plan.jwt> <keys>` reads tokens", path);
+    }
+    serde_json::from_str(&content).with_context(|| "Failed to parse plan JSON")
+}
+
+/// `rtt-planner verify-id <plan.json>`: the plan_id integrity gate alone.
+pub fn cmd_verify_id(args: &[String]) -> Result<()> {
+    let [plan_arg] = args else {
+        eprintln!("usage: rtt-planner verify-id <plan.json>");
+        bail!("Invalid arguments");
+    };
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    check_plan_id(&plan)?;
+    println!("{}", plan.plan_id);
+    eprintln!("[OK] plan_id matches canonical content");
+    Ok(())
+}
+
+fn print_usage() {
+    eprintln!("usage: rtt-planner verify <plan.json> <pub_key_b64>... [--require-all-current] [--max-sig-age <age>] [--clock-skew <age>]");
+    eprintln!("       rtt-planner verify <plan.json> --keyring <keys.json> [--require-all-current] [--max-sig-age <age>] [--clock-skew <age>]");
+    eprintln!("       rtt-planner verify --all <plan.json>... --keyring <keys.json> [same options]");
+    eprintln!("       rtt-planner verify --chain <plan.json>... --keyring <keys.json> [same options]");
+    eprintln!("                          [--manifests-dir <dir>] [--warn-before <age>] (any form)");
+}
+
+pub fn cmd_verify(args: &[String]) -> Result<()> {
+    let mut require_all_current = false;
+    let mut all = false;
+    let mut chain = false;
+    let mut keyring = None;
+    let mut max_secs = None;
+    let mut skew_secs = 0;
+    let mut warn_secs = None;
+    let mut manifests_dir = None;
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--require-all-current" => require_all_current = true,
+            "--all" => all = true,
+            "--chain" => {
+                all = true;
+                chain = true;
+            }
+            "--keyring" => keyring = Some(it.next().context("--keyring requires a value")?),
+            "--max-sig-age" => max_secs = Some(parse_age(it.next().context("--max-sig-age requires a value")?)?),
+            "--manifests-dir" => manifests_dir = Some(it.next().context("--manifests-dir requires a value")?),
+            "--clock-skew" => skew_secs = parse_age(it.next().context("--clock-skew requires a value")?)?,
+            "--warn-before" => warn_secs = Some(parse_age(it.next().context("--warn-before requires a value")?)?),
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let now = unix_now();
+    let max_age = max_secs.map(|max_secs| MaxAge { now, max_secs, skew_secs });
+    let manifests_dir = manifests_dir.map(|dir| validate_path(dir, "manifests directory")).transpose()?;
+    // Checks beyond the signatures, the same for every plan
+    let finish = |plan: &Plan| -> Result<()> {
+        plan.validate()?;
+        check_expiry(plan, now, skew_secs)?;
+        if let Some(warning) = warn_secs.and_then(|secs| expiry_warning(plan, now, secs)) {
+            eprintln!("[WARN] {}", warning);
+        }
+        if let Some(dir) = &manifests_dir {
+            check_manifests(plan, dir)?;
+        }
+        Ok(())
+    };
+
+    if all {
+        let (Some(path), false) = (keyring, positional.is_empty()) else {
+            print_usage();
+            bail!("Invalid arguments");
+        };
+        let keyring = load_keyring(&validate_path(path, "keyring file")?)?;
+        let plans = positional
+            .iter()
+            .map(|p| validate_path(p, "plan file").and_then(|p| load_plan(&p)))
+            .collect::<Result<Vec<_>>>()?;
+        let checks = check_signatures_batched(&plans, &keyring, max_age)?;
+        let mut failed = 0;
+        for ((path, plan), checks) in positional.iter().zip(&plans).zip(&checks) {
+            print_checks(checks);
+            let result = check_plan_id(plan).and_then(|()| require_valid(checks, require_all_current)).and_then(|()| finish(plan));
+            match result {
+                Ok(()) => eprintln!("[OK] {}: verified {}", path, plan.plan_id),
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("[WARN] {}: {}", path, e);
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("{} of {} plan(s) failed verification", failed, plans.len());
+        }
+        if chain {
+            let order = check_chain(&plans)?;
+            eprintln!("[OK] Chain of {} plan(s), in apply order:", order.len());
+            for i in order {
+                eprintln!("  {} ({})", plans[i].plan_id, positional[i]);
+            }
+        }
+        println!("OK");
+        return Ok(());
+    }
+
+    let keys = match keyring {
+        Some(path) if positional.len() == 1 => PublicKeys::Keyring(load_keyring(&validate_path(path, "keyring file")?)?),
+        None if positional.len() >= 2 => PublicKeys::Any(positional[1..].to_vec()),
+        _ => {
+            print_usage();
+            bail!("Invalid arguments");
+        }
+    };
+
+    let path = validate_path(&positional[0], "plan file")?;
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read plan file: {:?}", path))?;
+    let plan = if jwt::is_token(&content) {
+        let token = jwt::decode(&content)?;
+        let checks = [verify_token(&token, &keys, max_age)];
+        print_checks(&checks);
+        check_plan_id(&token.plan)?;
+        require_valid(&checks, require_all_current)?;
+        token.plan
+    } else {
+        let plan: Plan = serde_json::from_str(&content).with_context(|| "Failed to parse plan JSON")?;
+        // The checks are printed before the pass/fail rule runs; the cache
+        // keeps that from verifying every signature twice
+        let cache = VerifyCache::new(CACHE_TTL);
+        print_checks(&check_signatures(&plan, &keys, max_age, Some(&cache)));
+        verify_plan_cached(&plan, &keys, require_all_current, max_age, Some(&cache))?;
+        eprintln!("[INFO] {} signature result(s) reused from the first pass", cache.hits());
+        plan
+    };
+    finish(&plan)?;
+    if manifests_dir.is_some() {
+        eprintln!("[OK] Manifests match the recorded digest");
+    }
+    println!("OK");
+    eprintln!("[OK] Plan verified: {}", plan.plan_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Route;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::BTreeMap;
+
+    fn signed_plan(sk: &SigningKey) -> Plan {
+        let mut plan = Plan {
+            plan_id: String::new(),
+            routes_add: vec![Route { from: "a".into(), to: "b".into(), ..Default::default() }],
+            order: vec!["BATCH-1".into()],
+            annotations: BTreeMap::from([("ticket".into(), "CHG-1234".into())]),
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(sign_with(&plan, sk, "dev"));
+        plan
+    }
+
+    fn sign_with(plan: &Plan, sk: &SigningKey, key_id: &str) -> Sign {
+        sign_at(plan, sk, key_id, None)
+    }
+
+    fn sign_at(plan: &Plan, sk: &SigningKey, key_id: &str, signed_at: Option<u64>) -> Sign {
+        let sig = sk.sign(&signed_bytes(plan, signed_at).unwrap());
+        Sign {
+            alg: "ed25519".into(),
+            key_id: key_id.into(),
+            sig: STANDARD.encode(sig.to_bytes()),
+            signed_at,
+        }
+    }
+
+    fn public_key(sk: &SigningKey) -> String {
+        STANDARD.encode(sk.verifying_key().to_bytes())
+    }
+
+    #[test]
+    fn test_altered_annotation_breaks_verification() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let pk = PublicKeys::Any(vec![public_key(&sk)]);
+        let mut plan = signed_plan(&sk);
+        verify_plan(&plan, &pk, false, None).unwrap();
+
+        plan.annotations.insert("ticket".into(), "CHG-9999".into());
+        let err = verify_plan(&plan, &pk, false, None).unwrap_err();
+        assert!(err.to_string().contains("plan_id mismatch"));
+
+        // Even with a matching id, the signature no longer covers the content
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        let checks = check_signatures(&plan, &pk, None, None);
+        assert!(checks[0].error.as_deref().unwrap().contains("does not match"));
+        assert!(verify_plan(&plan, &pk, false, None).is_err());
+    }
+
+    #[test]
+    fn test_check_plan_id() {
+        let mut plan = signed_plan(&SigningKey::from_bytes(&[7u8; 32]));
+        let path = std::env::temp_dir().join(format!("rtt-verify-id-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_vec(&plan).unwrap()).unwrap();
+        check_plan_id(&load_plan(&path).unwrap()).unwrap();
+
+        plan.routes_add[0].to = "evil".into();
+        fs::write(&path, serde_json::to_vec(&plan).unwrap()).unwrap();
+        let err = check_plan_id(&load_plan(&path).unwrap()).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.contains(&format!("stored {}", plan.plan_id)), "{}", err);
+        assert!(err.contains(&format!("computed {}", compute_plan_id(&plan).unwrap())), "{}", err);
+    }
+
+    #[test]
+    fn test_unsigned_plan_fails() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let mut plan = signed_plan(&sk);
+        plan.sign = None;
+        let err = verify_plan(&plan, &PublicKeys::Any(vec![public_key(&sk)]), false, None).unwrap_err();
+        assert_eq!(err.to_string(), "Plan is not signed");
+    }
+
+    #[test]
+    fn test_partially_signed_plan() {
+        let dev = SigningKey::from_bytes(&[7u8; 32]);
+        let ops = SigningKey::from_bytes(&[9u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&dev), public_key(&ops)]);
+
+        // `ops` signed an earlier revision that `dev` has since re-signed
+        let mut plan = signed_plan(&dev);
+        let mut earlier = signed_plan(&dev);
+        earlier.annotations.clear();
+        plan.signatures.push(sign_with(&earlier, &ops, "ops"));
+
+        let checks = verify_plan(&plan, &keys, false, None).unwrap();
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].key_id, "dev");
+        assert!(checks[0].is_valid());
+        assert_eq!(checks[1].key_id, "ops");
+        assert!(!checks[1].is_valid());
+
+        let err = verify_plan(&plan, &keys, true, None).unwrap_err();
+        assert!(err.to_string().contains("1 of 2 signature(s) are invalid"));
+    }
+
+    #[test]
+    fn test_keyring_multisig() {
+        let dev = SigningKey::from_bytes(&[7u8; 32]);
+        let ops = SigningKey::from_bytes(&[9u8; 32]);
+        let mut plan = signed_plan(&dev);
+        plan.signatures.push(sign_with(&plan, &ops, "ops"));
+        plan.signatures.push(sign_with(&plan, &ops, "qa"));
+
+        let keyring = serde_json::json!({"keys": [
+            {"key_id": "dev", "alg": "ed25519", "public_key": public_key(&dev)},
+            {"key_id": "ops", "alg": "ed25519", "public_key": public_key(&ops)},
+        ]});
+        let path = std::env::temp_dir().join(format!("rtt-keyring-{}.json", std::process::id()));
+        fs::write(&path, keyring.to_string()).unwrap();
+        let keys = PublicKeys::Keyring(load_keyring(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let checks = verify_plan(&plan, &keys, false, None).unwrap();
+        assert!(checks[0].is_valid() && checks[1].is_valid());
+        // `qa` used a real key, but the keyring cannot vouch for it
+        assert!(checks[2].error.as_deref().unwrap().contains("not in the keyring"));
+        assert!(verify_plan(&plan, &keys, true, None).is_err());
+
+        // Keys are matched by key_id, not tried against every signature
+        let swapped = PublicKeys::Keyring(vec![KeyringEntry {
+            key_id: "dev".into(),
+            alg: "ed25519".into(),
+            public_key: public_key(&ops),
+        }]);
+        assert!(verify_plan(&plan, &swapped, false, None).is_err());
+    }
+
+    #[test]
+    fn test_max_sig_age() {
+        const DAY: u64 = 86_400;
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&sk)]);
+        let now = 1_700_000_000;
+        let mut plan = signed_plan(&sk);
+        plan.sign = Some(sign_at(&plan, &sk, "dev", Some(now - 40 * DAY)));
+
+        verify_plan(&plan, &keys, false, None).unwrap();
+        let window = Some(MaxAge { now, max_secs: parse_age("30d").unwrap(), skew_secs: 0 });
+        let checks = check_signatures(&plan, &keys, window, None);
+        assert!(checks[0].error.as_deref().unwrap().contains("older than"), "{:?}", checks);
+        assert!(verify_plan(&plan, &keys, false, window).is_err());
+
+        // signed_at is authenticated: backdating it breaks the signature
+        plan.sign.as_mut().unwrap().signed_at = Some(now - DAY);
+        let checks = check_signatures(&plan, &keys, window, None);
+        assert!(checks[0].error.as_deref().unwrap().contains("does not match"));
+
+        plan.sign = Some(sign_at(&plan, &sk, "dev", Some(now - DAY)));
+        verify_plan(&plan, &keys, false, window).unwrap();
+
+        // Without a timestamp the age is unknown
+        plan.sign = Some(sign_with(&plan, &sk, "dev"));
+        assert!(verify_plan(&plan, &keys, false, window).is_err());
+
+        // A signature just outside the window passes only within the skew
+        plan.sign = Some(sign_at(&plan, &sk, "dev", Some(now - 30 * DAY - 30)));
+        assert!(verify_plan(&plan, &keys, false, window).is_err());
+        let skewed = Some(MaxAge { now, max_secs: 30 * DAY, skew_secs: 60 });
+        verify_plan(&plan, &keys, false, skewed).unwrap();
+
+        // A signature from the future passes only within the skew
+        plan.sign = Some(sign_at(&plan, &sk, "dev", Some(now + 30)));
+        verify_plan(&plan, &keys, false, skewed).unwrap();
+        let checks = check_signatures(&plan, &keys, window, None);
+        assert!(checks[0].error.as_deref().unwrap().contains("in the future"), "{:?}", checks);
+
+        assert_eq!(parse_age("12h").unwrap(), 12 * 3600);
+        assert!(parse_age("30").is_err() && parse_age("d").is_err());
+        assert!(parse_age(&format!("{}d", u64::MAX / 2)).unwrap_err().to_string().contains("out of range"));
+    }
+
+    #[test]
+    fn test_clock_skew_expiry() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&sk)]);
+        let now = 1_700_000_000;
+        let mut plan = signed_plan(&sk);
+        plan.valid_until = Some(now - 30);
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(sign_with(&plan, &sk, "dev"));
+        verify_plan(&plan, &keys, false, None).unwrap();
+
+        // 30s past expiry: rejected outright, accepted with 60s of skew
+        let err = check_expiry(&plan, now, 0).unwrap_err().to_string();
+        assert!(err.contains("expired 30s ago"), "{}", err);
+        check_expiry(&plan, now, parse_age("60s").unwrap()).unwrap();
+        // Beyond the tolerance it is rejected again
+        assert!(check_expiry(&plan, now + 31, 60).is_err());
+        // A plan valid until the end of time never expires, whatever the skew
+        plan.valid_until = Some(u64::MAX);
+        check_expiry(&plan, now, 60).unwrap();
+
+        // valid_until is signed content: extending it breaks the plan_id
+        plan.valid_until = Some(now + 3600);
+        assert!(verify_plan(&plan, &keys, false, None).is_err());
+    }
+
+    #[test]
+    fn test_warn_before_expiry() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&sk)]);
+        let now = 1_700_000_000;
+        let mut plan = signed_plan(&sk);
+        plan.valid_until = Some(now + parse_age("24h").unwrap());
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(sign_with(&plan, &sk, "dev"));
+
+        // Inside a 48h window: a warning, but the plan still verifies
+        let warn = parse_age("48h").unwrap();
+        let warning = expiry_warning(&plan, now, warn).unwrap();
+        assert_eq!(warning, format!("Plan expires in 86400s (valid_until {}); renew it", now + 86400));
+        verify_plan(&plan, &keys, false, None).unwrap();
+        check_expiry(&plan, now, 0).unwrap();
+
+        assert_eq!(expiry_warning(&plan, now, parse_age("12h").unwrap()), None);
+        plan.valid_until = None;
+        assert_eq!(expiry_warning(&plan, now, warn), None);
+    }
+
+    #[test]
+    fn test_manifest_drift_fails_digest_check() {
+        let dir = std::env::temp_dir().join(format!("rtt-verify-manifests-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = r#"{"symbol": {"saddr": "rtt://core/api/metrics@1.0.0"}}"#;
+        fs::write(dir.join("metrics.json"), manifest).unwrap();
+
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&sk)]);
+        let mut plan = signed_plan(&sk);
+        assert!(check_manifests(&plan, &dir).is_err());
+        plan.manifests_digest = Some(manifest::digest(&dir).unwrap());
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.sign = Some(sign_with(&plan, &sk, "dev"));
+        verify_plan(&plan, &keys, false, None).unwrap();
+        check_manifests(&plan, &dir).unwrap();
+
+        // The signature over the old digest stays valid; the digest check does not
+        fs::write(dir.join("metrics.json"), manifest.replace("1.0.0", "1.1.0")).unwrap();
+        let drift = check_manifests(&plan, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        verify_plan(&plan, &keys, false, None).unwrap();
+        assert!(drift.unwrap_err().to_string().contains("Manifests have changed"));
+    }
+
+    #[test]
+    fn test_batch_verification_finds_culprit() {
+        let dev = SigningKey::from_bytes(&[7u8; 32]);
+        let ops = SigningKey::from_bytes(&[9u8; 32]);
+        let keyring = vec![
+            KeyringEntry { key_id: "dev".into(), alg: "ed25519".into(), public_key: public_key(&dev) },
+            KeyringEntry { key_id: "ops".into(), alg: "ed25519".into(), public_key: public_key(&ops) },
+        ];
+        let mut plans: Vec<Plan> = (0..4)
+            .map(|i| {
+                let mut plan = signed_plan(&dev);
+                plan.annotations.insert("ticket".into(), format!("CHG-{}", i));
+                plan.plan_id = compute_plan_id(&plan).unwrap();
+                plan.sign = Some(sign_with(&plan, &dev, "dev"));
+                plan.signatures.push(sign_with(&plan, &ops, "ops"));
+                plan
+            })
+            .collect();
+
+        let checks = check_signatures_batched(&plans, &keyring, None).unwrap();
+        assert!(checks.iter().flatten().all(|c| c.is_valid()));
+
+        // Plan 2's ops signature was made over plan 1's bytes
+        let wrong = sign_with(&plans[1], &ops, "ops");
+        plans[2].signatures[0] = wrong;
+        let checks = check_signatures_batched(&plans, &keyring, None).unwrap();
+        let bad: Vec<_> = checks
+            .iter()
+            .enumerate()
+            .flat_map(|(p, c)| c.iter().filter(|c| !c.is_valid()).map(move |c| (p, c.key_id.clone())))
+            .collect();
+        assert_eq!(bad, vec![(2, "ops".to_string())]);
+        assert!(checks[2][1].error.as_deref().unwrap().contains("does not match"));
+        // Per-plan results agree with the unbatched path
+        let one = check_signatures(&plans[2], &PublicKeys::Keyring(keyring.clone()), None, None);
+        assert_eq!(one.iter().map(|c| c.error.clone()).collect::<Vec<_>>(), checks[2].iter().map(|c| c.error.clone()).collect::<Vec<_>>());
+
+        // The raw batch API reports indices directly
+        let msgs: Vec<Vec<u8>> = plans.iter().map(|p| signed_bytes(p, None).unwrap()).collect();
+        let msg_refs: Vec<&[u8]> = msgs.iter().map(Vec::as_slice).collect();
+        let sigs: Vec<Signature> = plans.iter().map(|p| decode_signature(&p.signatures[0]).unwrap()).collect();
+        let keys = vec![ops.verifying_key(); 4];
+        assert_eq!(verify_batch(&msg_refs, &sigs, &keys), vec![2]);
+    }
+
+    #[test]
+    fn test_verify_cache_hits_and_invalidates() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keys = PublicKeys::Any(vec![public_key(&sk)]);
+        let mut plan = signed_plan(&sk);
+        let cache = VerifyCache::new(Duration::from_secs(60));
+
+        verify_plan_cached(&plan, &keys, true, None, Some(&cache)).unwrap();
+        assert_eq!(cache.hits(), 0);
+        verify_plan_cached(&plan, &keys, true, None, Some(&cache)).unwrap();
+        assert_eq!(cache.hits(), 1);
+
+        // Altered canonical bytes miss the cache and fail afresh
+        plan.routes_add[0].to = "c".into();
+        let checks = check_signatures(&plan, &keys, None, Some(&cache));
+        assert_eq!(cache.hits(), 1);
+        assert!(checks[0].error.as_deref().unwrap().contains("does not match"));
+        // The failure is cached too
+        check_signatures(&plan, &keys, None, Some(&cache));
+        assert_eq!(cache.hits(), 2);
+
+        // Expired entries are verified again
+        let expired = VerifyCache::new(Duration::ZERO);
+        check_signatures(&plan, &keys, None, Some(&expired));
+        check_signatures(&plan, &keys, None, Some(&expired));
+        assert_eq!(expired.hits(), 0);
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let sk = SigningKey::from_bytes(&[7u8; 32]);
+        let keyring = vec![KeyringEntry { key_id: "dev".into(), alg: "ed25519".into(), public_key: public_key(&sk) }];
+        let plan = |to: &str, depends_on: Vec<String>| {
+            let mut plan = Plan {
+                routes_add: vec![Route { from: "a".into(), to: to.into(), ..Default::default() }],
+                order: vec!["BATCH-1".into()],
+                depends_on,
+                ..Default::default()
+            };
+            plan.plan_id = compute_plan_id(&plan).unwrap();
+            plan.sign = Some(sign_with(&plan, &sk, "dev"));
+            plan
+        };
+        let base = plan("b", vec![]);
+        let middle = plan("c", vec![base.plan_id.clone()]);
+        let last = plan("d", vec![middle.plan_id.clone(), base.plan_id.clone()]);
+
+        // Given in any order, the chain verifies and comes out prerequisites first
+        let plans = vec![last, base, middle];
+        let checks = check_signatures_batched(&plans, &keyring, None).unwrap();
+        assert!(checks.iter().flatten().all(SignatureCheck::is_valid));
+        assert_eq!(check_chain(&plans).unwrap(), vec![1, 2, 0]);
+
+        // Without its prerequisite the chain fails, naming the missing id
+        let base_id = plans[1].plan_id.clone();
+        let orphan = [plan("c", vec![base_id.clone()])];
+        let err = check_chain(&orphan).unwrap_err().to_string();
+        assert!(err.contains(&format!("depends on {}, which is not among the given plans", base_id)), "{}", err);
+
+        // Plans cannot be their own prerequisites
+        let mut looped = plan("e", vec![]);
+        looped.depends_on = vec![looped.plan_id.clone()];
+        assert!(check_chain(&[looped]).unwrap_err().to_string().contains("cycle"));
+
+        // depends_on is signed content
+        let mut tampered = plans;
+        tampered[2].depends_on.clear();
+        assert!(check_plan_id(&tampered[2]).is_err());
+    }
+}
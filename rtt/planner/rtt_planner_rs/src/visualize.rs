@@ -0,0 +1,300 @@
+//! Plan visualization
+//!
+//! `rtt-planner visualize <plan.json> -o <plan.html> [--keyring <keys.json>]`
+//! writes a self-contained HTML page drawing the plan's route graph, for
+//! review without Graphviz. Routes are colored by batch, and clicking a
+//! batch in the legend hides or shows its routes. The page shows each
+//! signature's status: verified against the keyring when one is given,
+//! otherwise only who signed it.
+//!
+//! The plan data sits inline as JSON and the page carries its own small
+//! layout and SVG renderer, so viewing it needs no network access.
+
+use crate::verify::{check_plan_id, check_signatures_batched, load_keyring, load_plan, KeyringEntry};
+use crate::{validate_path, Plan};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs;
+
+/// Batch colors, in `order` position; they repeat past the end.
+const PALETTE: [&str; 8] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf"];
+
+#[derive(Serialize)]
+struct Batch {
+    name: String,
+    color: &'static str,
+}
+
+#[derive(Serialize)]
+struct Edge<'a> {
+    from: &'a str,
+    to: &'a str,
+    batch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Data<'a> {
+    plan_id: &'a str,
+    name: &'a str,
+    signatures: Vec<String>,
+    batches: Vec<Batch>,
+    routes: Vec<Edge<'a>>,
+}
+
+fn signature_status(plan: &Plan, keyring: Option<&[KeyringEntry]>) -> Result<Vec<String>> {
+    let mut status = Vec::new();
+    if let Err(e) = check_plan_id(plan) {
+        status.push(format!("plan_id check failed: {}", e));
+    }
+    match keyring {
+        Some(keyring) => {
+            let checks = check_signatures_batched(std::slice::from_ref(plan), keyring, None)?;
+            for check in checks.into_iter().flatten() {
+                status.push(match check.error {
+                    None => format!("{}: verified", check.key_id),
+                    Some(e) => format!("{}: {}", check.key_id, e),
+                });
+            }
+        }
+        None => status.extend(plan.all_signatures().map(|s| format!("{}: signed, not verified", s.key_id))),
+    }
+    if plan.all_signatures().next().is_none() {
+        status.push("unsigned".into());
+    }
+    Ok(status)
+}
+
+/// The page for `plan`.
+pub fn render(plan: &Plan, keyring: Option<&[KeyringEntry]>) -> Result<String> {
+    let batch_of = |batch: &Option<String>| batch.clone().or_else(|| plan.order.first().cloned());
+    let mut names: Vec<String> = plan.order.clone();
+    for route in &plan.routes_add {
+        if let Some(batch) = batch_of(&route.batch).filter(|b| !names.contains(b)) {
+            names.push(batch);
+        }
+    }
+    let data = Data {
+        plan_id: &plan.plan_id,
+        name: &plan.name,
+        signatures: signature_status(plan, keyring)?,
+        batches: names.into_iter().enumerate().map(|(i, name)| Batch { name, color: PALETTE[i % PALETTE.len()] }).collect(),
+        routes: plan
+            .routes_add
+            .iter()
+            .map(|r| Edge { from: &r.from, to: &r.to, batch: batch_of(&r.batch), weight: r.weight })
+            .collect(),
+    };
+    // A raw `<` could end the script element early or open a comment; `<`
+    // only occurs inside JSON strings, where `\u003c` means the same
+    let json = serde_json::to_string(&data)?.replace('<', "\\u003c");
+    let title = html_escape(if plan.name.is_empty() { &plan.plan_id } else { &plan.name });
+    Ok(fill(PAGE, &title, &json))
+}
+
+/// Fill the page's `{{title}}` and `{{data}}` slots in one pass, so text
+/// put in one slot is never taken for another.
+fn fill(page: &str, title: &str, data: &str) -> String {
+    let mut out = String::with_capacity(page.len() + title.len() + data.len());
+    let mut rest = page;
+    while let Some(i) = rest.find("{{") {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("{{title}}") {
+            out.push_str(title);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{{data}}") {
+            out.push_str(data);
+            rest = after;
+        } else {
+            out.push_str("{{");
+            rest = &rest[2..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub fn cmd_visualize(args: &[String]) -> Result<()> {
+    let (mut out, mut keyring) = (None, None);
+    let mut positional = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-o" => out = Some(it.next().context("-o requires a value")?),
+            "--keyring" => keyring = Some(it.next().context("--keyring requires a value")?),
+            a if a.starts_with("--") => bail!("Unknown option: {}", a),
+            _ => positional.push(arg),
+        }
+    }
+    let (Some(out), [plan_arg]) = (out, positional.as_slice()) else {
+        eprintln!("usage: rtt-planner visualize <plan.json> -o <plan.html> [--keyring <keys.json>]");
+        bail!("Invalid arguments");
+    };
+    let plan = load_plan(&validate_path(plan_arg, "plan file")?)?;
+    let keyring = keyring.map(|k| validate_path(k, "keyring file").and_then(|p| load_keyring(&p))).transpose()?;
+    let out = validate_path(out, "output file")?;
+    let page = render(&plan, keyring.as_deref())?;
+    fs::write(&out, page).with_context(|| format!("Failed to write output file: {:?}", out))?;
+    eprintln!("[OK] Visualized {} route(s): {:?}", plan.routes_add.len(), out);
+    Ok(())
+}
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+<style>
+body { font: 14px sans-serif; margin: 0; display: flex; height: 100vh; }
+#side { width: 300px; padding: 12px; overflow: auto; border-right: 1px solid #ddd; }
+#graph { flex: 1; }
+.batch { cursor: pointer; margin: 4px 0; }
+.batch.off { opacity: 0.3; }
+.swatch { display: inline-block; width: 12px; height: 12px; margin-right: 6px; vertical-align: middle; }
+.node circle { fill: #fff; stroke: #333; }
+.node text { font-size: 11px; }
+code { word-break: break-all; }
+</style>
+</head>
+<body>
+<div id="side">
+<h3 id="name"></h3>
+<p><code id="plan-id"></code></p>
+<h4>Signatures</h4>
+<ul id="signatures"></ul>
+<h4>Batches</h4>
+<div id="legend"></div>
+</div>
+<svg id="graph"></svg>
+<script type="application/json" id="plan-data">{{data}}</script>
+<script>
+(function () {
+  var data = JSON.parse(document.getElementById("plan-data").textContent);
+  var NS = "http://www.w3.org/2000/svg";
+  var svg = document.getElementById("graph");
+  function el(tag, attrs, parent) {
+    var e = document.createElementNS(NS, tag);
+    for (var k in attrs) e.setAttribute(k, attrs[k]);
+    parent.appendChild(e);
+    return e;
+  }
+  document.getElementById("name").textContent = data.name;
+  document.getElementById("plan-id").textContent = data.plan_id;
+  data.signatures.forEach(function (s) {
+    var li = document.createElement("li");
+    li.textContent = s;
+    document.getElementById("signatures").appendChild(li);
+  });
+
+  var colors = Object.create(null), hidden = Object.create(null);
+  data.batches.forEach(function (b) {
+    colors[b.name] = b.color;
+    var row = document.createElement("div");
+    row.className = "batch";
+    row.innerHTML = '<span class="swatch"></span>';
+    row.firstChild.style.background = b.color;
+    row.appendChild(document.createTextNode(b.name));
+    row.onclick = function () {
+      hidden[b.name] = !hidden[b.name];
+      row.classList.toggle("off", hidden[b.name]);
+      draw();
+    };
+    document.getElementById("legend").appendChild(row);
+  });
+
+  // Start on a circle, then relax with a few rounds of spring forces
+  var nodes = [], index = Object.create(null);
+  data.routes.forEach(function (r) {
+    [r.from, r.to].forEach(function (n) {
+      if (!(n in index)) { index[n] = nodes.length; nodes.push({ name: n }); }
+    });
+  });
+  var W = svg.clientWidth || 900, H = svg.clientHeight || 700, R = Math.min(W, H) / 2 - 60;
+  nodes.forEach(function (n, i) {
+    var a = 2 * Math.PI * i / Math.max(nodes.length, 1);
+    n.x = W / 2 + R * Math.cos(a);
+    n.y = H / 2 + R * Math.sin(a);
+  });
+  for (var step = 0; step < 200; step++) {
+    nodes.forEach(function (a) { a.dx = 0; a.dy = 0; });
+    nodes.forEach(function (a, i) {
+      nodes.slice(i + 1).forEach(function (b) {
+        var x = a.x - b.x, y = a.y - b.y, d2 = Math.max(x * x + y * y, 1), f = 4000 / d2;
+        a.dx += x * f; a.dy += y * f; b.dx -= x * f; b.dy -= y * f;
+      });
+    });
+    data.routes.forEach(function (r) {
+      var a = nodes[index[r.from]], b = nodes[index[r.to]], x = b.x - a.x, y = b.y - a.y;
+      a.dx += x * 0.01; a.dy += y * 0.01; b.dx -= x * 0.01; b.dy -= y * 0.01;
+    });
+    nodes.forEach(function (n) {
+      n.x = Math.max(40, Math.min(W - 40, n.x + Math.max(-10, Math.min(10, n.dx))));
+      n.y = Math.max(20, Math.min(H - 20, n.y + Math.max(-10, Math.min(10, n.dy))));
+    });
+  }
+
+  function draw() {
+    while (svg.firstChild) svg.removeChild(svg.firstChild);
+    var defs = el("defs", {}, svg);
+    data.batches.forEach(function (b, i) {
+      var m = el("marker", { id: "arrow" + i, viewBox: "0 0 10 10", refX: 18, refY: 5, markerWidth: 6, markerHeight: 6, orient: "auto" }, defs);
+      el("path", { d: "M0,0 L10,5 L0,10 z", fill: b.color }, m);
+    });
+    data.routes.forEach(function (r) {
+      if (hidden[r.batch]) return;
+      var a = nodes[index[r.from]], b = nodes[index[r.to]];
+      var i = data.batches.findIndex(function (x) { return x.name === r.batch; });
+      var line = el("line", { x1: a.x, y1: a.y, x2: b.x, y2: b.y, stroke: colors[r.batch] || "#999", "stroke-width": 2, "marker-end": "url(#arrow" + i + ")" }, svg);
+      el("title", {}, line).textContent = r.from + " -> " + r.to + " (" + r.batch + (r.weight != null ? ", weight " + r.weight : "") + ")";
+    });
+    nodes.forEach(function (n) {
+      var g = el("g", { "class": "node" }, svg);
+      el("circle", { cx: n.x, cy: n.y, r: 8 }, g);
+      el("text", { x: n.x + 10, y: n.y - 10 }, g).textContent = n.name;
+    });
+  }
+  draw();
+})();
+</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compute_plan_id, plan_name, Route};
+
+    #[test]
+    fn test_visualize_embeds_routes_and_batch_colors() {
+        let route = |from: &str, to: &str, batch: &str| Route { from: from.into(), to: to.into(), batch: Some(batch.into()), ..Default::default() };
+        let mut plan = Plan {
+            routes_add: vec![route("a", "b", "BATCH-1"), route("b", "c", "BATCH-2"), route("c", "</script>", "BATCH-2")],
+            order: vec!["BATCH-1".into(), "BATCH-2".into()],
+            ..Default::default()
+        };
+        plan.plan_id = compute_plan_id(&plan).unwrap();
+        plan.name = plan_name(&plan.plan_id);
+
+        let page = render(&plan, None).unwrap();
+        assert!(page.contains(r##"{"name":"BATCH-1","color":"#1f77b4"},{"name":"BATCH-2","color":"#ff7f0e"}"##));
+        assert!(page.contains(r#"{"from":"a","to":"b","batch":"BATCH-1"}"#));
+        assert!(page.contains(r#"{"from":"b","to":"c","batch":"BATCH-2"}"#));
+        assert!(page.contains(&format!(r#""plan_id":"{}""#, plan.plan_id)));
+        assert!(page.contains(r#""signatures":["unsigned"]"#));
+        // Endpoint names cannot close the data script, and nothing loads remotely
+        assert!(page.contains(r#""to":"\u003c/script>""#));
+        assert_eq!(page.matches("</script>").count(), 2);
+        assert!(!page.contains("src=") && !page.contains("https://"));
+
+        // A slot's text is not filled again
+        plan.name = "{{data}}".into();
+        assert!(render(&plan, None).unwrap().contains("<title>{{data}}</title>"));
+    }
+}
@@ -0,0 +1,211 @@
+//! External route weights
+//!
+//! `--weights <file>` merges weights produced elsewhere onto routes at plan
+//! time, so the routes file stays weight-free. The file is CSV with one
+//! `from,to,weight` row per route; blank lines, `#` comments and a
+//! `from,to,weight` header are ignored. Weights are finite and not
+//! negative. Routes without a weight count as `DEFAULT_WEIGHT`.
+//!
+//! When any route carries a weight, routes are ordered heaviest first
+//! (stably, so equal weights keep input order) before batching, which puts
+//! the most important routes in the earliest batches.
+//!
+//! Weights are also the solver's reward for admitting a route when manifest
+//! capacities force a choice. `--normalize-weights` rescales them for the
+//! objective only; plans keep the weights as given:
+//!
+//! - `none` (default): raw weights.
+//! - `minmax`: `(w - min) / (max - min)`, into `[0, 1]`.
+//! - `zscore`: `(w - mean) / stddev`, with the population standard deviation.
+//!
+//! Both are computed over every route being admitted, with unweighted routes
+//! at `DEFAULT_WEIGHT`, and map equal weights to 0. They depend only on the
+//! multiset of weights, so they are deterministic whatever the input order.
+//! Whichever is used, the objective weights are then scaled down together,
+//! if needed, until their magnitudes sum to at most half the solver's reward
+//! for admitting a route: weights choose among selections that admit
+//! equally many routes, and never trade a route away.
+
+use crate::Route;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_WEIGHT: f64 = 1.0;
+
+pub type Weights = HashMap<(String, String), f64>;
+
+pub fn parse_weights(content: &str) -> Result<Weights> {
+    let mut weights = Weights::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line == "from,to,weight" {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [from, to, weight] = fields[..] else {
+            bail!("Weights line {}: expected from,to,weight, got: {}", i + 1, line);
+        };
+        let weight: f64 = weight
+            .parse()
+            .ok()
+            .filter(|w: &f64| w.is_finite() && *w >= 0.0)
+            .with_context(|| format!("Weights line {}: invalid weight: {}", i + 1, weight))?;
+        weights.insert((from.into(), to.into()), weight);
+    }
+    Ok(weights)
+}
+
+pub fn load_weights(path: &Path) -> Result<Weights> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read weights file: {:?}", path))?;
+    parse_weights(&content)
+}
+
+/// Set the weight of every route listed in `weights` and return how many
+/// routes matched.
+pub fn apply_weights(routes: &mut [Route], weights: &Weights) -> usize {
+    let mut matched = 0;
+    for route in routes {
+        if let Some(&w) = weights.get(&(route.from.clone(), route.to.clone())) {
+            route.weight = Some(w);
+            matched += 1;
+        }
+    }
+    matched
+}
+
+/// Order routes heaviest first if any route is weighted.
+pub fn order_by_weight(routes: &mut [Route]) {
+    if routes.iter().all(|r| r.weight.is_none()) {
+        return;
+    }
+    let weight = |r: &Route| r.weight.unwrap_or(DEFAULT_WEIGHT);
+    routes.sort_by(|a, b| weight(b).total_cmp(&weight(a)));
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WeightNormalization {
+    #[default]
+    None,
+    MinMax,
+    ZScore,
+}
+
+impl FromStr for WeightNormalization {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "minmax" => Ok(Self::MinMax),
+            "zscore" => Ok(Self::ZScore),
+            _ => bail!("Unknown weight normalization: {} (expected minmax, zscore or none)", s),
+        }
+    }
+}
+
+/// Each route's weight as it enters the solver objective, where admitting a
+/// route is worth `admit_priority`. All zero when no route is weighted, so
+/// weights then leave the objective untouched.
+pub fn objective_weights(routes: &[Route], normalization: WeightNormalization, admit_priority: f64) -> Vec<f64> {
+    if routes.iter().all(|r| r.weight.is_none()) {
+        return vec![0.0; routes.len()];
+    }
+    let raw: Vec<f64> = routes.iter().map(|r| r.weight.unwrap_or(DEFAULT_WEIGHT)).collect();
+    let n = raw.len() as f64;
+    let (offset, scale) = match normalization {
+        WeightNormalization::None => (0.0, 1.0),
+        WeightNormalization::MinMax => {
+            let min = raw.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = raw.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (min, max - min)
+        }
+        WeightNormalization::ZScore => {
+            let mean = raw.iter().sum::<f64>() / n;
+            let var = raw.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n;
+            (mean, var.sqrt())
+        }
+    };
+    let normalized: Vec<f64> = raw.iter().map(|w| if scale > 0.0 { (w - offset) / scale } else { 0.0 }).collect();
+    let total: f64 = normalized.iter().map(|w| w.abs()).sum();
+    let limit = admit_priority / 2.0;
+    if total <= limit {
+        return normalized;
+    }
+    normalized.iter().map(|w| w * limit / total).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{assign_batches, BatchConfig};
+    use crate::identity::IdentityKeys;
+    use crate::prune::admit_capacities;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_weights_change_batch_order() {
+        let mut routes: Vec<Route> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|from| Route { from: from.to_string(), to: "sink".into(), ..Default::default() })
+            .collect();
+
+        let path = std::env::temp_dir().join(format!("rtt-weights-{}.csv", std::process::id()));
+        fs::write(&path, "from,to,weight\n# from analytics\nd,sink,5\nc, sink, 2.5\nx,y,9\n").unwrap();
+        let weights = load_weights(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(apply_weights(&mut routes, &weights), 2);
+        order_by_weight(&mut routes);
+        assert_eq!(routes[0].weight, Some(5.0));
+        assert_eq!(routes[3].weight, None);
+        assert_eq!(routes.iter().map(|r| r.from.as_str()).collect::<String>(), "dcab");
+
+        assign_batches(&mut routes, &BatchConfig { batch_size: Some(2), ..Default::default() }).unwrap();
+        assert_eq!(routes[0].from, "d");
+        assert_eq!(routes[0].batch.as_deref(), Some("BATCH-1"));
+        assert_eq!(routes[3].from, "b");
+        assert_eq!(routes[3].batch.as_deref(), Some("BATCH-2"));
+
+        assert!(parse_weights("a,b").is_err());
+        assert!(parse_weights("a,b,NaN").is_err());
+        assert!(parse_weights("a,b,-1").is_err());
+    }
+
+    #[test]
+    fn test_weights_never_outweigh_admission() {
+        let route = |from: &str, to: &str, weight: f64| Route { from: from.into(), to: to.into(), weight: Some(weight), ..Default::default() };
+        // p and q admit one route each: either the heavy p->q, or both light routes
+        let routes = vec![route("p", "q", 3000.0), route("p", "x", 1.0), route("q", "y", 1.0), route("z", "w", 40.0)];
+
+        let minmax = objective_weights(&routes, WeightNormalization::MinMax, 1000.0);
+        assert!(minmax.iter().all(|w| (0.0..=1.0).contains(w)));
+        assert_eq!((minmax[0], minmax[1]), (1.0, 0.0));
+        let zscore = objective_weights(&routes, WeightNormalization::ZScore, 1000.0);
+        assert!(zscore.iter().sum::<f64>().abs() < 1e-9);
+        assert_eq!(objective_weights(&routes[1..3], WeightNormalization::MinMax, 1000.0), vec![0.0, 0.0]);
+        let unweighted = [Route { from: "a".into(), to: "b".into(), ..Default::default() }];
+        assert_eq!(objective_weights(&unweighted, WeightNormalization::None, 1000.0), vec![0.0]);
+
+        // Raw weights keep their proportions but sum to half the admission reward
+        let raw = objective_weights(&routes, WeightNormalization::None, 1000.0);
+        assert!((raw.iter().sum::<f64>() - 500.0).abs() < 1e-9);
+        assert!((raw[0] / raw[3] - 75.0).abs() < 1e-9);
+        assert_eq!(objective_weights(&routes[1..], WeightNormalization::None, 1000.0), vec![1.0, 1.0, 40.0]);
+
+        let caps = BTreeMap::from([("p".to_string(), 1), ("q".to_string(), 1)]);
+        let admitted = |normalization| {
+            let mut dropped = Vec::new();
+            let (kept, _) = admit_capacities(routes.clone(), &caps, &IdentityKeys::default(), normalization, &mut dropped).unwrap();
+            kept.iter().map(|r| format!("{}->{}", r.from, r.to)).collect::<Vec<_>>()
+        };
+        // Even raw, the 3000 does not outweigh admitting a second route
+        for normalization in [WeightNormalization::None, WeightNormalization::MinMax, WeightNormalization::ZScore] {
+            assert_eq!(admitted(normalization), vec!["p->x", "q->y", "z->w"]);
+        }
+        assert!("log".parse::<WeightNormalization>().is_err());
+    }
+}
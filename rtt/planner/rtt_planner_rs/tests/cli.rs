@@ -0,0 +1,374 @@
+// End-to-end tests driving the rtt-planner binary
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn planner(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rtt-planner"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start rtt-planner");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_pipe_routes_in_plan_out() {
+    let routes = r#"{"routes": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}]}"#;
+    let out = planner(&["-", "manifests", "-"], routes);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    // stdout carries only the plan; the plan_id moves to stderr
+    let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(plan["routes_add"].as_array().unwrap().len(), 2);
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert_eq!(stderr.lines().next(), plan["plan_id"].as_str());
+}
+
+#[test]
+fn test_max_plan_bytes() {
+    let routes = r#"{"routes": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}]}"#;
+    let out = planner(&["--max-plan-bytes", "64", "-", "manifests", "-"], routes);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let size = planner(&["-", "manifests", "-"], routes).stdout.len() - 1;
+    assert!(stderr.contains("limit of 64"), "{}", stderr);
+    assert!(stderr.contains(&format!("Plan is {} bytes", size)), "{}", stderr);
+}
+
+#[test]
+fn test_plan_name_is_deterministic() {
+    let routes = r#"{"routes": [{"from": "a", "to": "b"}]}"#;
+    let names: Vec<String> = (0..2)
+        .map(|_| {
+            let out = planner(&["-", "manifests", "-"], routes);
+            let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+            let stderr = String::from_utf8(out.stderr).unwrap();
+            assert_eq!(stderr.lines().nth(1), plan["name"].as_str());
+            plan["name"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert!(names[0].starts_with("plan-"));
+    assert_eq!(names[0], names[1]);
+}
+
+#[test]
+fn test_preserve_order() {
+    let routes = r#"{"routes": [{"from": "z", "to": "a"}, {"from": "m", "to": "b"}, {"from": "z", "to": "a"}, {"from": "a", "to": "c"}]}"#;
+    let out = planner(&["--preserve-order", "-", "manifests", "-"], routes);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let froms: Vec<_> = plan["routes_add"].as_array().unwrap().iter().map(|r| r["from"].as_str().unwrap()).collect();
+    assert_eq!(froms, vec!["z", "m", "a"]);
+    assert_eq!(plan["order"], serde_json::json!(["BATCH-1"]));
+
+    // The id reflects the order
+    let reordered = r#"{"routes": [{"from": "m", "to": "b"}, {"from": "z", "to": "a"}, {"from": "a", "to": "c"}]}"#;
+    let other = planner(&["--preserve-order", "-", "manifests", "-"], reordered);
+    let other: serde_json::Value = serde_json::from_slice(&other.stdout).unwrap();
+    assert_ne!(plan["plan_id"], other["plan_id"]);
+
+    assert!(!planner(&["--preserve-order", "--batches", "2", "-", "manifests", "-"], routes).status.success());
+}
+
+#[cfg(feature = "otlp")]
+#[test]
+fn test_otlp_span_exported() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    // Mock collector: accept one OTLP/HTTP request and return its body
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let collector = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                len = v.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        (request_line, body)
+    });
+
+    let routes = r#"{"routes": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}]}"#;
+    let out = planner(&["--otlp-endpoint", &endpoint, "-", "manifests", "-"], routes);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    let (request_line, body) = collector.join().unwrap();
+    assert!(request_line.starts_with("POST /v1/traces "));
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let spans = body["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(spans[0]["name"], "plan");
+    assert!(spans.iter().any(|s| s["name"] == "expand"));
+    let attrs = spans[0]["attributes"].as_array().unwrap();
+    assert!(attrs.iter().any(|a| a["key"] == "rtt.routes.planned" && a["value"]["intValue"] == "2"));
+    assert!(attrs.iter().any(|a| a["key"] == "rtt.signed" && a["value"]["boolValue"] == false));
+}
+
+#[test]
+fn test_lockfile_rejects_concurrent_run() {
+    let dir = std::env::temp_dir();
+    let out = format!("rtt-lock-{}.json", std::process::id());
+    let lock_path = dir.join(format!("{}.lock", out));
+    let spawn = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rtt-planner"))
+            .args(args)
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to start rtt-planner")
+    };
+    let is_locked = || {
+        std::fs::File::open(&lock_path).is_ok_and(|f| matches!(f.try_lock_shared(), Err(std::fs::TryLockError::WouldBlock)))
+    };
+
+    // The first run takes the lock, then blocks reading routes from stdin
+    let mut first = spawn(&["--lockfile", "-", "manifests", &out]);
+    let start = std::time::Instant::now();
+    while !is_locked() {
+        assert!(start.elapsed().as_secs() < 10, "first run never took the lock");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let second = spawn(&["--lockfile", "-", "manifests", &out]).wait_with_output().unwrap();
+    assert!(!second.status.success());
+    assert!(String::from_utf8_lossy(&second.stderr).contains("locked by another planner run"));
+
+    first.stdin.take().unwrap().write_all(br#"{"routes": [{"from": "a", "to": "b"}]}"#).unwrap();
+    let first = first.wait_with_output().unwrap();
+    assert!(first.status.success(), "{}", String::from_utf8_lossy(&first.stderr));
+
+    // Released once the first run exits
+    let mut third = spawn(&["--lockfile", "-", "manifests", &out]);
+    third.stdin.take().unwrap().write_all(br#"{"routes": []}"#).unwrap();
+    assert!(third.wait_with_output().unwrap().status.success());
+
+    let _ = std::fs::remove_file(dir.join(&out));
+    let _ = std::fs::remove_file(&lock_path);
+}
+
+#[test]
+fn test_fail_on_empty() {
+    let empty = r#"{"routes": []}"#;
+    let out = planner(&["-", "manifests", "-"], empty);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(plan["order"], serde_json::json!([]));
+
+    let out = planner(&["--fail-on-empty", "-", "manifests", "-"], empty);
+    assert!(!out.status.success());
+    assert!(out.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("No routes left to plan"));
+}
+
+#[test]
+fn test_e2e_selftest() {
+    let out = planner(&["e2e-selftest"], "");
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(out.status.success(), "{}", stderr);
+    for step in ["build", "sign", "verify", "invert", "sign and verify inverse", "double inversion"] {
+        assert!(stderr.lines().any(|l| l == format!("[OK] {}", step)), "missing step {}: {}", step, stderr);
+    }
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "OK\n");
+    assert!(!planner(&["e2e-selftest", "extra"], "").status.success());
+}
+
+#[test]
+fn test_with_rollback() {
+    let dir = std::env::temp_dir();
+    let [out, rollback, back] = ["plan", "rollback", "back"].map(|f| format!("rtt-rollback-{}-{}.json", std::process::id(), f));
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_rtt-planner")).args(args).current_dir(&dir).output().unwrap();
+    let routes = format!("rtt-rollback-{}-routes.json", std::process::id());
+    std::fs::write(dir.join(&routes), r#"{"routes": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}]}"#).unwrap();
+
+    let planned = run(&["--batch-size", "1", "--with-rollback", &rollback, &routes, "manifests", &out]);
+    assert!(planned.status.success(), "{}", String::from_utf8_lossy(&planned.stderr));
+    let read = |f: &str| -> serde_json::Value { serde_json::from_slice(&std::fs::read(dir.join(f)).unwrap()).unwrap() };
+    let (plan, inverse) = (read(&out), read(&rollback));
+    assert_eq!(inverse["routes_del"], plan["routes_add"]);
+    assert_eq!(inverse["routes_add"], serde_json::json!([]));
+    let mut order = plan["order"].as_array().unwrap().clone();
+    assert_eq!(order.len(), 2);
+    order.reverse();
+    assert_eq!(inverse["order"], serde_json::Value::Array(order));
+    assert_ne!(inverse["plan_id"], plan["plan_id"]);
+    assert!(inverse["sign"].is_null());
+
+    // The rollback's id is sound, and undoing it gives back the plan
+    assert!(run(&["verify-id", &rollback]).status.success());
+    assert!(run(&["invert", &rollback, &back]).status.success());
+    assert_eq!(read(&back)["plan_id"], plan["plan_id"]);
+
+    for f in [&routes, &out, &rollback, &back] {
+        let _ = std::fs::remove_file(dir.join(f));
+    }
+}
+
+#[test]
+fn test_from_matrix_enforces_manifest_capacities() {
+    let dir = std::env::temp_dir().join(format!("rtt-cli-matrix-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("manifests")).unwrap();
+    let manifest = r#"{"symbol": {"saddr": "rtt://core/api/metrics@1.0.0", "type": "api", "direction": "provider", "capacity": 2}}"#;
+    std::fs::write(dir.join("manifests/metrics.json"), manifest).unwrap();
+    std::fs::write(dir.join("matrix.csv"), ",rtt://core/api/metrics\nrtt://ui,1\nrtt://cli,1\nrtt://batch,1\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rtt-planner")).args(args).current_dir(&dir).output().unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let plan: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        (plan["routes_add"].as_array().unwrap().len(), String::from_utf8(out.stderr).unwrap())
+    };
+    let args = ["from-matrix", "matrix.csv", "-o", "-", "--optimize"];
+    assert_eq!(run(&args).0, 3);
+    let (routes, stderr) = run(&[&args[..], &["--manifests-dir", "manifests"]].concat());
+    assert_eq!(routes, 2);
+    assert!(stderr.contains("rtt://core/api/metrics=3"), "{}", stderr);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_subcommands_write_through_the_main_output_path() {
+    let dir = std::env::temp_dir().join(format!("rtt-cli-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("matrix.csv"), ",b,c\na,1,2\n").unwrap();
+    std::fs::write(dir.join("base.json"), r#"{"routes": [{"from": "a", "to": "b"}]}"#).unwrap();
+    std::fs::write(dir.join("env.json"), r#"{"routes": [{"from": "b", "to": "c"}]}"#).unwrap();
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_rtt-planner")).args(args).current_dir(&dir).output().unwrap();
+
+    let matrix = ["from-matrix", "matrix.csv", "-o", "plan.json", "--dropped-out", "dropped.json"];
+    let too_big = run(&[&matrix[..], &["--max-plan-bytes", "10"]].concat());
+    assert!(!too_big.status.success());
+    assert!(String::from_utf8_lossy(&too_big.stderr).contains("--max-plan-bytes"));
+
+    // The id and name lines, and the dropped-routes report, as from the main command
+    let out = run(&matrix);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let plan: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.join("plan.json")).unwrap()).unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(stdout, format!("{}\n{}\n", plan["plan_id"].as_str().unwrap(), plan["name"].as_str().unwrap()));
+    assert_eq!(std::fs::read_to_string(dir.join("dropped.json")).unwrap(), "[]");
+
+    let locked = run(&["overlay", "base.json", "env.json", "-o", "-", "--lockfile"]);
+    assert!(String::from_utf8_lossy(&locked.stderr).contains("--lockfile needs an output file"));
+    let receipt = run(&["overlay", "base.json", "env.json", "-o", "overlay.json", "--receipt", "receipt.json"]);
+    assert!(receipt.status.success(), "{}", String::from_utf8_lossy(&receipt.stderr));
+    assert!(dir.join("receipt.json").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_aggregate_rollback_undoes_the_detailed_plan() {
+    let dir = std::env::temp_dir().join(format!("rtt-cli-aggregate-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("manifests")).unwrap();
+    let routes = r#"{"routes": [
+        {"from": "e1", "to": "e2", "labels": {"cluster": "east"}},
+        {"from": "e2", "to": "e3", "labels": {"cluster": "east"}},
+        {"from": "x", "to": "y"}
+    ]}"#;
+    std::fs::write(dir.join("routes.json"), routes).unwrap();
+    let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_rtt-planner")).args(args).current_dir(&dir).output().unwrap();
+
+    let planned = run(&["--aggregate-by", "labels.cluster", "--with-rollback", "undo.json", "routes.json", "manifests", "plan.json"]);
+    assert!(planned.status.success(), "{}", String::from_utf8_lossy(&planned.stderr));
+    let read = |f: &str| -> serde_json::Value { serde_json::from_slice(&std::fs::read(dir.join(f)).unwrap()).unwrap() };
+    let (plan, undo, sidecar) = (read("plan.json"), read("undo.json"), read("plan.aggregates.json"));
+    assert_eq!(plan["routes_add"].as_array().unwrap().len(), 2);
+    assert_eq!(plan["annotations"]["aggregate.detail_plan_id"], sidecar["plan_id"]);
+    assert_eq!(undo["routes_del"].as_array().unwrap().len(), 3);
+
+    let receipt = run(&["--aggregate-by", "labels.cluster", "--receipt", "r.json", "routes.json", "manifests", "plan.json"]);
+    assert!(String::from_utf8_lossy(&receipt.stderr).contains("cannot be combined with --aggregate-by"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Serve `connections` ssh-agent connections holding one ed25519 key, commented `ci`.
+#[cfg(unix)]
+fn mock_agent(socket: &std::path::Path, sk: ed25519_dalek::SigningKey, connections: usize) -> std::thread::JoinHandle<()> {
+    use ed25519_dalek::Signer;
+    use std::io::Read;
+    let put = |buf: &mut Vec<u8>, bytes: &[u8]| {
+        buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        buf.extend_from_slice(bytes);
+    };
+    let mut blob = Vec::new();
+    put(&mut blob, b"ssh-ed25519");
+    put(&mut blob, sk.verifying_key().as_bytes());
+    let listener = std::os::unix::net::UnixListener::bind(socket).unwrap();
+    std::thread::spawn(move || {
+        for _ in 0..connections {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut len = [0u8; 4];
+            while conn.read_exact(&mut len).is_ok() {
+                let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+                conn.read_exact(&mut msg).unwrap();
+                let mut reply = Vec::new();
+                if msg[0] == 11 {
+                    reply.push(12);
+                    reply.extend_from_slice(&1u32.to_be_bytes());
+                    put(&mut reply, &blob);
+                    put(&mut reply, b"ci");
+                } else {
+                    // Sign request: key blob, then the data
+                    let at = 1 + 4 + blob.len();
+                    let data_len = u32::from_be_bytes(msg[at..at + 4].try_into().unwrap()) as usize;
+                    let sig = sk.sign(&msg[at + 4..at + 4 + data_len]);
+                    let mut inner = Vec::new();
+                    put(&mut inner, b"ssh-ed25519");
+                    put(&mut inner, &sig.to_bytes());
+                    reply.push(14);
+                    put(&mut reply, &inner);
+                }
+                conn.write_all(&(reply.len() as u32).to_be_bytes()).unwrap();
+                conn.write_all(&reply).unwrap();
+            }
+        }
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_deterministic_signing_is_byte_identical() {
+    let dir = std::env::temp_dir().join(format!("rtt-cli-deterministic-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("manifests")).unwrap();
+    std::fs::write(dir.join("routes.json"), r#"{"routes": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}]}"#).unwrap();
+    let socket = dir.join("agent.sock");
+    let agent = mock_agent(&socket, ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]), 3);
+
+    // Plan and sign twice through the agent, as a re-run would
+    let sign = |extra: &[&str], out: &str| {
+        let args = [&["--signer", "ssh-agent", "--key-comment", "ci"], extra, &["routes.json", "manifests", out]].concat();
+        let run = Command::new(env!("CARGO_BIN_EXE_rtt-planner")).args(args).env("SSH_AUTH_SOCK", &socket).current_dir(&dir).output().unwrap();
+        assert!(run.status.success(), "{}", String::from_utf8_lossy(&run.stderr));
+        assert!(String::from_utf8_lossy(&run.stderr).contains("[OK] Plan signed successfully"));
+        std::fs::read(dir.join(out)).unwrap()
+    };
+    let first = sign(&["--deterministic-signing"], "first.json");
+    assert_eq!(first, sign(&["--deterministic-signing"], "second.json"));
+    let plan: serde_json::Value = serde_json::from_slice(&first).unwrap();
+    assert!(plan["sign"]["sig"].is_string() && plan["sign"]["signed_at"].is_null());
+
+    // Without the option the signing time is recorded and signed
+    let timed: serde_json::Value = serde_json::from_slice(&sign(&[], "timed.json")).unwrap();
+    assert!(timed["sign"]["signed_at"].is_u64());
+    agent.join().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,78 @@
+// Server mode tests; run with `cargo test --features serve`
+#![cfg(feature = "serve")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+fn serve() -> (Child, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rtt-planner"))
+        .args(["serve", "--listen", "127.0.0.1:0"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start rtt-planner serve");
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line.trim().strip_prefix("[INFO] Listening on ").expect(&line).to_string();
+    // Keep draining stderr so the server never blocks on it
+    std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+    (child, addr)
+}
+
+fn terminate(child: &Child) {
+    let status = Command::new("kill").args(["-TERM", &child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+}
+
+fn get(addr: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_healthz() {
+    let (mut child, addr) = serve();
+    assert!(get(&addr, "/healthz").starts_with("HTTP/1.1 200 OK"));
+    assert!(get(&addr, "/readyz").ends_with("ready"));
+    assert!(get(&addr, "/nope").starts_with("HTTP/1.1 404"));
+
+    terminate(&child);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_shutdown_drains_in_flight_request() {
+    let (mut child, addr) = serve();
+    let body = r#"{"routes": [{"from": "a", "to": "b"}]}"#;
+    let (head, tail) = body.split_at(10);
+
+    // Start a request, then ask the server to stop before it is complete
+    let mut stream = TcpStream::connect(&addr).unwrap();
+    write!(stream, "POST /plan HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}", addr, body.len(), head).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    terminate(&child);
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(child.try_wait().unwrap().is_none(), "server exited with a request in flight");
+    // Draining: probes see it, and new work is refused
+    assert!(get(&addr, "/readyz").starts_with("HTTP/1.1 503"));
+    assert!(get(&addr, "/healthz").starts_with("HTTP/1.1 200 OK"));
+    let mut late = TcpStream::connect(&addr).unwrap();
+    write!(late, "POST /plan HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}", addr, body.len(), body).unwrap();
+    let mut response = String::new();
+    late.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+
+    stream.write_all(tail.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    let plan: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(plan["routes_add"][0]["to"], "b");
+
+    assert!(child.wait().unwrap().success());
+}
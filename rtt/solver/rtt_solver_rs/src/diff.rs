@@ -0,0 +1,214 @@
+//! Structural comparison of two models
+//!
+//! Variables and constraints are matched by name and terms by variable name,
+//! so two independently built models compare equal when they describe the
+//! same problem, regardless of insertion order. A name used twice in one
+//! model cannot be matched, so it is reported as a duplicate and only the
+//! last entry under it is compared.
+
+use crate::{Cmp, Sense, Solver, VarId};
+use std::collections::{BTreeMap, BTreeSet};
+
+const EPS: f64 = 1e-9;
+
+/// A coefficient that differs between the two models. A term missing from
+/// one side is reported with coefficient 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoefChange {
+    pub var: String,
+    pub left: f64,
+    pub right: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundChange {
+    pub var: String,
+    pub left: (i64, i64),
+    pub right: (i64, i64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConstraintChange {
+    pub name: String,
+    pub coefficients: Vec<CoefChange>,
+    pub cmp: Option<(Cmp, Cmp)>,
+    pub rhs: Option<(f64, f64)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModelDiff {
+    pub vars_only_left: Vec<String>,
+    pub vars_only_right: Vec<String>,
+    pub bound_changes: Vec<BoundChange>,
+    pub constraints_only_left: Vec<String>,
+    pub constraints_only_right: Vec<String>,
+    pub constraint_changes: Vec<ConstraintChange>,
+    pub sense: Option<(Sense, Sense)>,
+    pub objective_changes: Vec<CoefChange>,
+    pub duplicate_vars_left: Vec<String>,
+    pub duplicate_vars_right: Vec<String>,
+    pub duplicate_constraints_left: Vec<String>,
+    pub duplicate_constraints_right: Vec<String>,
+}
+
+impl ModelDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == ModelDiff::default()
+    }
+}
+
+fn by_name(model: &Solver, terms: &[(VarId, f64)]) -> BTreeMap<String, f64> {
+    let mut out = BTreeMap::new();
+    for &(v, c) in terms {
+        *out.entry(model.vars[v.0].name.clone()).or_insert(0.0) += c;
+    }
+    out
+}
+
+fn coef_changes(left: &BTreeMap<String, f64>, right: &BTreeMap<String, f64>) -> Vec<CoefChange> {
+    let names: BTreeSet<&String> = left.keys().chain(right.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let l = left.get(name).copied().unwrap_or(0.0);
+            let r = right.get(name).copied().unwrap_or(0.0);
+            ((l - r).abs() > EPS).then(|| CoefChange { var: name.clone(), left: l, right: r })
+        })
+        .collect()
+}
+
+/// Names in `a` that are missing from `b`, sorted.
+fn missing<'a, T: 'a>(a: &'a BTreeMap<&'a str, T>, b: &BTreeMap<&str, T>) -> Vec<String> {
+    a.keys().filter(|k| !b.contains_key(*k)).map(|k| k.to_string()).collect()
+}
+
+/// Names given more than once, sorted.
+fn duplicates<'a>(names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    let dups: BTreeSet<&str> = names.filter(|n| !seen.insert(*n)).collect();
+    dups.into_iter().map(str::to_string).collect()
+}
+
+impl Solver {
+    /// Compare this model (left) with `other` (right).
+    pub fn diff(&self, other: &Solver) -> ModelDiff {
+        let lvars: BTreeMap<&str, (i64, i64)> =
+            self.vars.iter().map(|v| (v.name.as_str(), (v.lb, v.ub))).collect();
+        let rvars: BTreeMap<&str, (i64, i64)> =
+            other.vars.iter().map(|v| (v.name.as_str(), (v.lb, v.ub))).collect();
+
+        let bound_changes = lvars
+            .iter()
+            .filter_map(|(name, &l)| {
+                let &r = rvars.get(name)?;
+                (l != r).then(|| BoundChange { var: name.to_string(), left: l, right: r })
+            })
+            .collect();
+
+        let lcons: BTreeMap<&str, usize> =
+            self.constraints.iter().enumerate().map(|(i, c)| (c.name.as_str(), i)).collect();
+        let rcons: BTreeMap<&str, usize> =
+            other.constraints.iter().enumerate().map(|(i, c)| (c.name.as_str(), i)).collect();
+
+        let constraint_changes = lcons
+            .iter()
+            .filter_map(|(name, &li)| {
+                let &ri = rcons.get(name)?;
+                let (l, r) = (&self.constraints[li], &other.constraints[ri]);
+                let change = ConstraintChange {
+                    name: name.to_string(),
+                    coefficients: coef_changes(&by_name(self, &l.terms), &by_name(other, &r.terms)),
+                    cmp: (l.cmp != r.cmp).then_some((l.cmp, r.cmp)),
+                    rhs: ((l.rhs - r.rhs).abs() > EPS).then_some((l.rhs, r.rhs)),
+                };
+                let changed = !change.coefficients.is_empty() || change.cmp.is_some() || change.rhs.is_some();
+                changed.then_some(change)
+            })
+            .collect();
+
+        ModelDiff {
+            vars_only_left: missing(&lvars, &rvars),
+            vars_only_right: missing(&rvars, &lvars),
+            bound_changes,
+            constraints_only_left: missing(&lcons, &rcons),
+            constraints_only_right: missing(&rcons, &lcons),
+            constraint_changes,
+            sense: (self.sense != other.sense).then_some((self.sense, other.sense)),
+            objective_changes: coef_changes(
+                &by_name(self, &self.objective),
+                &by_name(other, &other.objective),
+            ),
+            duplicate_vars_left: duplicates(self.vars.iter().map(|v| v.name.as_str())),
+            duplicate_vars_right: duplicates(other.vars.iter().map(|v| v.name.as_str())),
+            duplicate_constraints_left: duplicates(self.constraints.iter().map(|c| c.name.as_str())),
+            duplicate_constraints_right: duplicates(other.constraints.iter().map(|c| c.name.as_str())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(with_precedence: bool, weight_b: f64) -> Solver {
+        let mut s = Solver::new();
+        let a = s.add_binary("route_a");
+        let b = s.add_binary("route_b");
+        s.add_constraint("capacity", &[(a, 1.0), (b, 1.0)], Cmp::Le, 1.0);
+        if with_precedence {
+            s.add_constraint("precedence_a_b", &[(b, 1.0), (a, -1.0)], Cmp::Le, 0.0);
+        }
+        s.set_objective(Sense::Maximize, &[(a, 2.0), (b, weight_b)]);
+        s
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        assert!(model(true, 3.0).diff(&model(true, 3.0)).is_empty());
+    }
+
+    #[test]
+    fn test_diff_one_constraint() {
+        let diff = model(true, 3.0).diff(&model(false, 3.0));
+        assert_eq!(diff.constraints_only_left, vec!["precedence_a_b"]);
+        assert!(diff.constraints_only_right.is_empty());
+        assert!(diff.constraint_changes.is_empty());
+        assert!(diff.vars_only_left.is_empty() && diff.objective_changes.is_empty());
+    }
+
+    #[test]
+    fn test_diff_coefficients_by_name() {
+        // Same model built in a different variable order
+        let mut other = Solver::new();
+        let b = other.add_binary("route_b");
+        let a = other.add_binary("route_a");
+        other.add_constraint("capacity", &[(b, 1.0), (a, 2.0)], Cmp::Le, 1.0);
+        other.set_objective(Sense::Maximize, &[(b, 4.0), (a, 2.0)]);
+
+        let diff = model(false, 3.0).diff(&other);
+        assert_eq!(diff.constraint_changes.len(), 1);
+        assert_eq!(
+            diff.constraint_changes[0].coefficients,
+            vec![CoefChange { var: "route_a".into(), left: 1.0, right: 2.0 }]
+        );
+        assert_eq!(
+            diff.objective_changes,
+            vec![CoefChange { var: "route_b".into(), left: 3.0, right: 4.0 }]
+        );
+    }
+
+    #[test]
+    fn test_diff_reports_duplicate_names() {
+        // The second "capacity" would otherwise hide behind the first
+        let mut right = model(false, 3.0);
+        let a = right.add_binary("route_a");
+        right.add_constraint("capacity", &[(a, 1.0)], Cmp::Le, 1.0);
+        right.add_constraint("capacity", &[(a, 1.0)], Cmp::Le, 1.0);
+
+        let diff = model(false, 3.0).diff(&right);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.duplicate_vars_right, vec!["route_a"]);
+        assert_eq!(diff.duplicate_constraints_right, vec!["capacity"]);
+        assert!(diff.duplicate_vars_left.is_empty() && diff.duplicate_constraints_left.is_empty());
+    }
+}
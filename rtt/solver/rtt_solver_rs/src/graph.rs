@@ -0,0 +1,419 @@
+//! Route-selection model from the planner's route graph
+//!
+//! Mirrors the admission model in `tools/ilp/solver_ilp.py`: one binary per
+//! route says whether it is admitted, and the objective admits as many
+//! routes as the constraints allow before minimizing total RTT less total
+//! route weight. With `admit_priority` well above any route's RTT cost and
+//! weight, dropping a route never pays for itself in latency or weight.
+//! Route priorities then break ties between equally good selections,
+//! through an objective term too small to matter otherwise.
+
+use crate::{Cmp, Sense, Solver, VarId};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Clone, Debug, Default)]
+pub struct GraphRoute {
+    /// Unique name; the selection variable is `route:<id>`.
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    /// Round-trip cost of the route, e.g. predicted latency in ms.
+    pub rtt: f64,
+    /// Reward for admitting the route, in the objective's units: where
+    /// routes compete for capacity, heavier ones are admitted first.
+    pub weight: f64,
+    /// Ids of routes that must be admitted for this one to be admitted.
+    pub after: Vec<String>,
+    /// Tie-break among selections with the same objective: higher priority
+    /// routes are preferred. Never outweighs a real objective difference.
+    pub priority: f64,
+}
+
+/// Weight of the priority tie-break per route: the lowest priority counts
+/// 0 and the highest this much. Selections whose primary objective differs
+/// by less than this for each route they swap count as ties.
+const TIE_BREAK: f64 = 1e-4;
+
+/// What `ingest_route_graph` optimizes once as many routes as possible are
+/// admitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GraphObjective {
+    /// Minimize total RTT.
+    #[default]
+    Rtt,
+    /// Minimize the largest number of admitted routes leaving any endpoint,
+    /// then total RTT. Adds one integer variable, `max_fanout`, bounding
+    /// every endpoint's fan-out; its cost is half of `admit_priority`, so
+    /// the spread never justifies dropping a route.
+    BalanceFanOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct RouteGraphConfig {
+    /// Objective reward per admitted route.
+    pub admit_priority: f64,
+    pub objective: GraphObjective,
+    /// Maximum admitted routes touching each endpoint, in either direction.
+    pub capacities: BTreeMap<String, u32>,
+    /// Capacity for endpoints missing from `capacities`; `None` is unlimited.
+    pub default_capacity: Option<u32>,
+    /// Order interchangeable routes so the search sees one of each set of
+    /// equivalent selections. See `symmetry_classes`.
+    pub symmetry_breaking: bool,
+}
+
+impl Default for RouteGraphConfig {
+    fn default() -> Self {
+        Self {
+            admit_priority: 1000.0,
+            objective: GraphObjective::Rtt,
+            capacities: BTreeMap::new(),
+            default_capacity: None,
+            symmetry_breaking: false,
+        }
+    }
+}
+
+/// How to make an over-subscribed route graph admissible in full.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteRepair {
+    /// Fewest route ids to drop so every remaining route can be admitted,
+    /// including routes that lose a dropped prerequisite.
+    pub drop: Vec<String>,
+    /// Capacity each over-subscribed endpoint would need to admit every
+    /// route as is.
+    pub relax: BTreeMap<String, u32>,
+}
+
+/// Endpoints with more incident routes than their capacity, each with the
+/// capacity it would need to admit every route. Empty when all routes can
+/// be admitted at once; no model is solved.
+pub fn oversubscribed_endpoints(routes: &[GraphRoute], config: &RouteGraphConfig) -> BTreeMap<String, u32> {
+    let mut incident: BTreeMap<&str, u32> = BTreeMap::new();
+    for route in routes {
+        *incident.entry(&route.from).or_default() += 1;
+        *incident.entry(&route.to).or_default() += 1;
+    }
+    incident
+        .into_iter()
+        .filter(|(endpoint, n)| {
+            let cap = config.capacities.get(*endpoint).copied().or(config.default_capacity);
+            cap.is_some_and(|cap| *n > cap)
+        })
+        .map(|(endpoint, n)| (endpoint.to_string(), n))
+        .collect()
+}
+
+/// Check that every route in the graph can be admitted at once. Returns
+/// `None` when it can, and otherwise the cheapest repair: the minimum drop
+/// set from an admission model that counts routes and ignores RTT, together
+/// with the capacity relaxations that would avoid dropping anything.
+pub fn repair_route_graph(routes: &[GraphRoute], config: &RouteGraphConfig) -> Result<Option<RouteRepair>> {
+    let relax = oversubscribed_endpoints(routes, config);
+    if relax.is_empty() {
+        return Ok(None);
+    }
+
+    let unit: Vec<GraphRoute> = routes.iter().map(|r| GraphRoute { rtt: 0.0, weight: 0.0, ..r.clone() }).collect();
+    let mut solver = Solver::new();
+    let vars = solver.ingest_route_graph(&unit, &RouteGraphConfig {
+        admit_priority: 1.0,
+        objective: GraphObjective::Rtt,
+        ..config.clone()
+    })?;
+    let solution = solver.solve()?;
+    let drop = routes
+        .iter()
+        .zip(&vars)
+        .filter(|(_, &var)| !solution.is_selected(var))
+        .map(|(route, _)| route.id.clone())
+        .collect();
+    Ok(Some(RouteRepair { drop, relax }))
+}
+
+/// Groups of route indices that are interchangeable in the model: same
+/// endpoints, RTT, weight, priority and prerequisites, and not a prerequisite of any route.
+/// Swapping the selection of two members never changes feasibility or the
+/// objective. Singletons are left out.
+fn symmetry_classes(routes: &[GraphRoute]) -> Vec<Vec<usize>> {
+    // from, to, rtt, weight and priority bits, sorted prerequisites
+    type ClassKey<'a> = (&'a str, &'a str, [u64; 3], Vec<&'a str>);
+
+    let prereqs: HashSet<&str> = routes.iter().flat_map(|r| r.after.iter().map(String::as_str)).collect();
+    let mut classes: BTreeMap<ClassKey, Vec<usize>> = BTreeMap::new();
+    for (i, route) in routes.iter().enumerate() {
+        if prereqs.contains(route.id.as_str()) {
+            continue;
+        }
+        let mut after: Vec<&str> = route.after.iter().map(String::as_str).collect();
+        after.sort_unstable();
+        let bits = [route.rtt, route.weight, route.priority].map(f64::to_bits);
+        classes.entry((&route.from, &route.to, bits, after)).or_default().push(i);
+    }
+    classes.into_values().filter(|class| class.len() > 1).collect()
+}
+
+impl Solver {
+    /// Add selection variables, precedence and capacity constraints, and the
+    /// RTT objective for `routes`. Returns the selection variable of each
+    /// route, in input order.
+    pub fn ingest_route_graph(&mut self, routes: &[GraphRoute], config: &RouteGraphConfig) -> Result<Vec<VarId>> {
+        let mut by_id = HashMap::new();
+        let mut selection = Vec::with_capacity(routes.len());
+        for route in routes {
+            let var = self.add_binary(&format!("route:{}", route.id));
+            if by_id.insert(route.id.as_str(), var).is_some() {
+                bail!("Duplicate route id in graph: {}", route.id);
+            }
+            selection.push(var);
+        }
+
+        // A route is admitted only together with its prerequisites
+        for (route, &var) in routes.iter().zip(&selection) {
+            for prereq in &route.after {
+                let Some(&pvar) = by_id.get(prereq.as_str()) else {
+                    bail!("Route {} depends on unknown route {}", route.id, prereq);
+                };
+                self.add_constraint(&format!("prec:{}:{}", route.id, prereq), &[(var, 1.0), (pvar, -1.0)], Cmp::Le, 0.0);
+            }
+        }
+
+        let mut incident: BTreeMap<&str, Vec<(VarId, f64)>> = BTreeMap::new();
+        for (route, &var) in routes.iter().zip(&selection) {
+            incident.entry(&route.from).or_default().push((var, 1.0));
+            incident.entry(&route.to).or_default().push((var, 1.0));
+        }
+        for (endpoint, terms) in incident {
+            let cap = config.capacities.get(endpoint).copied().or(config.default_capacity);
+            if let Some(cap) = cap {
+                self.add_constraint(&format!("cap:{}", endpoint), &terms, Cmp::Le, cap as f64);
+            }
+        }
+
+        // Scaled by the spread, not the sum, so that the step between two
+        // priorities does not shrink toward the solver's tolerance as routes
+        // are added
+        let lowest = routes.iter().map(|r| r.priority).fold(f64::INFINITY, f64::min);
+        let highest = routes.iter().map(|r| r.priority).fold(f64::NEG_INFINITY, f64::max);
+        let tie_break = if highest > lowest { TIE_BREAK / (highest - lowest) } else { 0.0 };
+        let mut objective: Vec<_> = routes
+            .iter()
+            .zip(&selection)
+            .map(|(route, &var)| (var, route.rtt - route.weight - config.admit_priority - tie_break * (route.priority - lowest)))
+            .collect();
+
+        if config.objective == GraphObjective::BalanceFanOut {
+            let max_fanout = self.add_var("max_fanout", 0, routes.len() as i64);
+            let mut outgoing: BTreeMap<&str, Vec<(VarId, f64)>> = BTreeMap::new();
+            for (route, &var) in routes.iter().zip(&selection) {
+                outgoing.entry(&route.from).or_default().push((var, 1.0));
+            }
+            for (endpoint, mut terms) in outgoing {
+                terms.push((max_fanout, -1.0));
+                self.add_constraint(&format!("fanout:{}", endpoint), &terms, Cmp::Le, 0.0);
+            }
+            objective.push((max_fanout, config.admit_priority / 2.0));
+        }
+
+        // Within each class, admit members in input order: x[i] >= x[i+1]
+        if config.symmetry_breaking {
+            for class in symmetry_classes(routes) {
+                for pair in class.windows(2) {
+                    let (a, b) = (pair[0], pair[1]);
+                    self.add_constraint(
+                        &format!("sym:{}:{}", routes[a].id, routes[b].id),
+                        &[(selection[b], 1.0), (selection[a], -1.0)],
+                        Cmp::Le,
+                        0.0,
+                    );
+                }
+            }
+        }
+        self.set_objective(Sense::Minimize, &objective);
+        Ok(selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(id: &str, from: &str, to: &str, rtt: f64, after: &[&str]) -> GraphRoute {
+        GraphRoute {
+            id: id.into(),
+            from: from.into(),
+            to: to.into(),
+            rtt,
+            after: after.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn graph() -> Vec<GraphRoute> {
+        vec![
+            route("a-gw", "a", "gw", 1.0, &[]),
+            route("b-gw", "b", "gw", 2.0, &[]),
+            route("c-gw", "c", "gw", 0.5, &["a-gw"]),
+            route("gw-db", "gw", "db", 1.5, &["a-gw", "b-gw"]),
+        ]
+    }
+
+    #[test]
+    fn test_model_counts() {
+        let mut s = Solver::new();
+        let config = RouteGraphConfig {
+            capacities: BTreeMap::from([("gw".into(), 2)]),
+            ..Default::default()
+        };
+        let vars = s.ingest_route_graph(&graph(), &config).unwrap();
+        assert_eq!(vars.len(), 4);
+        assert_eq!(s.variables().len(), 4);
+        // Three precedence edges plus the one capped endpoint
+        assert_eq!(s.constraints().len(), 4);
+        assert!(s.constraints().iter().any(|c| c.name == "cap:gw"));
+
+        // `gw` can take two routes; a-gw + c-gw is the cheapest feasible pair
+        let sol = s.solve().unwrap();
+        assert!(sol.is_selected(vars[0]) && sol.is_selected(vars[2]));
+        assert!(!sol.is_selected(vars[1]) && !sol.is_selected(vars[3]));
+    }
+
+    #[test]
+    fn test_balance_fanout() {
+        // Each target takes one route; `a` is the cheaper source for both
+        let routes = vec![
+            route("a-t1", "a", "t1", 1.0, &[]),
+            route("a-t2", "a", "t2", 1.0, &[]),
+            route("b-t1", "b", "t1", 5.0, &[]),
+            route("b-t2", "b", "t2", 5.0, &[]),
+        ];
+        let capacities = BTreeMap::from([("t1".into(), 1), ("t2".into(), 1)]);
+        let solve = |objective| {
+            let mut s = Solver::new();
+            let config = RouteGraphConfig { objective, capacities: capacities.clone(), ..Default::default() };
+            let vars = s.ingest_route_graph(&routes, &config).unwrap();
+            let sol = s.solve().unwrap();
+            let fanout = |from: &str| {
+                routes.iter().zip(&vars).filter(|(r, &v)| r.from == from && sol.is_selected(v)).count()
+            };
+            (fanout("a"), fanout("b"))
+        };
+
+        assert_eq!(solve(GraphObjective::Rtt), (2, 0));
+        assert_eq!(solve(GraphObjective::BalanceFanOut), (1, 1));
+    }
+
+    #[test]
+    fn test_default_capacity_and_errors() {
+        let mut s = Solver::new();
+        let config = RouteGraphConfig { default_capacity: Some(10), ..Default::default() };
+        s.ingest_route_graph(&graph(), &config).unwrap();
+        // a, b, c, gw, db all capped
+        assert_eq!(s.constraints().len(), 3 + 5);
+        assert!(s.solve().unwrap().objective.unwrap() < -3000.0);
+
+        let mut s = Solver::new();
+        let bad = vec![route("x", "a", "b", 1.0, &["missing"])];
+        assert!(s.ingest_route_graph(&bad, &RouteGraphConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_repair_restores_feasibility() {
+        let config = RouteGraphConfig {
+            capacities: BTreeMap::from([("gw".into(), 2)]),
+            ..Default::default()
+        };
+        let repair = repair_route_graph(&graph(), &config).unwrap().unwrap();
+        assert_eq!(repair.relax, BTreeMap::from([("gw".into(), 4)]));
+        // Two of the four gw routes must go; dropping a-gw would also cost c-gw
+        // and gw-db, so the minimum keeps it
+        assert_eq!(repair.drop, vec!["c-gw", "gw-db"]);
+
+        let rest: Vec<_> = graph().into_iter().filter(|r| !repair.drop.contains(&r.id)).collect();
+        assert_eq!(repair_route_graph(&rest, &config).unwrap(), None);
+        assert_eq!(repair_route_graph(&graph(), &RouteGraphConfig::default()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_symmetry_breaking() {
+        // Eight parallel links into `gw`, which takes three, plus a distinct route
+        let mut routes: Vec<_> = (0..8).map(|i| route(&format!("link-{}", i), "a", "gw", 1.0, &[])).collect();
+        routes.push(route("b-gw", "b", "gw", 3.0, &[]));
+        let solve = |symmetry_breaking| {
+            let mut s = Solver::new();
+            let config = RouteGraphConfig {
+                capacities: BTreeMap::from([("gw".into(), 3)]),
+                symmetry_breaking,
+                ..Default::default()
+            };
+            let vars = s.ingest_route_graph(&routes, &config).unwrap();
+            let sym = s.constraints().iter().filter(|c| c.name.starts_with("sym:")).count();
+            let sol = s.solve().unwrap();
+            let picked: Vec<_> = routes.iter().zip(&vars).filter(|(_, &v)| sol.is_selected(v)).map(|(r, _)| r.id.clone()).collect();
+            (sol.objective.unwrap(), sol.stats.nodes, sym, picked)
+        };
+
+        let (plain_obj, plain_nodes, plain_sym, _) = solve(false);
+        let (obj, nodes, sym, picked) = solve(true);
+        assert_eq!(plain_sym, 0);
+        // The eight links form one class; `b-gw` is alone in its own
+        assert_eq!(sym, 7);
+        assert_eq!(obj, plain_obj);
+        assert!(nodes < plain_nodes, "{} vs {} nodes", nodes, plain_nodes);
+        // The first members of the class are the ones admitted
+        assert_eq!(picked, vec!["link-0", "link-1", "link-2"]);
+
+        // A route that is someone's prerequisite is not interchangeable
+        routes[1].after = vec!["link-0".into()];
+        assert!(symmetry_classes(&routes).iter().all(|class| !class.contains(&0) && !class.contains(&1)));
+    }
+
+    #[test]
+    fn test_priority_breaks_ties() {
+        // `gw` takes one of two routes with the same RTT
+        let config = RouteGraphConfig { capacities: BTreeMap::from([("gw".into(), 1)]), ..Default::default() };
+        let pick = |priorities: [f64; 2]| {
+            let routes: Vec<_> = ["a", "b"]
+                .iter()
+                .zip(priorities)
+                .map(|(from, priority)| GraphRoute { priority, ..route(&format!("{}-gw", from), from, "gw", 1.0, &[]) })
+                .collect();
+            let mut s = Solver::new();
+            let vars = s.ingest_route_graph(&routes, &config).unwrap();
+            let sol = s.solve().unwrap();
+            assert!((sol.objective.unwrap() - (1.0 - 1000.0)).abs() < TIE_BREAK);
+            routes.iter().zip(&vars).filter(|(_, &v)| sol.is_selected(v)).map(|(r, _)| r.id.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(pick([0.0, 5.0]), vec!["b-gw"]);
+        assert_eq!(pick([5.0, 0.0]), vec!["a-gw"]);
+        assert_eq!(pick([1.0, 2.0]), vec!["b-gw"]);
+
+        // A cheaper route still wins over a higher-priority one
+        let routes = vec![
+            GraphRoute { priority: 100.0, ..route("a-gw", "a", "gw", 1.001, &[]) },
+            route("b-gw", "b", "gw", 1.0, &[]),
+        ];
+        let mut s = Solver::new();
+        let vars = s.ingest_route_graph(&routes, &config).unwrap();
+        assert!(s.solve().unwrap().is_selected(vars[1]));
+    }
+
+    #[test]
+    fn test_priority_breaks_ties_under_large_priority_sums() {
+        // A one-unit difference still decides when all priorities sum to ~1e5
+        let config = RouteGraphConfig { capacities: BTreeMap::from([("gw".into(), 1)]), ..Default::default() };
+        for (pa, pb, want) in [(1000.0, 1001.0, 1), (1001.0, 1000.0, 0)] {
+            let mut routes = vec![
+                GraphRoute { priority: pa, ..route("a-gw", "a", "gw", 1.0, &[]) },
+                GraphRoute { priority: pb, ..route("b-gw", "b", "gw", 1.0, &[]) },
+            ];
+            routes.extend((0..100).map(|i| GraphRoute { priority: 1000.0, ..route(&format!("n{}", i), &format!("n{}", i), "sink", 1.0, &[]) }));
+            let mut s = Solver::new();
+            let vars = s.ingest_route_graph(&routes, &config).unwrap();
+            let sol = s.solve().unwrap();
+            assert!(sol.is_selected(vars[want]), "priorities {} and {}", pa, pb);
+            assert!(!sol.is_selected(vars[1 - want]));
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! RTT Solver - integer linear models for route selection
+//!
+//! A small exact solver for the planner: bounded integer variables, linear
+//! constraints and a linear objective, solved by depth-first branch and
+//! bound. Models built by the planner are a few hundred binaries at most,
+//! which keeps exhaustive search with activity-bound pruning practical.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+mod diff;
+mod graph;
+mod ortools;
+
+pub use diff::{BoundChange, CoefChange, ConstraintChange, ModelDiff};
+pub use graph::{oversubscribed_endpoints, repair_route_graph, GraphObjective, GraphRoute, RouteGraphConfig, RouteRepair};
+
+const EPS: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Le,
+    Ge,
+    Eq,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sense {
+    Minimize,
+    Maximize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Variable {
+    pub name: String,
+    pub lb: i64,
+    pub ub: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Constraint {
+    pub name: String,
+    pub terms: Vec<(VarId, f64)>,
+    pub cmp: Cmp,
+    pub rhs: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Optimal,
+    /// A solution was found, but a callback stopped the search before it
+    /// was proven optimal.
+    Feasible,
+    /// A solution meeting `SolveOptions::objective_target` was found and
+    /// the search stopped there.
+    TargetReached,
+    /// The node or time budget in `SolveOptions` ran out before the search
+    /// finished; `objective` is the best solution found so far, if any.
+    BudgetExhausted,
+    Infeasible,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SolveOptions {
+    /// Stop at the first solution whose objective is at or better than
+    /// this: at most it when minimizing, at least it when maximizing.
+    pub objective_target: Option<f64>,
+    /// Give up after visiting this many search nodes.
+    pub max_nodes: Option<u64>,
+    /// Give up once the search has run this long.
+    pub time_limit: Option<Duration>,
+}
+
+/// A new best solution, as seen by a `solve_with` callback. Both values are
+/// in the model's own sense.
+#[derive(Clone, Copy, Debug)]
+pub struct Incumbent {
+    pub objective: f64,
+    /// Bound on the optimum from the variable domains alone: no solution
+    /// beats it, though it may not be attainable.
+    pub bound: f64,
+}
+
+/// What a `solve_with` callback wants next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SolveStats {
+    /// Search nodes visited, including pruned ones.
+    pub nodes: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub status: Status,
+    pub objective: Option<f64>,
+    pub stats: SolveStats,
+    values: Vec<i64>,
+}
+
+impl Solution {
+    pub fn value(&self, var: VarId) -> i64 {
+        self.values[var.0]
+    }
+
+    pub fn is_selected(&self, var: VarId) -> bool {
+        self.value(var) != 0
+    }
+}
+
+pub struct Solver {
+    vars: Vec<Variable>,
+    constraints: Vec<Constraint>,
+    objective: Vec<(VarId, f64)>,
+    sense: Sense,
+    fixed: BTreeMap<VarId, i64>,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self {
+            vars: Vec::new(),
+            constraints: Vec::new(),
+            objective: Vec::new(),
+            sense: Sense::Minimize,
+            fixed: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_var(&mut self, name: &str, lb: i64, ub: i64) -> VarId {
+        assert!(lb <= ub, "empty domain for {}", name);
+        self.vars.push(Variable { name: name.into(), lb, ub });
+        VarId(self.vars.len() - 1)
+    }
+
+    pub fn add_binary(&mut self, name: &str) -> VarId {
+        self.add_var(name, 0, 1)
+    }
+
+    pub fn add_constraint(&mut self, name: &str, terms: &[(VarId, f64)], cmp: Cmp, rhs: f64) {
+        self.constraints.push(Constraint {
+            name: name.into(),
+            terms: terms.to_vec(),
+            cmp,
+            rhs,
+        });
+    }
+
+    pub fn set_objective(&mut self, sense: Sense, terms: &[(VarId, f64)]) {
+        self.sense = sense;
+        self.objective = terms.to_vec();
+    }
+
+    pub fn variables(&self) -> &[Variable] {
+        &self.vars
+    }
+
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
+    }
+
+    /// Pin `var` to `value` for subsequent solves, until `unfix_variable`.
+    pub fn fix_variable(&mut self, var: VarId, value: i64) -> Result<()> {
+        let Some(v) = self.vars.get(var.0) else {
+            bail!("Cannot fix variable {}: the model has {} variable(s)", var.0, self.vars.len());
+        };
+        if value < v.lb || value > v.ub {
+            bail!("Cannot fix {} to {}: outside [{}, {}]", v.name, value, v.lb, v.ub);
+        }
+        self.fixed.insert(var, value);
+        Ok(())
+    }
+
+    pub fn unfix_variable(&mut self, var: VarId) {
+        self.fixed.remove(&var);
+    }
+
+    /// Convert an internal, always-minimized objective value to the model's sense.
+    fn external(&self, obj: f64) -> f64 {
+        match self.sense {
+            Sense::Minimize => obj,
+            Sense::Maximize => -obj,
+        }
+    }
+
+    fn domain(&self, var: usize) -> (i64, i64) {
+        match self.fixed.get(&VarId(var)) {
+            Some(&v) => (v, v),
+            None => (self.vars[var].lb, self.vars[var].ub),
+        }
+    }
+
+    pub fn solve(&self) -> Result<Solution> {
+        self.solve_with(|_| Control::Continue)
+    }
+
+    /// Solve, calling `on_incumbent` each time a better solution is found.
+    /// Returning `Control::Stop` ends the search with that solution and
+    /// `Status::Feasible`, e.g. once it is within a target gap.
+    pub fn solve_with(&self, on_incumbent: impl FnMut(&Incumbent) -> Control) -> Result<Solution> {
+        self.solve_with_options(&SolveOptions::default(), on_incumbent)
+    }
+
+    /// `solve_with` under `opts`. Reaching the objective target ends the
+    /// search with `Status::TargetReached`, and running out of nodes or
+    /// time with `Status::BudgetExhausted`.
+    pub fn solve_with_options(
+        &self,
+        opts: &SolveOptions,
+        mut on_incumbent: impl FnMut(&Incumbent) -> Control,
+    ) -> Result<Solution> {
+        if opts.objective_target.is_some_and(|t| !t.is_finite()) {
+            bail!("Objective target must be finite");
+        }
+        let mut search = Search::new(self, &mut on_incumbent);
+        // The search minimizes, so the target flips with the objective
+        search.target = opts.objective_target.map(|t| self.external(t));
+        search.max_nodes = opts.max_nodes;
+        search.deadline = opts.time_limit.map(|limit| Instant::now() + limit);
+        if !(0..self.constraints.len()).any(|ci| search.violated(ci)) {
+            search.run(0);
+        }
+
+        let stats = SolveStats { nodes: search.nodes };
+        let status = if search.target_reached {
+            Status::TargetReached
+        } else if search.exhausted {
+            Status::BudgetExhausted
+        } else if search.stopped {
+            Status::Feasible
+        } else {
+            Status::Optimal
+        };
+        Ok(match search.best {
+            Some((obj, values)) => Solution {
+                status,
+                objective: Some(self.external(obj)),
+                stats,
+                values,
+            },
+            None => Solution {
+                status: if search.exhausted { Status::BudgetExhausted } else { Status::Infeasible },
+                objective: None,
+                stats,
+                values: Vec::new(),
+            },
+        })
+    }
+}
+
+/// Branch-and-bound state. The objective is always minimized internally;
+/// maximization negates the coefficients.
+struct Search<'a> {
+    solver: &'a Solver,
+    on_incumbent: &'a mut dyn FnMut(&Incumbent) -> Control,
+    domains: Vec<(i64, i64)>,
+    obj: Vec<f64>,
+    /// Constraint terms grouped by variable: `(constraint index, coefficient)`.
+    occurs: Vec<Vec<(usize, f64)>>,
+    /// Lowest and highest constraint activity reachable from the current node.
+    act_min: Vec<f64>,
+    act_max: Vec<f64>,
+    obj_min: f64,
+    /// `obj_min` at the root, reported to callbacks as the bound.
+    root_bound: f64,
+    values: Vec<i64>,
+    best: Option<(f64, Vec<i64>)>,
+    nodes: u64,
+    stopped: bool,
+    /// Internal objective value that is good enough to stop at.
+    target: Option<f64>,
+    target_reached: bool,
+    max_nodes: Option<u64>,
+    deadline: Option<Instant>,
+    exhausted: bool,
+}
+
+fn term_range(coef: f64, lb: i64, ub: i64) -> (f64, f64) {
+    let (a, b) = (coef * lb as f64, coef * ub as f64);
+    (a.min(b), a.max(b))
+}
+
+impl<'a> Search<'a> {
+    fn new(solver: &'a Solver, on_incumbent: &'a mut dyn FnMut(&Incumbent) -> Control) -> Self {
+        let n = solver.vars.len();
+        let domains: Vec<_> = (0..n).map(|v| solver.domain(v)).collect();
+
+        let sign = match solver.sense {
+            Sense::Minimize => 1.0,
+            Sense::Maximize => -1.0,
+        };
+        let mut obj = vec![0.0; n];
+        for &(v, c) in &solver.objective {
+            obj[v.0] += sign * c;
+        }
+
+        let mut occurs = vec![Vec::new(); n];
+        let mut act_min = vec![0.0; solver.constraints.len()];
+        let mut act_max = vec![0.0; solver.constraints.len()];
+        for (ci, con) in solver.constraints.iter().enumerate() {
+            for &(v, c) in &con.terms {
+                occurs[v.0].push((ci, c));
+                let (lo, hi) = term_range(c, domains[v.0].0, domains[v.0].1);
+                act_min[ci] += lo;
+                act_max[ci] += hi;
+            }
+        }
+        let obj_min = (0..n).map(|v| term_range(obj[v], domains[v].0, domains[v].1).0).sum();
+
+        Self {
+            solver,
+            on_incumbent,
+            domains,
+            obj,
+            occurs,
+            act_min,
+            act_max,
+            obj_min,
+            root_bound: obj_min,
+            values: vec![0; n],
+            best: None,
+            nodes: 0,
+            stopped: false,
+            target: None,
+            target_reached: false,
+            max_nodes: None,
+            deadline: None,
+            exhausted: false,
+        }
+    }
+
+    fn violated(&self, ci: usize) -> bool {
+        let con = &self.solver.constraints[ci];
+        match con.cmp {
+            Cmp::Le => self.act_min[ci] > con.rhs + EPS,
+            Cmp::Ge => self.act_max[ci] < con.rhs - EPS,
+            Cmp::Eq => self.act_min[ci] > con.rhs + EPS || self.act_max[ci] < con.rhs - EPS,
+        }
+    }
+
+    /// Move `var` between its full domain and the single value `x`.
+    /// `dir` is 1.0 to assign and -1.0 to undo.
+    fn shift(&mut self, var: usize, x: i64, dir: f64) {
+        let (lb, ub) = self.domains[var];
+        for &(ci, c) in &self.occurs[var] {
+            let (lo, hi) = term_range(c, lb, ub);
+            self.act_min[ci] += dir * (c * x as f64 - lo);
+            self.act_max[ci] += dir * (c * x as f64 - hi);
+        }
+        let (lo, _) = term_range(self.obj[var], lb, ub);
+        self.obj_min += dir * (self.obj[var] * x as f64 - lo);
+    }
+
+    fn run(&mut self, depth: usize) {
+        if self.stopped {
+            return;
+        }
+        self.nodes += 1;
+        // The clock is read every 1024 nodes to keep it off the hot path
+        let late = self.nodes.is_multiple_of(1024) && self.deadline.is_some_and(|d| Instant::now() >= d);
+        if self.max_nodes.is_some_and(|max| self.nodes > max) || late {
+            self.exhausted = true;
+            self.stopped = true;
+            return;
+        }
+        if let Some((best, _)) = &self.best {
+            if self.obj_min >= best - EPS {
+                return;
+            }
+        }
+        if depth == self.values.len() {
+            self.best = Some((self.obj_min, self.values.clone()));
+            let incumbent = Incumbent {
+                objective: self.solver.external(self.obj_min),
+                bound: self.solver.external(self.root_bound),
+            };
+            self.stopped = (self.on_incumbent)(&incumbent) == Control::Stop;
+            if self.target.is_some_and(|t| self.obj_min <= t + EPS) {
+                self.target_reached = true;
+                self.stopped = true;
+            }
+            return;
+        }
+
+        // Try the objective-improving end of the domain first so good
+        // incumbents appear early and prune more of the tree.
+        let (lb, ub) = self.domains[depth];
+        let candidates: Vec<i64> = if self.obj[depth] < 0.0 {
+            (lb..=ub).rev().collect()
+        } else {
+            (lb..=ub).collect()
+        };
+
+        for x in candidates {
+            self.values[depth] = x;
+            self.shift(depth, x, 1.0);
+            let feasible = !self.occurs[depth].iter().any(|&(ci, _)| self.violated(ci));
+            if feasible {
+                self.run(depth + 1);
+            }
+            self.shift(depth, x, -1.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pick at most two of three routes, maximizing total weight.
+    fn knapsack() -> (Solver, [VarId; 3]) {
+        let mut s = Solver::new();
+        let a = s.add_binary("route_a");
+        let b = s.add_binary("route_b");
+        let c = s.add_binary("route_c");
+        s.add_constraint("capacity", &[(a, 1.0), (b, 1.0), (c, 1.0)], Cmp::Le, 2.0);
+        s.set_objective(Sense::Maximize, &[(a, 1.0), (b, 5.0), (c, 3.0)]);
+        (s, [a, b, c])
+    }
+
+    #[test]
+    fn test_solve_optimal() {
+        let (s, [a, b, c]) = knapsack();
+        let sol = s.solve().unwrap();
+        assert_eq!(sol.status, Status::Optimal);
+        assert_eq!(sol.objective, Some(8.0));
+        assert!(!sol.is_selected(a) && sol.is_selected(b) && sol.is_selected(c));
+    }
+
+    #[test]
+    fn test_fix_variable() {
+        let (mut s, [a, b, c]) = knapsack();
+        s.fix_variable(a, 1).unwrap();
+        let sol = s.solve().unwrap();
+        assert_eq!(sol.objective, Some(6.0));
+        assert!(sol.is_selected(a) && sol.is_selected(b) && !sol.is_selected(c));
+
+        s.fix_variable(b, 0).unwrap();
+        let sol = s.solve().unwrap();
+        assert!(sol.is_selected(a) && !sol.is_selected(b) && sol.is_selected(c));
+
+        s.unfix_variable(a);
+        s.unfix_variable(b);
+        assert_eq!(s.solve().unwrap().objective, Some(8.0));
+        assert!(s.fix_variable(c, 2).is_err());
+        // A VarId from another, larger model is rejected rather than panicking
+        assert!(s.fix_variable(VarId(3), 0).is_err());
+    }
+
+    #[test]
+    fn test_callback_stops_at_acceptable_incumbent() {
+        let (s, _) = knapsack();
+        let full = s.solve().unwrap();
+
+        // Accept anything within 30% of the bound
+        let mut seen = Vec::new();
+        let sol = s
+            .solve_with(|inc| {
+                seen.push(inc.objective);
+                assert_eq!(inc.bound, 9.0);
+                if inc.objective >= 0.7 * inc.bound {
+                    Control::Stop
+                } else {
+                    Control::Continue
+                }
+            })
+            .unwrap();
+        assert_eq!(sol.status, Status::Feasible);
+        assert_eq!(sol.objective, seen.last().copied());
+        assert!(sol.objective.unwrap() >= 6.3);
+        // Incumbents only improve, and only the last one was acceptable
+        assert!(seen.windows(2).all(|w| w[1] > w[0]));
+        assert!(seen[..seen.len() - 1].iter().all(|&o| o < 6.3));
+        assert!(sol.stats.nodes <= full.stats.nodes);
+    }
+
+    #[test]
+    fn test_objective_target_stops_early() {
+        let (s, _) = knapsack();
+        let full = s.solve().unwrap();
+
+        let mut seen = Vec::new();
+        let opts = SolveOptions { objective_target: Some(6.0), ..Default::default() };
+        let sol = s
+            .solve_with_options(&opts, |inc| {
+                seen.push(inc.objective);
+                Control::Continue
+            })
+            .unwrap();
+        assert_eq!(sol.status, Status::TargetReached);
+        // The first incumbent at or above the target ends the search
+        assert!(sol.objective.unwrap() >= 6.0);
+        assert_eq!(sol.objective, seen.last().copied());
+        assert!(seen[..seen.len() - 1].iter().all(|&o| o < 6.0));
+        assert!(sol.stats.nodes < full.stats.nodes);
+
+        // An unreachable target leaves the search to prove optimality
+        let opts = SolveOptions { objective_target: Some(100.0), ..Default::default() };
+        let sol = s.solve_with_options(&opts, |_| Control::Continue).unwrap();
+        assert_eq!((sol.status, sol.objective), (Status::Optimal, Some(8.0)));
+        let nan = SolveOptions { objective_target: Some(f64::NAN), ..Default::default() };
+        assert!(s.solve_with_options(&nan, |_| Control::Continue).is_err());
+    }
+
+    #[test]
+    fn test_node_budget() {
+        let (s, _) = knapsack();
+        let full = s.solve().unwrap();
+
+        // Too few nodes to reach a leaf: no solution, but not infeasible either
+        let opts = SolveOptions { max_nodes: Some(2), ..Default::default() };
+        let sol = s.solve_with_options(&opts, |_| Control::Continue).unwrap();
+        assert_eq!((sol.status, sol.objective), (Status::BudgetExhausted, None));
+        assert_eq!(sol.stats.nodes, 3);
+
+        // Enough for an incumbent, not for the proof
+        let opts = SolveOptions { max_nodes: Some(full.stats.nodes - 1), ..Default::default() };
+        let sol = s.solve_with_options(&opts, |_| Control::Continue).unwrap();
+        assert_eq!(sol.status, Status::BudgetExhausted);
+        assert!(sol.objective.is_some());
+
+        let opts = SolveOptions { max_nodes: Some(full.stats.nodes), time_limit: Some(Duration::from_secs(60)), ..Default::default() };
+        assert_eq!(s.solve_with_options(&opts, |_| Control::Continue).unwrap().status, Status::Optimal);
+    }
+
+    #[test]
+    fn test_infeasible() {
+        let (mut s, [a, b, c]) = knapsack();
+        s.add_constraint("need_all", &[(a, 1.0), (b, 1.0), (c, 1.0)], Cmp::Ge, 3.0);
+        let sol = s.solve().unwrap();
+        assert_eq!(sol.status, Status::Infeasible);
+        assert_eq!(sol.objective, None);
+    }
+}
@@ -0,0 +1,160 @@
+//! Export to OR-Tools
+//!
+//! `Solver::to_ortools_proto` renders the model as the JSON form of OR-Tools'
+//! `MPModelProto`, which `google.protobuf.json_format.Parse` accepts and
+//! `pywraplp.Solver.LoadModelFromProto` solves with any MIP backend:
+//!
+//! ```python
+//! proto = json_format.Parse(exported, linear_solver_pb2.MPModelProto())
+//! solver = pywraplp.Solver.CreateSolver("CBC")
+//! solver.LoadModelFromProto(proto)
+//! ```
+//!
+//! Variables keep their order, so `var_index` values are `VarId`s. Fixed
+//! variables are exported with equal bounds.
+
+use crate::{Cmp, Sense, Solver};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Proto3 JSON spells infinite doubles as strings.
+fn bound(value: f64) -> Value {
+    if value == f64::INFINITY {
+        json!("Infinity")
+    } else if value == f64::NEG_INFINITY {
+        json!("-Infinity")
+    } else {
+        json!(value)
+    }
+}
+
+impl Solver {
+    pub fn to_ortools_proto(&self) -> Value {
+        let mut objective: BTreeMap<usize, f64> = BTreeMap::new();
+        for &(var, coef) in &self.objective {
+            *objective.entry(var.0).or_default() += coef;
+        }
+
+        let variables: Vec<Value> = self
+            .vars
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let (lb, ub) = match self.fixed.get(&crate::VarId(i)) {
+                    Some(&value) => (value, value),
+                    None => (v.lb, v.ub),
+                };
+                json!({
+                    "name": v.name,
+                    "lower_bound": lb as f64,
+                    "upper_bound": ub as f64,
+                    "objective_coefficient": objective.get(&i).copied().unwrap_or(0.0),
+                    "is_integer": true,
+                })
+            })
+            .collect();
+
+        let constraints: Vec<Value> = self
+            .constraints
+            .iter()
+            .map(|c| {
+                let (lb, ub) = match c.cmp {
+                    Cmp::Le => (f64::NEG_INFINITY, c.rhs),
+                    Cmp::Ge => (c.rhs, f64::INFINITY),
+                    Cmp::Eq => (c.rhs, c.rhs),
+                };
+                json!({
+                    "name": c.name,
+                    "var_index": c.terms.iter().map(|(v, _)| v.0).collect::<Vec<_>>(),
+                    "coefficient": c.terms.iter().map(|(_, coef)| coef).collect::<Vec<_>>(),
+                    "lower_bound": bound(lb),
+                    "upper_bound": bound(ub),
+                })
+            })
+            .collect();
+
+        json!({
+            "name": "rtt",
+            "maximize": self.sense == Sense::Maximize,
+            "variable": variables,
+            "constraint": constraints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GraphRoute, RouteGraphConfig, Solver};
+    use std::collections::BTreeMap;
+
+    fn model() -> Solver {
+        let routes = [("a-gw", "a", 1.0), ("b-gw", "b", 2.0), ("c-gw", "c", 0.5)].map(|(id, from, rtt)| GraphRoute {
+            id: id.into(),
+            from: from.into(),
+            to: "gw".into(),
+            rtt,
+            ..Default::default()
+        });
+        let mut s = Solver::new();
+        let config = RouteGraphConfig {
+            capacities: BTreeMap::from([("gw".into(), 2)]),
+            ..Default::default()
+        };
+        s.ingest_route_graph(&routes, &config).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_export_counts_and_bounds() {
+        let s = model();
+        let proto: serde_json::Value = serde_json::from_str(&s.to_ortools_proto().to_string()).unwrap();
+        assert_eq!(proto["variable"].as_array().unwrap().len(), s.variables().len());
+        assert_eq!(proto["constraint"].as_array().unwrap().len(), s.constraints().len());
+        assert_eq!(proto["maximize"], false);
+
+        let cap = &proto["constraint"][0];
+        assert_eq!(cap["name"], "cap:gw");
+        assert_eq!(cap["var_index"], serde_json::json!([0, 1, 2]));
+        assert_eq!(cap["lower_bound"], "-Infinity");
+        assert_eq!(cap["upper_bound"], 2.0);
+        assert_eq!(proto["variable"][1]["objective_coefficient"], 2.0 - 1000.0);
+    }
+
+    /// Solves the export with OR-Tools (needs `python3 -m pip install ortools`)
+    /// and compares objectives. Skipped when python3 cannot import ortools.
+    #[cfg(feature = "ortools")]
+    #[test]
+    fn test_ortools_solves_to_same_objective() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        const SCRIPT: &str = "import sys\n\
+from google.protobuf import json_format\n\
+from ortools.linear_solver import linear_solver_pb2, pywraplp\n\
+proto = json_format.Parse(sys.stdin.read(), linear_solver_pb2.MPModelProto())\n\
+solver = pywraplp.Solver.CreateSolver('CBC')\n\
+solver.LoadModelFromProto(proto)\n\
+assert solver.Solve() == pywraplp.Solver.OPTIMAL\n\
+print(solver.Objective().Value())\n";
+
+        let probe = Command::new("python3").args(["-c", "import ortools"]).stderr(Stdio::null()).status();
+        if !probe.is_ok_and(|status| status.success()) {
+            eprintln!("skipping test_ortools_solves_to_same_objective: python3 cannot import ortools");
+            return;
+        }
+
+        let s = model();
+        let mut child = Command::new("python3")
+            .args(["-c", SCRIPT])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("python3 not found");
+        child.stdin.take().unwrap().write_all(s.to_ortools_proto().to_string().as_bytes()).unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success());
+        let theirs: f64 = String::from_utf8(out.stdout).unwrap().trim().parse().unwrap();
+        let ours = s.solve().unwrap().objective.unwrap();
+        assert!((theirs - ours).abs() < 1e-6, "OR-Tools {} vs {}", theirs, ours);
+    }
+}